@@ -0,0 +1,54 @@
+// Encodes linear BT.709 scene colors for the swapchain, see `OutputParameters`. HDR outputs map 1.0 to
+// `paper_white_nits` and clamp to `max_nits`, SDR outputs are tonemapped.
+
+#define OUTPUT_ENCODING_SRGB 0u
+#define OUTPUT_ENCODING_SRGB_UNORM 1u
+#define OUTPUT_ENCODING_EXTENDED_SRGB_LINEAR 2u
+#define OUTPUT_ENCODING_HDR10 3u
+
+// Narkowicz ACES filmic fit.
+vec3 tonemap_aces(vec3 color) {
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+vec3 bt709_to_bt2020(vec3 color) {
+    const mat3 conversion = mat3(
+        0.6274, 0.0691, 0.0164,
+        0.3293, 0.9195, 0.0880,
+        0.0433, 0.0114, 0.8956
+    );
+    return conversion * color;
+}
+
+// SMPTE ST 2084 inverse EOTF, `nits` up to 10000.
+vec3 pq_encode(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+vec3 encode_output(vec3 color, uint encoding, float paper_white_nits, float max_nits) {
+    color = max(color, vec3(0.0));
+    switch (encoding) {
+        case OUTPUT_ENCODING_SRGB:
+            return tonemap_aces(color);
+        case OUTPUT_ENCODING_SRGB_UNORM:
+            return linear_to_srgb(tonemap_aces(color));
+        case OUTPUT_ENCODING_EXTENDED_SRGB_LINEAR:
+            return min(color * paper_white_nits, vec3(max_nits)) / 80.0;
+        case OUTPUT_ENCODING_HDR10:
+            return pq_encode(min(bt709_to_bt2020(color) * paper_white_nits, vec3(max_nits)));
+        default:
+            return color;
+    }
+}
@@ -0,0 +1,20 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "../output_encoding.glsl"
+
+layout(location = 0) in vec3 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 transform;
+    uint encoding;
+    float paperWhiteNits;
+    float maxNits;
+} pushConstants;
+
+void main() {
+    vec3 color = encode_output(fragColor, pushConstants.encoding, pushConstants.paperWhiteNits, pushConstants.maxNits);
+    outColor = vec4(color, 1.0);
+}
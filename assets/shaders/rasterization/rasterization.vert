@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 transform;
+} pushConstants;
+
+layout(location = 0) out vec3 fragColor;
+
+void main() {
+    gl_Position = pushConstants.transform * vec4(inPosition, 1.0);
+    fragColor = inColor;
+}
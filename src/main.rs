@@ -1,17 +1,55 @@
 use std::ffi::CString;
-use std::rc::Rc;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use ash::Entry;
-use winit::event::{Event, WindowEvent};
+use ash::vk;
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
-use crate::vulkan::{Context, Device, Instance, PhysicalDevice, Surface};
-use crate::vulkan::debug::ValidationInfo;
-use crate::vulkan::swapchain::Swapchain;
+use vision::vulkan::{Buffer, Context, DeviceRequirements, Framebuffer, PendingScreenshot, RenderPass, ShaderModule, VisionResult};
+use vision::vulkan::debug::ValidationInfo;
+use vision::vulkan::pipeline::rasterization::GraphicsPipeline;
+use vision::vulkan::swapchain::{FrameManager, OutputParameters, PresentMode, PresentTarget, Swapchain, SwapchainConfig,
+                                SwapchainStatus};
 
-mod vulkan;
+const FRAMES_IN_FLIGHT: usize = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Vertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+unsafe impl bytemuck::Zeroable for Vertex {}
+
+unsafe impl bytemuck::Pod for Vertex {}
+
+const TRIANGLE: [Vertex; 3] = [
+    Vertex { position: [0.0, -0.5, 0.0], color: [1.0, 0.0, 0.0] },
+    Vertex { position: [0.5, 0.5, 0.0], color: [0.0, 1.0, 0.0] },
+    Vertex { position: [-0.5, 0.5, 0.0], color: [0.0, 0.0, 1.0] },
+];
+
+const IDENTITY: [f32; 16] = [
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+];
+
+/// Shared by both stages, the fragment shader encodes its output for the swapchain.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    transform: [f32; 16],
+    output: OutputParameters,
+}
+
+unsafe impl bytemuck::Zeroable for PushConstants {}
+
+unsafe impl bytemuck::Pod for PushConstants {}
 
 fn main() {
     println!("Hello, world!");
@@ -32,26 +70,321 @@ fn main() {
         required_validation_layers: vec![CString::new("VK_LAYER_KHRONOS_validation").unwrap()],
     };
 
-    let required_extensions = vec![ash::extensions::khr::Swapchain::name().as_ptr()];
+    let requirements = DeviceRequirements::new()
+        .require_extension(ash::extensions::khr::Swapchain::name())
+        .prefer_extension(vk::ExtHdrMetadataFn::name());
+
+    let context = Arc::new(Context::new(&window, validation_info, requirements, None)
+        .expect("Failed to create context"));
 
-    let context = Rc::new(Context::new(&window, validation_info, required_extensions));
+    for candidate in context.device_candidates().expect("Failed to list devices") {
+        println!("{}", candidate);
+    }
+    println!("Using {}", context.device().physical_device().name());
 
 
     // Vulkan impl
 
+    let window_size = window.inner_size();
+    let mut swapchain = Swapchain::new(Arc::clone(&context),
+                                       [window_size.width, window_size.height],
+                                       SwapchainConfig {
+                                           image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                                               | vk::ImageUsageFlags::TRANSFER_SRC,
+                                           ..SwapchainConfig::default()
+                                       })
+        .expect("Failed to create swapchain");
+    let depth_format = context.depth_format(false).expect("Failed to find depth format");
+    let msaa_samples = context.msaa_samples(vk::SampleCountFlags::TYPE_4);
+    let mut render_pass = RenderPass::create(Arc::clone(&context),
+                                             *swapchain.extent(),
+                                             swapchain.format().format,
+                                             depth_format,
+                                             msaa_samples,
+                                             vk::ImageLayout::PRESENT_SRC_KHR)
+        .expect("Failed to create render pass");
+    let mut framebuffers = render_pass.create_framebuffers(swapchain.image_views())
+        .expect("Failed to create framebuffers");
+    let mut frame_manager = FrameManager::new(Arc::clone(&context), FRAMES_IN_FLIGHT)
+        .expect("Failed to create frame manager");
+    let mut triangle = Triangle::new(&context, &render_pass).expect("Failed to create triangle");
 
 
     // Winit Loop
 
+    let mut needs_recreation = false;
+    let mut screenshot_requested = false;
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
+        *control_flow = ControlFlow::Poll;
 
         match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 window_id,
             } if window_id == window.id() => *control_flow = ControlFlow::Exit,
+            Event::WindowEvent {
+                event: WindowEvent::Resized(_),
+                window_id,
+            } if window_id == window.id() => needs_recreation = true,
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::V),
+                        ..
+                    },
+                    ..
+                },
+                window_id,
+            } if window_id == window.id() => {
+                let mut config = swapchain.config().clone();
+                config.present_mode = match config.present_mode {
+                    PresentMode::Vsync => PresentMode::VsyncRelaxed,
+                    PresentMode::VsyncRelaxed => PresentMode::Mailbox,
+                    PresentMode::Mailbox => PresentMode::Immediate,
+                    PresentMode::Immediate => PresentMode::Vsync,
+                };
+                println!("Switching to {:?}", config.present_mode);
+                swapchain.set_config(config);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::H),
+                        ..
+                    },
+                    ..
+                },
+                window_id,
+            } if window_id == window.id() => {
+                let config = match swapchain.config().hdr_metadata {
+                    Some(_) => SwapchainConfig::default(),
+                    None => SwapchainConfig::hdr(),
+                };
+                swapchain.set_config(SwapchainConfig {
+                    present_mode: swapchain.config().present_mode,
+                    image_usage: swapchain.config().image_usage,
+                    ..config
+                });
+            }
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::P),
+                        ..
+                    },
+                    ..
+                },
+                window_id,
+            } if window_id == window.id() => screenshot_requested = true,
+            Event::MainEventsCleared => window.request_redraw(),
+            Event::RedrawRequested(window_id) if window_id == window.id() => {
+                let window_size = window.inner_size();
+                if needs_recreation {
+                    let dimensions = [window_size.width, window_size.height];
+                    match recreate_swapchain(&mut render_pass,
+                                             &mut swapchain,
+                                             &mut framebuffers,
+                                             &frame_manager,
+                                             dimensions) {
+                        Ok(true) => {
+                            // The render pass is recreated when the swapchain format changed.
+                            triangle.recreate_pipeline(&context, &render_pass)
+                                .expect("Failed to recreate pipeline");
+                            needs_recreation = false;
+                            println!("Output encoding {:?}", swapchain.output_encoding());
+                        }
+                        Ok(false) => (),
+                        Err(error) => panic!("Failed to recreate swapchain: {}", error),
+                    }
+                }
+                // Nothing is drawn while the window is minimized.
+                if needs_recreation || window_size.width == 0 || window_size.height == 0 {
+                    return;
+                }
+
+                let capture = std::mem::take(&mut screenshot_requested);
+                match draw_frame(&context,
+                                 &render_pass,
+                                 &framebuffers,
+                                 &swapchain,
+                                 &mut frame_manager,
+                                 &triangle,
+                                 capture) {
+                    Ok((status, screenshot)) => {
+                        if status != SwapchainStatus::Optimal {
+                            needs_recreation = true;
+                        }
+                        if let Some(screenshot) = screenshot {
+                            match save_screenshot(&frame_manager, screenshot) {
+                                Ok(path) => println!("Saved screenshot to {}", path),
+                                Err(error) => println!("Failed to save screenshot: {}", error),
+                            }
+                        }
+                    }
+                    Err(error) => panic!("Failed to draw frame: {}", error),
+                }
+            }
+            Event::LoopDestroyed => frame_manager.wait_idle().expect("Failed to wait for frames"),
             _ => (),
         }
     });
-}
\ No newline at end of file
+}
+
+/// Returns `false` while the window is minimized.
+fn recreate_swapchain(render_pass: &mut RenderPass,
+                      swapchain: &mut Swapchain,
+                      framebuffers: &mut Vec<Framebuffer>,
+                      frame_manager: &FrameManager,
+                      dimensions: [u32; 2]) -> VisionResult<bool> {
+    if !swapchain.recreate(dimensions)? {
+        return Ok(false);
+    }
+
+    // The attachments and framebuffers are shared by all frames and are replaced in place.
+    frame_manager.wait_idle()?;
+    render_pass.set_format(swapchain.format().format)?;
+    render_pass.resize(*swapchain.extent())?;
+    *framebuffers = render_pass.create_framebuffers(swapchain.image_views())?;
+    Ok(true)
+}
+
+/// Waits for the frame the screenshot was recorded in and saves it next to the working directory.
+fn save_screenshot(frame_manager: &FrameManager, screenshot: PendingScreenshot) -> VisionResult<String> {
+    frame_manager.wait_idle()?;
+    let screenshot = screenshot.finish()?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default();
+    let extension = if screenshot.is_hdr() { "exr" } else { "png" };
+    let path = format!("screenshot-{}.{}", timestamp, extension);
+    screenshot.save(&path)?;
+    Ok(path)
+}
+
+fn draw_frame(context: &Arc<Context>,
+              render_pass: &RenderPass,
+              framebuffers: &[Framebuffer],
+              swapchain: &Swapchain,
+              frame_manager: &mut FrameManager,
+              triangle: &Triangle,
+              capture_screenshot: bool) -> VisionResult<(SwapchainStatus, Option<PendingScreenshot>)> {
+    let frame = match frame_manager.begin_frame(swapchain)? {
+        Some(frame) => frame,
+        None => return Ok((SwapchainStatus::OutOfDate, None)),
+    };
+
+    let clear_values = [
+        vk::ClearValue { color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } },
+        vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+    ];
+    let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+        .render_pass(render_pass.vk_render_pass())
+        .framebuffer(framebuffers[frame.image_index() as usize].vk_framebuffer())
+        .render_area(vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent: *swapchain.extent() })
+        .clear_values(&clear_values);
+
+    unsafe {
+        let device = context.device().vk_device();
+        device.cmd_begin_render_pass(frame.command_buffer(), &render_pass_begin_info, vk::SubpassContents::INLINE);
+    }
+    triangle.cmd_draw(context, frame.command_buffer(), swapchain);
+    unsafe {
+        context.device().vk_device().cmd_end_render_pass(frame.command_buffer());
+    }
+
+    let screenshot = if capture_screenshot {
+        context.cmd_memory_barrier(frame.command_buffer(),
+                                   vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                                   vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                                   vk::PipelineStageFlags::TRANSFER,
+                                   vk::AccessFlags::TRANSFER_READ);
+        let image = &swapchain.images()[frame.image_index() as usize];
+        let screenshot = PendingScreenshot::cmd_capture(context,
+                                                        frame.command_buffer(),
+                                                        image,
+                                                        swapchain.present_layout())?;
+        Some(screenshot.decode_output(Some(swapchain.output_parameters())))
+    } else {
+        None
+    };
+
+    let status = frame_manager.end_frame(swapchain, frame)?;
+    Ok((status, screenshot))
+}
+
+struct Triangle {
+    vertex_shader: ShaderModule,
+    fragment_shader: ShaderModule,
+    pipeline: GraphicsPipeline,
+    vertices: Buffer<Vertex>,
+}
+
+impl Triangle {
+    fn new(context: &Arc<Context>, render_pass: &RenderPass) -> VisionResult<Self> {
+        let vertex_shader = ShaderModule::from_bytes(
+            Arc::clone(context), include_bytes!("../assets/shaders/rasterization/rasterization.vert.spv"))?;
+        let fragment_shader = ShaderModule::from_bytes(
+            Arc::clone(context), include_bytes!("../assets/shaders/rasterization/rasterization.frag.spv"))?;
+        let pipeline = create_pipeline(context, render_pass, &vertex_shader, &fragment_shader)?;
+        let vertices = Buffer::vertex(Arc::clone(context), &TRIANGLE)?;
+
+        Ok(Self {
+            vertex_shader,
+            fragment_shader,
+            pipeline,
+            vertices,
+        })
+    }
+
+    fn recreate_pipeline(&mut self, context: &Arc<Context>, render_pass: &RenderPass) -> VisionResult<()> {
+        self.pipeline = create_pipeline(context, render_pass, &self.vertex_shader, &self.fragment_shader)?;
+        Ok(())
+    }
+
+    fn cmd_draw(&self, context: &Context, command_buffer: vk::CommandBuffer, swapchain: &Swapchain) {
+        let extent = *swapchain.extent();
+        let push_constants = PushConstants {
+            transform: IDENTITY,
+            output: swapchain.output_parameters(),
+        };
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent };
+
+        self.pipeline.cmd_bind(command_buffer);
+        self.pipeline.layout().cmd_push_constants(command_buffer,
+                                                  vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                                                  0,
+                                                  bytemuck::bytes_of(&push_constants));
+        unsafe {
+            let device = context.device().vk_device();
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertices.vk_buffer()], &[0]);
+            device.cmd_draw(command_buffer, TRIANGLE.len() as u32, 1, 0, 0);
+        }
+    }
+}
+
+fn create_pipeline(context: &Arc<Context>,
+                   render_pass: &RenderPass,
+                   vertex_shader: &ShaderModule,
+                   fragment_shader: &ShaderModule) -> VisionResult<GraphicsPipeline> {
+    GraphicsPipeline::builder()
+        .vertex_shader(vertex_shader)
+        .fragment_shader(fragment_shader)
+        .vertex_binding(0, std::mem::size_of::<Vertex>() as u32, vk::VertexInputRate::VERTEX)
+        .vertex_attribute(0, 0, vk::Format::R32G32B32_SFLOAT, 0)
+        .vertex_attribute(1, 0, vk::Format::R32G32B32_SFLOAT, 12)
+        .push_constant_range(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                             0,
+                             std::mem::size_of::<PushConstants>() as u32)
+        .build(Arc::clone(context), render_pass, 0)
+}
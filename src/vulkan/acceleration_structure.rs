@@ -0,0 +1,544 @@
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::slice;
+use std::sync::Arc;
+
+use ash::extensions::khr;
+use ash::vk;
+use bytemuck::Pod;
+
+use crate::vulkan::{Buffer, Context, IndexType, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::util::align_up;
+
+/// Triangle list of a bottom level acceleration structure. The buffers need `SHADER_DEVICE_ADDRESS` and
+/// `ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR` usage, e.g. buffers created with `Buffer::geometry`.
+#[derive(Clone, Copy)]
+pub struct TriangleGeometry<'a> {
+    vertex_address: vk::DeviceAddress,
+    vertex_format: vk::Format,
+    vertex_stride: vk::DeviceSize,
+    vertex_count: u32,
+    index_address: vk::DeviceAddress,
+    index_type: vk::IndexType,
+    primitive_count: u32,
+    flags: vk::GeometryFlagsKHR,
+    _buffers: PhantomData<&'a ()>,
+}
+
+impl<'a> TriangleGeometry<'a> {
+    /// Non indexed triangles, each vertex `V` starts with a position of `vertex_format`. Fails if the number of
+    /// vertices is not a multiple of 3.
+    pub fn new<V: Pod>(vertices: &'a Buffer<V>, vertex_format: vk::Format) -> VisionResult<Self> {
+        check_triangle_list(vertices.len(), "vertices")?;
+        Ok(Self::from_vertices(vertices, vertex_format))
+    }
+
+    /// Fails if the number of indices is not a multiple of 3.
+    pub fn indexed<V: Pod, I: IndexType>(vertices: &'a Buffer<V>,
+                                         vertex_format: vk::Format,
+                                         indices: &'a Buffer<I>) -> VisionResult<Self> {
+        check_triangle_list(indices.len(), "indices")?;
+        Ok(Self {
+            index_address: indices.device_address(),
+            index_type: I::INDEX_TYPE,
+            primitive_count: indices.len() as u32 / 3,
+            ..Self::from_vertices(vertices, vertex_format)
+        })
+    }
+
+    fn from_vertices<V: Pod>(vertices: &'a Buffer<V>, vertex_format: vk::Format) -> Self {
+        Self {
+            vertex_address: vertices.device_address(),
+            vertex_format,
+            vertex_stride: size_of::<V>() as vk::DeviceSize,
+            vertex_count: vertices.len() as u32,
+            index_address: 0,
+            index_type: vk::IndexType::NONE_KHR,
+            primitive_count: vertices.len() as u32 / 3,
+            flags: vk::GeometryFlagsKHR::OPAQUE,
+            _buffers: PhantomData,
+        }
+    }
+
+    /// Byte offset of the position inside a vertex, 0 by default.
+    pub fn position_offset(mut self, offset: vk::DeviceSize) -> Self {
+        self.vertex_address += offset;
+        self
+    }
+
+    /// `OPAQUE` by default, which skips any hit shaders.
+    pub fn flags(mut self, flags: vk::GeometryFlagsKHR) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    fn vk_geometry(&self) -> vk::AccelerationStructureGeometryKHR {
+        let triangles = vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+            .vertex_format(self.vertex_format)
+            .vertex_data(vk::DeviceOrHostAddressConstKHR { device_address: self.vertex_address })
+            .vertex_stride(self.vertex_stride)
+            .max_vertex(self.vertex_count.saturating_sub(1))
+            .index_type(self.index_type)
+            .index_data(vk::DeviceOrHostAddressConstKHR { device_address: self.index_address });
+        vk::AccelerationStructureGeometryKHR::builder()
+            .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR { triangles: triangles.build() })
+            .flags(self.flags)
+            .build()
+    }
+
+    fn layout(&self) -> GeometryLayout {
+        GeometryLayout {
+            geometry_type: vk::GeometryTypeKHR::TRIANGLES,
+            vertex_format: self.vertex_format,
+            vertex_count: self.vertex_count,
+            index_type: self.index_type,
+            flags: self.flags,
+            primitive_count: self.primitive_count,
+        }
+    }
+}
+
+fn check_triangle_list(len: usize, elements: &str) -> VisionResult<()> {
+    match len % 3 {
+        0 => Ok(()),
+        _ => Err(VisionError::InvalidAccelerationStructure(format!(
+            "{} {} do not form a triangle list, the count has to be a multiple of 3", len, elements))),
+    }
+}
+
+/// Properties of a geometry that an update has to keep, the instances of a top level structure are one geometry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct GeometryLayout {
+    geometry_type: vk::GeometryTypeKHR,
+    vertex_format: vk::Format,
+    vertex_count: u32,
+    index_type: vk::IndexType,
+    flags: vk::GeometryFlagsKHR,
+    primitive_count: u32,
+}
+
+impl GeometryLayout {
+    fn instances(count: usize) -> Self {
+        Self {
+            geometry_type: vk::GeometryTypeKHR::INSTANCES,
+            vertex_format: vk::Format::UNDEFINED,
+            vertex_count: 0,
+            index_type: vk::IndexType::NONE_KHR,
+            flags: vk::GeometryFlagsKHR::empty(),
+            primitive_count: count as u32,
+        }
+    }
+}
+
+/// Placement of a bottom level structure in a top level acceleration structure.
+#[derive(Clone, Copy)]
+pub struct AccelerationStructureInstance<'a> {
+    blas: &'a AccelerationStructure,
+    transform: [[f32; 4]; 3],
+    custom_index: u32,
+    mask: u8,
+    hit_group_offset: u32,
+    flags: vk::GeometryInstanceFlagsKHR,
+}
+
+impl<'a> AccelerationStructureInstance<'a> {
+    /// Untransformed instance visible to all rays.
+    pub fn new(blas: &'a AccelerationStructure) -> Self {
+        Self {
+            blas,
+            transform: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+            ],
+            custom_index: 0,
+            mask: 0xff,
+            hit_group_offset: 0,
+            flags: vk::GeometryInstanceFlagsKHR::empty(),
+        }
+    }
+
+    /// Rows of the object to world matrix, the last row is implicitly `[0, 0, 0, 1]`.
+    pub fn transform(mut self, transform: [[f32; 4]; 3]) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// `gl_InstanceCustomIndexEXT` in shaders, only the lower 24 bits are used.
+    pub fn custom_index(mut self, custom_index: u32) -> Self {
+        self.custom_index = custom_index;
+        self
+    }
+
+    /// Rays only hit the instance if their cull mask shares a bit with `mask`.
+    pub fn mask(mut self, mask: u8) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    /// Offset of the instance's hit groups in the shader binding table, only the lower 24 bits are used.
+    pub fn hit_group_offset(mut self, offset: u32) -> Self {
+        self.hit_group_offset = offset;
+        self
+    }
+
+    pub fn flags(mut self, flags: vk::GeometryInstanceFlagsKHR) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    fn vk_instance(&self) -> vk::AccelerationStructureInstanceKHR {
+        let mut matrix = [0.0; 12];
+        for (row, values) in self.transform.iter().enumerate() {
+            matrix[row * 4..row * 4 + 4].copy_from_slice(values);
+        }
+        vk::AccelerationStructureInstanceKHR {
+            transform: vk::TransformMatrixKHR { matrix },
+            instance_custom_index_and_mask: vk::Packed24_8::new(self.custom_index, self.mask),
+            instance_shader_binding_table_record_offset_and_flags:
+                vk::Packed24_8::new(self.hit_group_offset, self.flags.as_raw() as u8),
+            acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+                device_handle: self.blas.device_address(),
+            },
+        }
+    }
+}
+
+/// Bottom or top level acceleration structure in its own device local buffer. Builds, updates and compaction wait
+/// for completion and are followed by a barrier, so the structure can be used by later builds and ray tracing
+/// shaders. The device needs the `acceleration_structure` and `vulkan_12.buffer_device_address` features.
+pub struct AccelerationStructure {
+    context: Arc<Context>,
+    acceleration_structure: vk::AccelerationStructureKHR,
+    buffer: Buffer,
+    ty: vk::AccelerationStructureTypeKHR,
+    flags: vk::BuildAccelerationStructureFlagsKHR,
+    device_address: vk::DeviceAddress,
+    // Layout of each geometry, updates must not change them.
+    geometries: Vec<GeometryLayout>,
+    update_scratch_size: vk::DeviceSize,
+}
+
+impl AccelerationStructure {
+    /// Builds a bottom level structure from `geometries`, which may be updated with `refit` if `flags` contain
+    /// `ALLOW_UPDATE` and compacted with `compact` if they contain `ALLOW_COMPACTION`.
+    pub fn build_bottom_level(context: Arc<Context>,
+                              geometries: &[TriangleGeometry],
+                              flags: vk::BuildAccelerationStructureFlagsKHR) -> VisionResult<Self> {
+        let vk_geometries = geometries.iter().map(TriangleGeometry::vk_geometry).collect::<Vec<_>>();
+        let layouts = geometries.iter().map(TriangleGeometry::layout).collect::<Vec<_>>();
+        Self::build(context, vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL, flags, &vk_geometries, layouts)
+    }
+
+    /// Builds a top level structure of at least one instance, which may be moved with `update_instances` if `flags`
+    /// contain `ALLOW_UPDATE`. Bottom level structures have to outlive it and must not be rebuilt while it is in use.
+    pub fn build_top_level(context: Arc<Context>,
+                           instances: &[AccelerationStructureInstance],
+                           flags: vk::BuildAccelerationStructureFlagsKHR) -> VisionResult<Self> {
+        let instance_buffer = Self::instance_buffer(&context, instances)?;
+        let geometries = [Self::instances_geometry(&instance_buffer)];
+        let layouts = vec![GeometryLayout::instances(instances.len())];
+        Self::build(context, vk::AccelerationStructureTypeKHR::TOP_LEVEL, flags, &geometries, layouts)
+    }
+
+    /// Updates a bottom level structure to moved vertices of the same triangles. Faster than a rebuild, but the
+    /// structure degrades if the geometry changes a lot. The geometries have to keep their vertex format and count,
+    /// index type, flags and primitive count.
+    pub fn refit(&mut self, geometries: &[TriangleGeometry]) -> VisionResult<()> {
+        if self.ty != vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL {
+            return Err(VisionError::InvalidAccelerationStructure("only bottom level structures can be refit".into()));
+        }
+        let layouts = geometries.iter().map(TriangleGeometry::layout).collect::<Vec<_>>();
+        let vk_geometries = geometries.iter().map(TriangleGeometry::vk_geometry).collect::<Vec<_>>();
+        self.update(&vk_geometries, &layouts)
+    }
+
+    /// Updates the transforms, masks and other properties of the instances of a top level structure, e.g. for
+    /// animations. The number of instances has to stay the same.
+    pub fn update_instances(&mut self, instances: &[AccelerationStructureInstance]) -> VisionResult<()> {
+        if self.ty != vk::AccelerationStructureTypeKHR::TOP_LEVEL {
+            return Err(VisionError::InvalidAccelerationStructure("only top level structures have instances".into()));
+        }
+        let instance_buffer = Self::instance_buffer(&self.context, instances)?;
+        let geometries = [Self::instances_geometry(&instance_buffer)];
+        self.update(&geometries, &[GeometryLayout::instances(instances.len())])
+    }
+
+    /// Copies the structure into a new one of its compacted size, it has to be built with `ALLOW_COMPACTION`. The
+    /// copy keeps the flags, so it can be updated if this structure can.
+    pub fn compact(&self) -> VisionResult<Self> {
+        if !self.flags.contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION) {
+            let message = "structure was not built with ALLOW_COMPACTION";
+            return Err(VisionError::InvalidAccelerationStructure(message.into()));
+        }
+        let loader = Self::loader(&self.context)?;
+        let device = self.context.device().vk_device();
+
+        let pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR)
+            .query_count(1);
+        let query_pool = unsafe {
+            device.create_query_pool(&pool_info, None)
+        }.map_vk_err("vkCreateQueryPool")?;
+        let compacted_size = self.query_compacted_size(query_pool);
+        unsafe { device.destroy_query_pool(query_pool, None) };
+
+        let mut compacted = Self::create(Arc::clone(&self.context), self.ty, self.flags, compacted_size?)?;
+        compacted.geometries = self.geometries.clone();
+        compacted.update_scratch_size = self.update_scratch_size;
+
+        let copy_info = vk::CopyAccelerationStructureInfoKHR::builder()
+            .src(self.acceleration_structure)
+            .dst(compacted.acceleration_structure)
+            .mode(vk::CopyAccelerationStructureModeKHR::COMPACT);
+        self.context.execute_transient(|command_buffer| {
+            unsafe { loader.cmd_copy_acceleration_structure(command_buffer, &copy_info) };
+            Self::cmd_build_barrier(&self.context, command_buffer);
+        })?;
+        Ok(compacted)
+    }
+
+    /// Address referenced by instances of top level structures.
+    pub fn device_address(&self) -> vk::DeviceAddress {
+        self.device_address
+    }
+
+    /// Size of the structure in bytes.
+    pub fn size(&self) -> vk::DeviceSize {
+        self.buffer.size()
+    }
+
+    pub fn ty(&self) -> vk::AccelerationStructureTypeKHR {
+        self.ty
+    }
+
+    pub fn flags(&self) -> vk::BuildAccelerationStructureFlagsKHR {
+        self.flags
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn vk_acceleration_structure(&self) -> vk::AccelerationStructureKHR {
+        self.acceleration_structure
+    }
+
+    fn loader(context: &Context) -> VisionResult<&khr::AccelerationStructure> {
+        context.device().acceleration_structure()
+            .ok_or(VisionError::MissingFeature("acceleration_structure.acceleration_structure"))
+    }
+
+    fn build(context: Arc<Context>,
+             ty: vk::AccelerationStructureTypeKHR,
+             flags: vk::BuildAccelerationStructureFlagsKHR,
+             geometries: &[vk::AccelerationStructureGeometryKHR],
+             layouts: Vec<GeometryLayout>) -> VisionResult<Self> {
+        let loader = Self::loader(&context)?;
+        let primitive_counts = layouts.iter().map(|layout| layout.primitive_count).collect::<Vec<_>>();
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(ty)
+            .flags(flags)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .geometries(geometries);
+        let sizes = unsafe {
+            loader.get_acceleration_structure_build_sizes(vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                                                          &build_info,
+                                                          &primitive_counts)
+        };
+
+        let mut acceleration_structure = Self::create(context, ty, flags, sizes.acceleration_structure_size)?;
+        acceleration_structure.geometries = layouts;
+        acceleration_structure.update_scratch_size = sizes.update_scratch_size;
+        acceleration_structure.execute_build(vk::BuildAccelerationStructureModeKHR::BUILD,
+                                             geometries,
+                                             sizes.build_scratch_size)?;
+        Ok(acceleration_structure)
+    }
+
+    fn update(&mut self,
+              geometries: &[vk::AccelerationStructureGeometryKHR],
+              layouts: &[GeometryLayout]) -> VisionResult<()> {
+        if !self.flags.contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE) {
+            return Err(VisionError::InvalidAccelerationStructure("structure was not built with ALLOW_UPDATE".into()));
+        }
+        if layouts.len() != self.geometries.len() {
+            return Err(VisionError::InvalidAccelerationStructure(format!(
+                "updates must keep the {} geometries, got {}",
+                self.geometries.len(),
+                layouts.len()
+            )));
+        }
+        let changed = self.geometries.iter().zip(layouts).enumerate().find(|(_, (built, layout))| built != layout);
+        if let Some((index, (built, layout))) = changed {
+            return Err(VisionError::InvalidAccelerationStructure(format!(
+                "updates must keep the layout of geometry {}, built with {:?}, got {:?}",
+                index,
+                built,
+                layout
+            )));
+        }
+        self.execute_build(vk::BuildAccelerationStructureModeKHR::UPDATE, geometries, self.update_scratch_size)
+    }
+
+    /// Creates an unbuilt structure of `size` bytes.
+    fn create(context: Arc<Context>,
+              ty: vk::AccelerationStructureTypeKHR,
+              flags: vk::BuildAccelerationStructureFlagsKHR,
+              size: vk::DeviceSize) -> VisionResult<Self> {
+        let loader = Self::loader(&context)?;
+        let buffer = Buffer::create(Arc::clone(&context),
+                                    size as usize,
+                                    vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+                                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                                    vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        let create_info = vk::AccelerationStructureCreateInfoKHR::builder()
+            .buffer(buffer.vk_buffer())
+            .size(size)
+            .ty(ty);
+        let acceleration_structure = unsafe {
+            loader.create_acceleration_structure(&create_info, None)
+        }.map_vk_err("vkCreateAccelerationStructureKHR")?;
+
+        let address_info = vk::AccelerationStructureDeviceAddressInfoKHR::builder()
+            .acceleration_structure(acceleration_structure);
+        let device_address = unsafe { loader.get_acceleration_structure_device_address(&address_info) };
+
+        Ok(Self {
+            context,
+            acceleration_structure,
+            buffer,
+            ty,
+            flags,
+            device_address,
+            geometries: vec![],
+            update_scratch_size: 0,
+        })
+    }
+
+    /// Builds or updates the structure in place with a temporary scratch buffer.
+    fn execute_build(&self,
+                     mode: vk::BuildAccelerationStructureModeKHR,
+                     geometries: &[vk::AccelerationStructureGeometryKHR],
+                     scratch_size: vk::DeviceSize) -> VisionResult<()> {
+        let loader = Self::loader(&self.context)?;
+        let alignment = self.context.acceleration_structure_properties()
+            .min_acceleration_structure_scratch_offset_alignment as vk::DeviceSize;
+
+        // The buffer is padded so that the scratch memory can start at an aligned address.
+        let scratch = Buffer::<u8>::create(Arc::clone(&self.context),
+                                           (scratch_size + alignment) as usize,
+                                           vk::BufferUsageFlags::STORAGE_BUFFER
+                                               | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                                           vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        let scratch_address = align_up(scratch.device_address(), alignment);
+
+        let src = match mode {
+            vk::BuildAccelerationStructureModeKHR::UPDATE => self.acceleration_structure,
+            _ => vk::AccelerationStructureKHR::null(),
+        };
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(self.ty)
+            .flags(self.flags)
+            .mode(mode)
+            .src_acceleration_structure(src)
+            .dst_acceleration_structure(self.acceleration_structure)
+            .geometries(geometries)
+            .scratch_data(vk::DeviceOrHostAddressKHR { device_address: scratch_address });
+        let ranges = self.geometries.iter()
+            .map(|layout| vk::AccelerationStructureBuildRangeInfoKHR {
+                primitive_count: layout.primitive_count,
+                primitive_offset: 0,
+                first_vertex: 0,
+                transform_offset: 0,
+            })
+            .collect::<Vec<_>>();
+
+        self.context.execute_transient(|command_buffer| {
+            // Inputs are usually uploaded right before the build.
+            self.context.cmd_memory_barrier(command_buffer,
+                                            vk::PipelineStageFlags::TRANSFER,
+                                            vk::AccessFlags::TRANSFER_WRITE,
+                                            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                                            vk::AccessFlags::SHADER_READ);
+            unsafe { loader.cmd_build_acceleration_structures(command_buffer, &[build_info.build()], &[&ranges]) };
+            Self::cmd_build_barrier(&self.context, command_buffer);
+        })
+    }
+
+    /// Makes written structures visible to later builds, copies and ray tracing shaders.
+    fn cmd_build_barrier(context: &Context, command_buffer: vk::CommandBuffer) {
+        context.cmd_memory_barrier(command_buffer,
+                                   vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                                   vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                                   vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR
+                                       | vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                                   vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR);
+    }
+
+    fn query_compacted_size(&self, query_pool: vk::QueryPool) -> VisionResult<vk::DeviceSize> {
+        let loader = Self::loader(&self.context)?;
+        let device = self.context.device().vk_device();
+        self.context.execute_transient(|command_buffer| unsafe {
+            device.cmd_reset_query_pool(command_buffer, query_pool, 0, 1);
+            loader.cmd_write_acceleration_structures_properties(command_buffer,
+                                                                &[self.acceleration_structure],
+                                                                vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+                                                                query_pool,
+                                                                0);
+        })?;
+
+        let mut size = [0u64];
+        unsafe {
+            device.get_query_pool_results(query_pool,
+                                          0,
+                                          1,
+                                          &mut size,
+                                          vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT)
+        }.map_vk_err("vkGetQueryPoolResults")?;
+        Ok(size[0])
+    }
+
+    /// Uploads `instances` to a temporary buffer read by a top level build.
+    fn instance_buffer(context: &Arc<Context>, instances: &[AccelerationStructureInstance]) -> VisionResult<Buffer> {
+        if instances.is_empty() {
+            let message = "a top level structure needs at least one instance";
+            return Err(VisionError::InvalidAccelerationStructure(message.into()));
+        }
+        let vk_instances = instances.iter()
+            .map(AccelerationStructureInstance::vk_instance)
+            .collect::<Vec<_>>();
+        let size = vk_instances.len() * size_of::<vk::AccelerationStructureInstanceKHR>();
+        // `VkAccelerationStructureInstanceKHR` is plain data without padding.
+        let data = unsafe { slice::from_raw_parts(vk_instances.as_ptr() as *const u8, size) };
+
+        let buffer = Buffer::create(Arc::clone(context),
+                                    size,
+                                    vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                                        | vk::BufferUsageFlags::TRANSFER_DST,
+                                    vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        buffer.upload(data)?;
+        Ok(buffer)
+    }
+
+    fn instances_geometry(instance_buffer: &Buffer) -> vk::AccelerationStructureGeometryKHR {
+        let instances = vk::AccelerationStructureGeometryInstancesDataKHR::builder()
+            .array_of_pointers(false)
+            .data(vk::DeviceOrHostAddressConstKHR { device_address: instance_buffer.device_address() });
+        vk::AccelerationStructureGeometryKHR::builder()
+            .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR { instances: instances.build() })
+            .build()
+    }
+}
+
+impl Drop for AccelerationStructure {
+    fn drop(&mut self) {
+        let loader = self.context.device().acceleration_structure()
+            .expect("structure was created with acceleration structures enabled");
+        unsafe { loader.destroy_acceleration_structure(self.acceleration_structure, None) };
+    }
+}
@@ -1,45 +1,56 @@
-use std::os::raw::c_void;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut, Range};
+use std::slice;
 use std::sync::Arc;
 
-use ash::version::DeviceV1_0;
 use ash::vk;
+use bytemuck::Pod;
 
-use crate::vulkan::Context;
+use crate::vulkan::{Context, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::memory::{Allocation, AllocationStrategy};
 
-struct MemoryMapPointer(*mut c_void);
+/// Element types that can be bound as index buffer.
+pub trait IndexType: Pod {
+    const INDEX_TYPE: vk::IndexType;
+}
 
-unsafe impl Send for MemoryMapPointer {}
+impl IndexType for u16 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT16;
+}
 
-unsafe impl Sync for MemoryMapPointer {}
+impl IndexType for u32 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+}
 
-pub struct Buffer {
+/// A buffer holding `len` elements of `T`, its memory is sub-allocated from the context's allocator.
+pub struct Buffer<T: Pod = u8> {
     context: Arc<Context>,
-    pub buffer: vk::Buffer,
-    pub memory: vk::DeviceMemory,
-    pub size: vk::DeviceSize,
-    mapped_pointer: Option<MemoryMapPointer>,
+    buffer: vk::Buffer,
+    // Only `None` while dropping.
+    allocation: Option<Allocation>,
+    len: usize,
+    _marker: PhantomData<T>,
 }
 
-
-impl Buffer {
-    fn new(context: Arc<Context>,
-           buffer: vk::Buffer,
-           memory: vk::DeviceMemory,
-           size: vk::DeviceSize) -> Self {
-        Self {
-            context,
-            buffer,
-            memory,
-            size,
-            mapped_pointer: None,
-        }
-    }
-
+impl<T: Pod> Buffer<T> {
+    /// Creates a buffer for `len` elements, the memory type is the first one with `mem_properties`.
     pub fn create(context: Arc<Context>,
-                  size: vk::DeviceSize,
+                  len: usize,
                   usage: vk::BufferUsageFlags,
-                  mem_properties: vk::MemoryPropertyFlags) -> Self {
+                  mem_properties: vk::MemoryPropertyFlags) -> VisionResult<Self> {
+        Self::create_with_strategy(context, len, usage, mem_properties, AllocationStrategy::Buddy)
+    }
+
+    pub fn create_with_strategy(context: Arc<Context>,
+                                len: usize,
+                                usage: vk::BufferUsageFlags,
+                                mem_properties: vk::MemoryPropertyFlags,
+                                strategy: AllocationStrategy) -> VisionResult<Self> {
         let device = context.device().vk_device();
+        // Vulkan does not allow empty buffers.
+        let size = Self::byte_size(len)?.max(1);
         let buffer = {
             let buffer_info = vk::BufferCreateInfo::builder()
                 .size(size)
@@ -47,41 +58,145 @@ impl Buffer {
                 .sharing_mode(vk::SharingMode::EXCLUSIVE);
             unsafe {
                 device.create_buffer(&buffer_info, None)
-                    .expect("Failed to create buffer")
+                    .map_vk_err("vkCreateBuffer")?
             }
         };
 
-        let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
-        let memory = {
-            let mem_type = context.find_memory_type_index(
-                mem_requirements,
-                mem_properties,
-            );
-
-            let alloc_info = vk::MemoryAllocateInfo::builder()
-                .allocation_size(mem_requirements.size)
-                .memory_type_index(mem_type);
-            unsafe {
-                device
-                    .allocate_memory(&alloc_info, None)
-                    .expect("Failed to allocate memory")
+        let allocation = match context.allocator().allocate_for_buffer(buffer, mem_properties, strategy) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe { device.destroy_buffer(buffer, None) };
+                return Err(error);
             }
         };
 
+        // From here on the buffer and its memory are released by drop.
+        let buffer = Self {
+            context: Arc::clone(&context),
+            buffer,
+            allocation: Some(allocation),
+            len,
+            _marker: PhantomData,
+        };
+
         unsafe {
             device
-                .bind_buffer_memory(buffer, memory, 0)
-                .expect("Failed to bind buffer memory")
+                .bind_buffer_memory(buffer.buffer, buffer.allocation().memory(), buffer.allocation().offset())
+                .map_vk_err("vkBindBufferMemory")?
         };
 
-        Buffer::new(context, buffer, memory, mem_requirements.size)
+        Ok(buffer)
+    }
+
+    /// Host visible buffer used as source or destination of transfers, allocated linearly as it is short lived.
+    pub fn staging(context: Arc<Context>, len: usize) -> VisionResult<Self> {
+        Self::create_with_strategy(
+            context,
+            len,
+            vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            AllocationStrategy::Linear,
+        )
+    }
+
+    pub fn staging_from_slice(context: Arc<Context>, data: &[T]) -> VisionResult<Self> {
+        let mut buffer = Self::staging(context, data.len())?;
+        buffer.write(0, data)?;
+        Ok(buffer)
+    }
+
+    /// Device local vertex buffer initialized with `data` through a staging buffer.
+    pub fn vertex(context: Arc<Context>, data: &[T]) -> VisionResult<Self> {
+        Self::device_local_from_slice(context, data, vk::BufferUsageFlags::VERTEX_BUFFER)
+    }
+
+    /// Host visible uniform buffer, meant to be rewritten every frame with `write`.
+    pub fn uniform(context: Arc<Context>, len: usize) -> VisionResult<Self> {
+        Self::create(
+            context,
+            len,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+    }
+
+    /// Device local storage buffer that can be filled and read back with transfers.
+    pub fn storage(context: Arc<Context>, len: usize) -> VisionResult<Self> {
+        Self::create(
+            context,
+            len,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+    }
+
+    pub fn storage_from_slice(context: Arc<Context>, data: &[T]) -> VisionResult<Self> {
+        Self::device_local_from_slice(
+            context,
+            data,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC,
+        )
+    }
+
+    /// Device local buffer of indirect draw or dispatch commands, it can also be written by compute shaders.
+    pub fn indirect(context: Arc<Context>, data: &[T]) -> VisionResult<Self> {
+        Self::device_local_from_slice(
+            context,
+            data,
+            vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+        )
+    }
+
+    /// Device local buffer of vertices or indices that can also be read by acceleration structure builds.
+    pub fn geometry(context: Arc<Context>, data: &[T]) -> VisionResult<Self> {
+        Self::device_local_from_slice(
+            context,
+            data,
+            vk::BufferUsageFlags::VERTEX_BUFFER
+                | vk::BufferUsageFlags::INDEX_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        )
     }
 
-    pub fn cmd_copy(&self, command_buffer: vk::CommandBuffer, src: &Buffer, size: vk::DeviceSize) {
+    fn device_local_from_slice(context: Arc<Context>, data: &[T], usage: vk::BufferUsageFlags) -> VisionResult<Self> {
+        let buffer = Self::create(
+            Arc::clone(&context),
+            data.len(),
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        buffer.upload(data)?;
+        Ok(buffer)
+    }
+
+    /// Copies `data` to the start of the buffer through a temporary staging buffer and waits for completion. An
+    /// empty `data` does nothing.
+    pub fn upload(&self, data: &[T]) -> VisionResult<()> {
+        self.check_range(0, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let staging = Self::staging_from_slice(Arc::clone(&self.context), data)?;
+        self.context.execute_transient(|command_buffer| {
+            self.cmd_copy(command_buffer, &staging, data.len())
+        })?
+    }
+
+    /// Copies the first `len` elements of `src` to the start of this buffer, nothing is recorded if `len` is 0.
+    pub fn cmd_copy(&self, command_buffer: vk::CommandBuffer, src: &Buffer<T>, len: usize) -> VisionResult<()> {
+        src.check_range(0, len)?;
+        self.check_range(0, len)?;
+        if len == 0 {
+            return Ok(());
+        }
         let region = vk::BufferCopy {
             src_offset: 0,
             dst_offset: 0,
-            size,
+            size: Self::byte_size(len)?,
         };
         let regions = [region];
 
@@ -91,41 +206,203 @@ impl Buffer {
                 .vk_device()
                 .cmd_copy_buffer(command_buffer, src.buffer, self.buffer, &regions)
         };
+        Ok(())
     }
 
-    pub fn map_memory(&mut self) -> *mut c_void {
-        if let Some(ptr) = &self.mapped_pointer {
-            ptr.0
-        } else {
-            unsafe {
-                let ptr = self
-                    .context
-                    .device()
-                    .vk_device()
-                    .map_memory(self.memory, 0, self.size, vk::MemoryMapFlags::empty())
-                    .expect("Failed to map memory");
-                self.mapped_pointer = Some(MemoryMapPointer(ptr));
-                ptr
-            }
+    /// Maps the whole buffer, the returned guard flushes non coherent memory when it is dropped.
+    pub fn map(&mut self) -> VisionResult<MappedSlice<'_, T>> {
+        let len = self.len;
+        let ptr = self.mapped_ptr()? as *mut T;
+        self.invalidate(0..len)?;
+        Ok(MappedSlice {
+            slice: unsafe { slice::from_raw_parts_mut(ptr, len) },
+            buffer: self,
+        })
+    }
+
+    /// Writes `data` starting at element `offset`.
+    pub fn write(&mut self, offset: usize, data: &[T]) -> VisionResult<()> {
+        self.check_range(offset, data.len())?;
+        let ptr = self.mapped_ptr()? as *mut T;
+        unsafe {
+            slice::from_raw_parts_mut(ptr.add(offset), data.len()).copy_from_slice(data);
         }
+        self.flush(offset..offset + data.len())
     }
 
-    pub fn unmap_memory(&mut self) {
-        if self.mapped_pointer.take().is_some() {
-            unsafe {
-                self.context.device().vk_device().unmap_memory(self.memory);
-            }
+    /// Reads `len` elements starting at element `offset`.
+    pub fn read(&self, offset: usize, len: usize) -> VisionResult<Vec<T>> {
+        self.check_range(offset, len)?;
+        let ptr = self.mapped_ptr()? as *const T;
+        self.invalidate(offset..offset + len)?;
+        let data = unsafe { slice::from_raw_parts(ptr.add(offset), len) };
+        Ok(data.to_vec())
+    }
+
+    /// Copies the whole buffer into host memory, device local buffers are read through a staging buffer. GPU work
+    /// writing to a host visible buffer has to be completed before.
+    pub fn readback(&self) -> VisionResult<Vec<T>> {
+        if self.len == 0 {
+            return Ok(vec![]);
+        }
+        if self.memory_properties().contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            return self.read(0, self.len);
         }
+
+        let staging = Self::staging(Arc::clone(&self.context), self.len)?;
+        self.context.execute_transient(|command_buffer| -> VisionResult<()> {
+            self.context.cmd_memory_barrier(command_buffer,
+                                            vk::PipelineStageFlags::ALL_COMMANDS,
+                                            vk::AccessFlags::MEMORY_WRITE,
+                                            vk::PipelineStageFlags::TRANSFER,
+                                            vk::AccessFlags::TRANSFER_READ);
+            staging.cmd_copy(command_buffer, self, self.len)?;
+            self.context.cmd_memory_barrier(command_buffer,
+                                            vk::PipelineStageFlags::TRANSFER,
+                                            vk::AccessFlags::TRANSFER_WRITE,
+                                            vk::PipelineStageFlags::HOST,
+                                            vk::AccessFlags::HOST_READ);
+            Ok(())
+        })??;
+        staging.read(0, self.len)
+    }
+
+    pub fn vk_buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    /// Address of the buffer in shaders, it needs `SHADER_DEVICE_ADDRESS` usage and the `buffer_device_address`
+    /// feature has to be enabled.
+    pub fn device_address(&self) -> vk::DeviceAddress {
+        let info = vk::BufferDeviceAddressInfo::builder().buffer(self.buffer);
+        unsafe { self.context.device().vk_device().get_buffer_device_address(&info) }
+    }
+
+    /// Describes the whole buffer for a descriptor write.
+    pub fn descriptor_info(&self) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        }
+    }
+
+    pub fn vk_memory(&self) -> vk::DeviceMemory {
+        self.allocation().memory()
+    }
+
+    /// Offset of the buffer inside `vk_memory`.
+    pub fn memory_offset(&self) -> vk::DeviceSize {
+        self.allocation().offset()
+    }
+
+    /// Number of elements of `T`.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size of the elements in bytes, the allocation may be larger.
+    pub fn size(&self) -> vk::DeviceSize {
+        // Cannot overflow, `create` checked the size of `len` elements.
+        (self.len * size_of::<T>()) as vk::DeviceSize
+    }
+
+    pub fn memory_properties(&self) -> vk::MemoryPropertyFlags {
+        self.allocation().memory_properties()
+    }
+
+    fn allocation(&self) -> &Allocation {
+        self.allocation.as_ref().expect("buffer allocation is only taken on drop")
+    }
+
+    fn byte_size(len: usize) -> VisionResult<vk::DeviceSize> {
+        len.checked_mul(size_of::<T>())
+            .map(|size| size as vk::DeviceSize)
+            .ok_or(VisionError::BufferTooLarge { len, element_size: size_of::<T>() })
+    }
+
+    fn check_range(&self, offset: usize, len: usize) -> VisionResult<()> {
+        if offset.checked_add(len).is_none_or(|end| end > self.len) {
+            return Err(VisionError::OutOfBounds {
+                offset,
+                len,
+                capacity: self.len,
+            });
+        }
+        Ok(())
+    }
+
+    /// Host visible memory is mapped by the allocator for the whole lifetime of the buffer.
+    fn mapped_ptr(&self) -> VisionResult<*mut u8> {
+        self.allocation().mapped_ptr().ok_or(VisionError::MemoryNotHostVisible)
+    }
+
+    fn flush(&self, elements: Range<usize>) -> VisionResult<()> {
+        let (offset, size) = Self::byte_range(elements);
+        self.context.allocator().flush(self.allocation(), offset, size)
+    }
+
+    fn invalidate(&self, elements: Range<usize>) -> VisionResult<()> {
+        let (offset, size) = Self::byte_range(elements);
+        self.context.allocator().invalidate(self.allocation(), offset, size)
+    }
+
+    /// Callers check `elements` against the length first, so the byte range fits like the buffer's size does.
+    fn byte_range(elements: Range<usize>) -> (vk::DeviceSize, vk::DeviceSize) {
+        let offset = (elements.start * size_of::<T>()) as vk::DeviceSize;
+        let size = (elements.len() * size_of::<T>()) as vk::DeviceSize;
+        (offset, size)
     }
 }
 
+impl<T: IndexType> Buffer<T> {
+    /// Device local index buffer initialized with `data` through a staging buffer.
+    pub fn index(context: Arc<Context>, data: &[T]) -> VisionResult<Self> {
+        Self::device_local_from_slice(context, data, vk::BufferUsageFlags::INDEX_BUFFER)
+    }
 
-impl Drop for Buffer {
+    pub fn index_type(&self) -> vk::IndexType {
+        T::INDEX_TYPE
+    }
+}
+
+impl<T: Pod> Drop for Buffer<T> {
     fn drop(&mut self) {
         unsafe {
-            self.unmap_memory();
             self.context.device().vk_device().destroy_buffer(self.buffer, None);
-            self.context.device().vk_device().free_memory(self.memory, None);
+        }
+        if let Some(allocation) = self.allocation.take() {
+            self.context.allocator().free(allocation);
         }
     }
-}
\ No newline at end of file
+}
+
+/// Mutable view of a mapped buffer, writes are flushed when the guard is dropped.
+pub struct MappedSlice<'a, T: Pod> {
+    buffer: &'a Buffer<T>,
+    slice: &'a mut [T],
+}
+
+impl<T: Pod> Deref for MappedSlice<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.slice
+    }
+}
+
+impl<T: Pod> DerefMut for MappedSlice<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.slice
+    }
+}
+
+impl<T: Pod> Drop for MappedSlice<'_, T> {
+    fn drop(&mut self) {
+        let _ = self.buffer.flush(0..self.slice.len());
+    }
+}
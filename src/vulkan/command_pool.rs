@@ -1,35 +1,40 @@
 use std::sync::Arc;
 
-use ash::version::DeviceV1_0;
 use ash::vk;
 
-use crate::vulkan::{Context, Device};
-use crate::vulkan::physical_device::QueueFamilyIndices;
+use crate::vulkan::VisionResult;
+use crate::vulkan::error::VkResultExt;
 use crate::vulkan::shared_context::SharedContext;
 
 pub struct CommandPool {
     context: Arc<SharedContext>,
     command_pool: vk::CommandPool,
+    queue_family_index: u32,
 }
 
 impl CommandPool {
-    pub fn new(context: Arc<SharedContext>, queue_families_indices: QueueFamilyIndices, create_flags: vk::CommandPoolCreateFlags) -> Self {
+    pub fn new(context: Arc<SharedContext>, queue_family_index: u32, create_flags: vk::CommandPoolCreateFlags) -> VisionResult<Self> {
         let create_info = vk::CommandPoolCreateInfo::builder()
-            .queue_family_index(queue_families_indices.graphics_family)
+            .queue_family_index(queue_family_index)
             .flags(create_flags);
         let command_pool = unsafe {
             context.device().vk_device().create_command_pool(&create_info, None)
-                .expect("Failed to create command pool")
+                .map_vk_err("vkCreateCommandPool")?
         };
-        Self {
+        Ok(Self {
             context,
             command_pool,
-        }
+            queue_family_index,
+        })
     }
 
     pub fn vk_command_pool(&self) -> vk::CommandPool {
         self.command_pool
     }
+
+    pub fn queue_family_index(&self) -> u32 {
+        self.queue_family_index
+    }
 }
 
 impl Drop for CommandPool {
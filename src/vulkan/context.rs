@@ -1,148 +1,224 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use ash::{Entry, vk};
-use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::vk;
 use winit::window::Window;
 
-use crate::vulkan::{CommandPool, Device, Instance, PhysicalDevice, Surface};
+use crate::vulkan::{AccelerationStructure, AccelerationStructureInstance, CommandPool, Device, DeviceCandidate, DeviceRequirements, DeviceSelector, Instance, PhysicalDevice, QueueType, Submission, Surface, TriangleGeometry, VisionError, VisionResult};
 use crate::vulkan::debug::ValidationInfo;
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::memory::{Allocator, AllocatorStats};
 use crate::vulkan::shared_context::SharedContext;
 
 pub struct Context {
     shared_context: Arc<SharedContext>,
     general_command_pool: CommandPool,
-    transient_command_pool: CommandPool,
+    transient_command_pools: HashMap<QueueType, CommandPool>,
 }
 
 impl Context {
-    pub fn new(window: &Window, validation_info: ValidationInfo, required_extensions: Vec<*const i8>) -> Self {
-        let shared_context = Arc::new(SharedContext::new(window, validation_info, required_extensions));
-
-        let general_command_pool = CommandPool::new(Arc::clone(&shared_context),
-                                                    shared_context.device().physical_device().queue_family_indices(),
-                                                    vk::CommandPoolCreateFlags::empty());
-
-        let transient_command_pool = CommandPool::new(Arc::clone(&shared_context),
-                                                      shared_context.device().physical_device().queue_family_indices(),
-                                                      vk::CommandPoolCreateFlags::TRANSIENT);
+    /// Creates a context presenting to `window`. Without a `device_selector` the highest scoring device is used
+    /// unless the `VISION_DEVICE` environment variable selects one.
+    pub fn new(window: &Window,
+               validation_info: ValidationInfo,
+               requirements: DeviceRequirements,
+               device_selector: Option<DeviceSelector>) -> VisionResult<Self> {
+        let shared_context = Arc::new(SharedContext::new(window, validation_info, requirements, device_selector)?);
+        Self::from_shared_context(shared_context)
+    }
 
-        Self {
-            shared_context,
-            general_command_pool,
-            transient_command_pool,
-        }
+    /// Creates a context that does not require a window, no surface is created and no present queue is requested.
+    pub fn new_headless(validation_info: ValidationInfo,
+                        requirements: DeviceRequirements,
+                        device_selector: Option<DeviceSelector>) -> VisionResult<Self> {
+        let shared_context = Arc::new(SharedContext::new_headless(validation_info, requirements, device_selector)?);
+        Self::from_shared_context(shared_context)
     }
 
-    pub fn new_thread(&self) -> Self {
-        let shared_context = Arc::clone(&self.shared_context);
+    pub fn new_thread(&self) -> VisionResult<Self> {
+        Self::from_shared_context(Arc::clone(&self.shared_context))
+    }
 
+    fn from_shared_context(shared_context: Arc<SharedContext>) -> VisionResult<Self> {
         let general_command_pool = CommandPool::new(Arc::clone(&shared_context),
-                                                    shared_context.device().physical_device().queue_family_indices(),
-                                                    vk::CommandPoolCreateFlags::empty());
-
-        let transient_command_pool = CommandPool::new(Arc::clone(&shared_context),
-                                                      shared_context.device().physical_device().queue_family_indices(),
-                                                      vk::CommandPoolCreateFlags::TRANSIENT);
+                                                    shared_context.device().queue_family_index(QueueType::Graphics),
+                                                    vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)?;
+
+        let mut transient_command_pools = HashMap::new();
+        for queue_type in [QueueType::Graphics, QueueType::Compute, QueueType::Transfer] {
+            let command_pool = CommandPool::new(Arc::clone(&shared_context),
+                                                shared_context.device().queue_family_index(queue_type),
+                                                vk::CommandPoolCreateFlags::TRANSIENT)?;
+            transient_command_pools.insert(queue_type, command_pool);
+        }
 
-        Self {
+        Ok(Self {
             shared_context,
             general_command_pool,
-            transient_command_pool,
-        }
+            transient_command_pools,
+        })
     }
 
-    pub fn execute_transient<R, F: FnOnce(vk::CommandBuffer) -> R>(&self, executor: F) -> R {
-        let command_buffer = {
-            let alloc_info = vk::CommandBufferAllocateInfo::builder()
-                .level(vk::CommandBufferLevel::PRIMARY)
-                .command_pool(self.transient_command_pool.vk_command_pool())
-                .command_buffer_count(1);
-
-            unsafe {
-                self.device().vk_device()
-                    .allocate_command_buffers(&alloc_info)
-                    .expect("Failed to allocate command buffer")[0]
-            }
-        };
-
-        let command_buffers = [command_buffer];
-
-        {
-            let begin_info = vk::CommandBufferBeginInfo::builder()
-                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-            unsafe {
-                self.device().vk_device()
-                    .begin_command_buffer(command_buffer, &begin_info)
-                    .expect("Failed to begin command buffer")
-            };
-        }
+    /// Records a one time command buffer with `executor`, submits it to the graphics queue and waits for completion.
+    pub fn execute_transient<R, F: FnOnce(vk::CommandBuffer) -> R>(&self, executor: F) -> VisionResult<R> {
+        self.execute_transient_on(QueueType::Graphics, executor)
+    }
 
-        let executor_result = executor(command_buffer);
+    /// Like `execute_transient` but submits to the queue of `queue_type`. Resources with exclusive sharing mode
+    /// that are also used on another queue family need a queue family ownership transfer.
+    pub fn execute_transient_on<R, F: FnOnce(vk::CommandBuffer) -> R>(&self, queue_type: QueueType, executor: F) -> VisionResult<R> {
+        self.execute_async(queue_type, executor)?.wait()
+    }
 
-        unsafe {
-            self.device().vk_device()
-                .end_command_buffer(command_buffer)
-                .expect("Failed to end command buffer")
-        };
+    /// Records a one time command buffer with `executor` and submits it without waiting. The returned submission
+    /// holds the result of `executor`, e.g. staging buffers, until it completed.
+    pub fn execute_async<R, F: FnOnce(vk::CommandBuffer) -> R>(&self, queue_type: QueueType, executor: F) -> VisionResult<Submission<'_, R>> {
+        Submission::new(self, queue_type, executor)
+    }
 
-        {
-            let submit_info = vk::SubmitInfo::builder()
-                .command_buffers(&command_buffers)
-                .build();
-            let submit_infos = [submit_info];
-            unsafe {
-                let queue = self.device().graphics_queue();
-                self.device().vk_device()
-                    .queue_submit(queue, &submit_infos, vk::Fence::null())
-                    .expect("Failed to submit to queue");
-                self.device().vk_device()
-                    .queue_wait_idle(queue)
-                    .expect("Failed to wait for queue to be idle");
-            };
-        }
+    pub(crate) fn transient_command_pool(&self, queue_type: QueueType) -> &CommandPool {
+        &self.transient_command_pools[&queue_type]
+    }
 
+    /// Records a global memory barrier, e.g. to make transfer writes visible to host reads.
+    pub fn cmd_memory_barrier(&self,
+                              command_buffer: vk::CommandBuffer,
+                              src_stage: vk::PipelineStageFlags,
+                              src_access: vk::AccessFlags,
+                              dst_stage: vk::PipelineStageFlags,
+                              dst_access: vk::AccessFlags) {
+        let barriers = [vk::MemoryBarrier::builder()
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .build()];
         unsafe {
-            self.device().vk_device().free_command_buffers(self.transient_command_pool.vk_command_pool(), &command_buffers);
+            self.device().vk_device().cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &barriers,
+                &[],
+                &[],
+            )
         };
+    }
 
-        executor_result
+    pub fn graphics_queue_wait_idle(&self) -> VisionResult<()> {
+        self.queue_wait_idle(QueueType::Graphics)
     }
 
-    pub fn graphics_queue_wait_idle(&self) {
+    pub fn queue_wait_idle(&self, queue_type: QueueType) -> VisionResult<()> {
         unsafe {
             self.device().vk_device()
-                .queue_wait_idle(self.device().graphics_queue())
-                .expect("Failed to wait for queue to be idle")
+                .queue_wait_idle(self.device().queue(queue_type))
+                .map_vk_err("vkQueueWaitIdle")
         }
     }
 
+    pub fn queue(&self, queue_type: QueueType) -> vk::Queue {
+        self.device().queue(queue_type)
+    }
+
+    pub fn queue_family_index(&self, queue_type: QueueType) -> u32 {
+        self.device().queue_family_index(queue_type)
+    }
+
     pub fn instance(&self) -> &Instance {
         self.shared_context.instance()
     }
 
-    pub fn surface(&self) -> &Surface {
+    pub fn surface(&self) -> Option<&Surface> {
         self.shared_context.surface()
     }
 
+    pub fn is_headless(&self) -> bool {
+        self.surface().is_none()
+    }
+
     pub fn device(&self) -> &Device {
         self.shared_context.device()
     }
 
+    /// Lists all physical devices of the instance with the reason unsuitable ones were rejected.
+    pub fn device_candidates(&self) -> VisionResult<Vec<DeviceCandidate>> {
+        PhysicalDevice::candidates(self.instance(),
+                                   self.surface(),
+                                   self.device().physical_device().requirements(),
+                                   None)
+    }
+
+    pub fn format_properties(&self, format: vk::Format) -> vk::FormatProperties {
+        self.device().physical_device().format_properties(self.instance(), format)
+    }
+
+    pub fn ray_tracing_pipeline_properties(&self) -> vk::PhysicalDeviceRayTracingPipelinePropertiesKHR {
+        self.device().physical_device().ray_tracing_pipeline_properties(self.instance())
+    }
+
+    pub fn acceleration_structure_properties(&self) -> vk::PhysicalDeviceAccelerationStructurePropertiesKHR {
+        self.device().physical_device().acceleration_structure_properties(self.instance())
+    }
+
+    /// Builds a bottom level acceleration structure of triangle geometries and waits for completion.
+    pub fn build_bottom_level_acceleration_structure(self: &Arc<Self>,
+                                                     geometries: &[TriangleGeometry],
+                                                     flags: vk::BuildAccelerationStructureFlagsKHR)
+                                                     -> VisionResult<AccelerationStructure> {
+        AccelerationStructure::build_bottom_level(Arc::clone(self), geometries, flags)
+    }
+
+    /// Builds a top level acceleration structure of bottom level instances and waits for completion.
+    pub fn build_top_level_acceleration_structure(self: &Arc<Self>,
+                                                  instances: &[AccelerationStructureInstance],
+                                                  flags: vk::BuildAccelerationStructureFlagsKHR)
+                                                  -> VisionResult<AccelerationStructure> {
+        AccelerationStructure::build_top_level(Arc::clone(self), instances, flags)
+    }
 
-    pub fn find_memory_type_index(&self, requirements: vk::MemoryRequirements, required_properties: vk::MemoryPropertyFlags) -> u32 {
-        let memory_properties = unsafe {
-            self.instance().vk_instance().get_physical_device_memory_properties(self.device().physical_device().vk_physical_device())
+    /// Returns the first of `candidates` supporting `features` with `tiling`.
+    pub fn find_supported_format(&self,
+                                 candidates: &[vk::Format],
+                                 tiling: vk::ImageTiling,
+                                 features: vk::FormatFeatureFlags) -> Option<vk::Format> {
+        self.device().physical_device().find_supported_format(self.instance(), candidates, tiling, features)
+    }
+
+    /// Most precise depth format usable as optimally tiled depth attachment, with a stencil component if
+    /// `with_stencil` is set.
+    pub fn depth_format(&self, with_stencil: bool) -> VisionResult<vk::Format> {
+        let candidates: &[vk::Format] = if with_stencil {
+            &[vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT, vk::Format::D16_UNORM_S8_UINT]
+        } else {
+            &[vk::Format::D32_SFLOAT, vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT, vk::Format::D16_UNORM]
         };
+        self.find_supported_format(candidates,
+                                   vk::ImageTiling::OPTIMAL,
+                                   vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+            .ok_or(VisionError::NoDepthFormat)
+    }
 
-        for i in 0..memory_properties.memory_type_count {
-            if requirements.memory_type_bits & (1 << i) != 0
-                && memory_properties.memory_types[i as usize]
-                .property_flags
-                .contains(required_properties)
-            {
-                return i;
-            }
-        }
-        panic!("Failed to find suitable memory type.")
+    /// Highest sample count for color and depth attachments supported by the device, capped at `max_samples`.
+    pub fn msaa_samples(&self, max_samples: vk::SampleCountFlags) -> vk::SampleCountFlags {
+        self.device().physical_device().max_usable_sample_count(max_samples)
+    }
+
+    /// Graphics pool for long lived command buffers, they can be reset individually.
+    pub fn general_command_pool(&self) -> &CommandPool {
+        &self.general_command_pool
+    }
+
+
+    pub fn allocator(&self) -> &Allocator {
+        self.shared_context.allocator()
+    }
+
+    /// Memory statistics of the allocator shared by all contexts created with `new_thread`.
+    pub fn memory_stats(&self) -> AllocatorStats {
+        self.allocator().stats()
+    }
+
+    pub fn find_memory_type_index(&self, requirements: vk::MemoryRequirements, required_properties: vk::MemoryPropertyFlags) -> VisionResult<u32> {
+        self.allocator().find_memory_type_index(requirements, required_properties)
     }
 }
\ No newline at end of file
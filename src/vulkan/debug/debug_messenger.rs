@@ -6,29 +6,34 @@ use ash::{Entry, Instance};
 use ash::extensions::ext::DebugUtils;
 use ash::vk;
 
+use crate::vulkan::VisionResult;
+use crate::vulkan::error::VkResultExt;
+
 pub struct DebugMessenger {
     utils_loader: DebugUtils,
     utils_messenger: vk::DebugUtilsMessengerEXT,
 }
 
 impl DebugMessenger {
-    pub fn new(entry: &Entry, instance: &Instance) -> Self {
+    pub fn new(entry: &Entry, instance: &Instance) -> VisionResult<Self> {
         let utils_loader = DebugUtils::new(entry, instance);
 
         let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
             //.message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
             .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING)
-            .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
+            .message_type(vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE)
             .pfn_user_callback(Some(vulkan_debug_callback));
 
         let utils_messenger = unsafe {
             utils_loader.create_debug_utils_messenger(&create_info, None)
-        }.unwrap();
+        }.map_vk_err("vkCreateDebugUtilsMessengerEXT")?;
 
-        Self {
+        Ok(Self {
             utils_loader,
             utils_messenger,
-        }
+        })
     }
 
     pub fn required_extension_names() -> Vec<*const i8> {
@@ -51,7 +56,7 @@ unsafe extern "system" fn vulkan_debug_callback(
     _user_data: *mut c_void,
 ) -> vk::Bool32 {
     let callback_data = *p_callback_data;
-    let message_id_number = callback_data.message_id_number as i32;
+    let message_id_number = callback_data.message_id_number;
 
     let message_id_name = if callback_data.p_message_id_name.is_null() {
         Cow::from("")
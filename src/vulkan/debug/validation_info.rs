@@ -1,7 +1,9 @@
 use std::ffi::{CStr, CString};
 
 use ash::Entry;
-use ash::version::EntryV1_0;
+
+use crate::vulkan::VisionResult;
+use crate::vulkan::error::VkResultExt;
 
 pub struct ValidationInfo {
     pub is_enabled: bool,
@@ -9,16 +11,16 @@ pub struct ValidationInfo {
 }
 
 impl ValidationInfo {
-    pub fn check_validation_layer_support(&self, entry: &Entry) -> bool {
+    pub fn check_validation_layer_support(&self, entry: &Entry) -> VisionResult<bool> {
         if !self.is_enabled {
-            return false;
+            return Ok(false);
         }
 
         let layer_properties = entry.enumerate_instance_layer_properties()
-            .expect("Failed to enumerate instance layer properties");
+            .map_vk_err("vkEnumerateInstanceLayerProperties")?;
 
-        if layer_properties.len() <= 0 {
-            return false;
+        if layer_properties.is_empty() {
+            return Ok(false);
         }
 
         for required_layer_name in self.required_validation_layers.iter() {
@@ -26,20 +28,19 @@ impl ValidationInfo {
 
             for layer_property in layer_properties.iter() {
                 let test_layer_name = unsafe {
-                    CStr::from_ptr((&layer_property.layer_name).as_ptr())
-                }.to_str().expect("Failed to convert string");
+                    CStr::from_ptr(layer_property.layer_name.as_ptr())
+                };
 
-                let required_layer_str = required_layer_name.to_str().expect("Failed to convert string");
-                if required_layer_str == test_layer_name {
+                if required_layer_name.as_c_str() == test_layer_name {
                     layer_found = true;
                     break;
                 }
             }
 
-            if layer_found == false {
-                return false;
+            if !layer_found {
+                return Ok(false);
             }
         }
-        true
+        Ok(true)
     }
 }
\ No newline at end of file
@@ -1,29 +1,31 @@
-use std::collections::HashSet;
-use std::ops::Deref;
+use std::ffi::CStr;
 
 use ash::Device as VkDevice;
-use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::extensions::khr;
 use ash::vk;
 use ash::vk::Queue;
 
-use crate::vulkan::{Instance, PhysicalDevice, Surface};
+use crate::vulkan::{DeviceFeatures, Instance, PhysicalDevice, QueueType, VisionResult};
+use crate::vulkan::error::VkResultExt;
 
 pub struct Device {
     device: VkDevice,
     physical_device: PhysicalDevice,
     graphics_queue: Queue,
-    present_queue: Queue,
+    present_queue: Option<Queue>,
+    compute_queue: Queue,
+    transfer_queue: Queue,
+    dynamic_rendering: Option<khr::DynamicRendering>,
+    ray_tracing_pipeline: Option<khr::RayTracingPipeline>,
+    acceleration_structure: Option<khr::AccelerationStructure>,
 }
 
 impl Device {
-    pub fn new(instance: &Instance, physical_device: PhysicalDevice) -> Self {
+    pub fn new(instance: &Instance, physical_device: PhysicalDevice) -> VisionResult<Self> {
         let queue_family_indices = physical_device.queue_family_indices();
         let queue_priorities = [1.0f32];
 
-        let mut indices = vec![queue_family_indices.graphics_family, queue_family_indices.present_family];
-        indices.dedup();
-
-        let queue_create_infos = indices.iter()
+        let queue_create_infos = queue_family_indices.unique_families().iter()
             .map(|index| {
                 vk::DeviceQueueCreateInfo::builder()
                     .queue_family_index(*index)
@@ -32,51 +34,129 @@ impl Device {
             }).collect::<Vec<_>>();
 
 
-        let device_features = vk::PhysicalDeviceFeatures {
-            ..Default::default()
-        };
+        let extension_names = physical_device.enabled_extensions().iter()
+            .map(|extension| extension.as_ptr())
+            .collect::<Vec<_>>();
+
+        let mut enabled_features = *physical_device.enabled_features();
+        let mut features2 = enabled_features.features2();
 
         let device_create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
-            .enabled_extension_names(physical_device.required_extensions())
-            .enabled_features(&device_features);
+            .enabled_extension_names(&extension_names)
+            .push_next(&mut *features2);
 
         let device = unsafe {
             instance.vk_instance()
                 .create_device(physical_device.vk_physical_device(), &device_create_info, None)
-        }.unwrap();
+        }.map_vk_err("vkCreateDevice")?;
 
         let graphics_queue = unsafe {
             device.get_device_queue(queue_family_indices.graphics_family, 0)
         };
 
-        let present_queue = unsafe {
-            device.get_device_queue(queue_family_indices.present_family, 0)
+        let present_queue = queue_family_indices.present_family
+            .map(|present_family| unsafe { device.get_device_queue(present_family, 0) });
+
+        let compute_queue = unsafe {
+            device.get_device_queue(queue_family_indices.compute_family, 0)
+        };
+
+        let transfer_queue = unsafe {
+            device.get_device_queue(queue_family_indices.transfer_family, 0)
+        };
+
+        let dynamic_rendering = match physical_device.enabled_features().dynamic_rendering.dynamic_rendering {
+            vk::TRUE => Some(khr::DynamicRendering::new(instance.vk_instance(), &device)),
+            _ => None,
+        };
+        let ray_tracing_pipeline = match physical_device.enabled_features().ray_tracing_pipeline.ray_tracing_pipeline {
+            vk::TRUE => Some(khr::RayTracingPipeline::new(instance.vk_instance(), &device)),
+            _ => None,
+        };
+        let acceleration_structure = match physical_device.enabled_features().acceleration_structure.acceleration_structure {
+            vk::TRUE => Some(khr::AccelerationStructure::new(instance.vk_instance(), &device)),
+            _ => None,
         };
 
-        Self {
+        Ok(Self {
             device,
             physical_device,
             graphics_queue,
             present_queue,
-        }
+            compute_queue,
+            transfer_queue,
+            dynamic_rendering,
+            ray_tracing_pipeline,
+            acceleration_structure,
+        })
     }
 
     pub fn physical_device(&self) -> &PhysicalDevice {
         &self.physical_device
     }
 
+    pub fn enabled_extensions(&self) -> &[&'static CStr] {
+        self.physical_device.enabled_extensions()
+    }
+
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.physical_device.is_extension_enabled(name)
+    }
+
+    /// Features enabled on the device, optional features are only set if the device supports them.
+    pub fn enabled_features(&self) -> &DeviceFeatures {
+        self.physical_device.enabled_features()
+    }
+
     pub fn vk_device(&self) -> &VkDevice {
         &self.device
     }
 
+    /// Loaded when the `dynamic_rendering` feature is enabled.
+    pub fn dynamic_rendering(&self) -> Option<&khr::DynamicRendering> {
+        self.dynamic_rendering.as_ref()
+    }
+
+    /// Loaded when the `ray_tracing_pipeline` feature is enabled.
+    pub fn ray_tracing_pipeline(&self) -> Option<&khr::RayTracingPipeline> {
+        self.ray_tracing_pipeline.as_ref()
+    }
+
+    /// Loaded when the `acceleration_structure` feature is enabled.
+    pub fn acceleration_structure(&self) -> Option<&khr::AccelerationStructure> {
+        self.acceleration_structure.as_ref()
+    }
+
     pub fn graphics_queue(&self) -> Queue {
         self.graphics_queue
     }
 
-    pub fn present_queue(&self) -> Queue {
+    pub fn present_queue(&self) -> Option<Queue> {
         self.present_queue
     }
+
+    /// Same queue as `graphics_queue` if the device has no dedicated compute family.
+    pub fn compute_queue(&self) -> Queue {
+        self.compute_queue
+    }
+
+    /// Same queue as `compute_queue` or `graphics_queue` if the device has no dedicated transfer family.
+    pub fn transfer_queue(&self) -> Queue {
+        self.transfer_queue
+    }
+
+    pub fn queue(&self, queue_type: QueueType) -> Queue {
+        match queue_type {
+            QueueType::Graphics => self.graphics_queue,
+            QueueType::Compute => self.compute_queue,
+            QueueType::Transfer => self.transfer_queue,
+        }
+    }
+
+    pub fn queue_family_index(&self, queue_type: QueueType) -> u32 {
+        self.physical_device.queue_family_indices().family(queue_type)
+    }
 }
 
 impl Drop for Device {
@@ -0,0 +1,339 @@
+use std::ffi::CStr;
+use std::ptr;
+
+use ash::extensions::khr;
+use ash::vk;
+
+use crate::vulkan::Instance;
+
+/// Generates a function combining each `VkBool32` member of two feature structs with `op`, the names of the members
+/// set in the result are appended to `names`.
+macro_rules! feature_fields {
+    ($name:ident, $ty:ty, [$($field:ident),* $(,)?]) => {
+        fn $name(lhs: &$ty, rhs: &$ty, op: fn(bool, bool) -> bool, prefix: &str, names: &mut Vec<String>) -> $ty {
+            let mut result = *lhs;
+            $(
+                result.$field = if op(lhs.$field == vk::TRUE, rhs.$field == vk::TRUE) {
+                    names.push(format!("{}.{}", prefix, stringify!($field)));
+                    vk::TRUE
+                } else {
+                    vk::FALSE
+                };
+            )*
+            result
+        }
+    };
+}
+
+feature_fields!(core_fields, vk::PhysicalDeviceFeatures, [
+    robust_buffer_access, full_draw_index_uint32, image_cube_array, independent_blend,
+    geometry_shader, tessellation_shader, sample_rate_shading, dual_src_blend, logic_op,
+    multi_draw_indirect, draw_indirect_first_instance, depth_clamp, depth_bias_clamp,
+    fill_mode_non_solid, depth_bounds, wide_lines, large_points, alpha_to_one, multi_viewport,
+    sampler_anisotropy, texture_compression_etc2, texture_compression_astc_ldr,
+    texture_compression_bc, occlusion_query_precise, pipeline_statistics_query,
+    vertex_pipeline_stores_and_atomics, fragment_stores_and_atomics,
+    shader_tessellation_and_geometry_point_size, shader_image_gather_extended,
+    shader_storage_image_extended_formats, shader_storage_image_multisample,
+    shader_storage_image_read_without_format, shader_storage_image_write_without_format,
+    shader_uniform_buffer_array_dynamic_indexing, shader_sampled_image_array_dynamic_indexing,
+    shader_storage_buffer_array_dynamic_indexing, shader_storage_image_array_dynamic_indexing,
+    shader_clip_distance, shader_cull_distance, shader_float64, shader_int64, shader_int16,
+    shader_resource_residency, shader_resource_min_lod, sparse_binding, sparse_residency_buffer,
+    sparse_residency_image2_d, sparse_residency_image3_d, sparse_residency2_samples,
+    sparse_residency4_samples, sparse_residency8_samples, sparse_residency16_samples,
+    sparse_residency_aliased, variable_multisample_rate, inherited_queries,
+]);
+
+feature_fields!(vulkan_11_fields, vk::PhysicalDeviceVulkan11Features, [
+    storage_buffer16_bit_access, uniform_and_storage_buffer16_bit_access, storage_push_constant16,
+    storage_input_output16, multiview, multiview_geometry_shader, multiview_tessellation_shader,
+    variable_pointers_storage_buffer, variable_pointers, protected_memory, sampler_ycbcr_conversion,
+    shader_draw_parameters,
+]);
+
+feature_fields!(vulkan_12_fields, vk::PhysicalDeviceVulkan12Features, [
+    sampler_mirror_clamp_to_edge, draw_indirect_count, storage_buffer8_bit_access,
+    uniform_and_storage_buffer8_bit_access, storage_push_constant8, shader_buffer_int64_atomics,
+    shader_shared_int64_atomics, shader_float16, shader_int8, descriptor_indexing,
+    shader_input_attachment_array_dynamic_indexing,
+    shader_uniform_texel_buffer_array_dynamic_indexing,
+    shader_storage_texel_buffer_array_dynamic_indexing,
+    shader_uniform_buffer_array_non_uniform_indexing,
+    shader_sampled_image_array_non_uniform_indexing,
+    shader_storage_buffer_array_non_uniform_indexing,
+    shader_storage_image_array_non_uniform_indexing,
+    shader_input_attachment_array_non_uniform_indexing,
+    shader_uniform_texel_buffer_array_non_uniform_indexing,
+    shader_storage_texel_buffer_array_non_uniform_indexing,
+    descriptor_binding_uniform_buffer_update_after_bind,
+    descriptor_binding_sampled_image_update_after_bind,
+    descriptor_binding_storage_image_update_after_bind,
+    descriptor_binding_storage_buffer_update_after_bind,
+    descriptor_binding_uniform_texel_buffer_update_after_bind,
+    descriptor_binding_storage_texel_buffer_update_after_bind,
+    descriptor_binding_update_unused_while_pending, descriptor_binding_partially_bound,
+    descriptor_binding_variable_descriptor_count, runtime_descriptor_array, sampler_filter_minmax,
+    scalar_block_layout, imageless_framebuffer, uniform_buffer_standard_layout,
+    shader_subgroup_extended_types, separate_depth_stencil_layouts, host_query_reset,
+    timeline_semaphore, buffer_device_address, buffer_device_address_capture_replay,
+    buffer_device_address_multi_device, vulkan_memory_model, vulkan_memory_model_device_scope,
+    vulkan_memory_model_availability_visibility_chains, shader_output_viewport_index,
+    shader_output_layer, subgroup_broadcast_dynamic_id,
+]);
+
+feature_fields!(acceleration_structure_fields, vk::PhysicalDeviceAccelerationStructureFeaturesKHR, [
+    acceleration_structure, acceleration_structure_capture_replay,
+    acceleration_structure_indirect_build, acceleration_structure_host_commands,
+    descriptor_binding_acceleration_structure_update_after_bind,
+]);
+
+feature_fields!(ray_tracing_pipeline_fields, vk::PhysicalDeviceRayTracingPipelineFeaturesKHR, [
+    ray_tracing_pipeline, ray_tracing_pipeline_shader_group_handle_capture_replay,
+    ray_tracing_pipeline_shader_group_handle_capture_replay_mixed,
+    ray_tracing_pipeline_trace_rays_indirect, ray_traversal_primitive_culling,
+]);
+
+feature_fields!(dynamic_rendering_fields, vk::PhysicalDeviceDynamicRenderingFeatures, [dynamic_rendering]);
+
+/// Device features grouped by the struct they are queried and enabled with. Structs other than `core` are chained
+/// to `VkPhysicalDeviceFeatures2`, their `p_next` pointers are always null.
+#[derive(Clone, Copy, Default)]
+pub struct DeviceFeatures {
+    pub core: vk::PhysicalDeviceFeatures,
+    pub vulkan_11: vk::PhysicalDeviceVulkan11Features,
+    pub vulkan_12: vk::PhysicalDeviceVulkan12Features,
+    pub acceleration_structure: vk::PhysicalDeviceAccelerationStructureFeaturesKHR,
+    pub ray_tracing_pipeline: vk::PhysicalDeviceRayTracingPipelineFeaturesKHR,
+    pub dynamic_rendering: vk::PhysicalDeviceDynamicRenderingFeatures,
+}
+
+// The feature structs only carry null `p_next` pointers.
+unsafe impl Send for DeviceFeatures {}
+
+unsafe impl Sync for DeviceFeatures {}
+
+impl DeviceFeatures {
+    /// Queries the features supported by `physical_device`. Structs belonging to Vulkan 1.2 or to an extension are
+    /// only queried when the device supports them and are left empty otherwise.
+    pub fn supported(instance: &Instance,
+                     physical_device: vk::PhysicalDevice,
+                     available_extensions: &[&CStr]) -> Self {
+        let api_version = unsafe {
+            instance.vk_instance().get_physical_device_properties(physical_device)
+        }.api_version;
+        let supports_vulkan_12 = vk::api_version_major(api_version) > 1 || vk::api_version_minor(api_version) >= 2;
+
+        let mut features = DeviceFeatures::default();
+        let core = {
+            let mut features2 = vk::PhysicalDeviceFeatures2::builder();
+            if supports_vulkan_12 {
+                features2 = features2
+                    .push_next(&mut features.vulkan_11)
+                    .push_next(&mut features.vulkan_12);
+            }
+            if available_extensions.contains(&khr::AccelerationStructure::name()) {
+                features2 = features2.push_next(&mut features.acceleration_structure);
+            }
+            if available_extensions.contains(&khr::RayTracingPipeline::name()) {
+                features2 = features2.push_next(&mut features.ray_tracing_pipeline);
+            }
+            if available_extensions.contains(&khr::DynamicRendering::name()) {
+                features2 = features2.push_next(&mut features.dynamic_rendering);
+            }
+
+            unsafe {
+                instance.vk_instance().get_physical_device_features2(physical_device, &mut features2);
+            }
+            features2.features
+        };
+
+        features.core = core;
+        features.clear_chain();
+        features
+    }
+
+    pub fn union(&self, other: &DeviceFeatures) -> DeviceFeatures {
+        self.combine(other, |lhs, rhs| lhs || rhs).0
+    }
+
+    pub fn intersection(&self, other: &DeviceFeatures) -> DeviceFeatures {
+        self.combine(other, |lhs, rhs| lhs && rhs).0
+    }
+
+    /// Names of the features enabled in `self` but not in `available`, e.g. `vulkan_12.buffer_device_address`.
+    pub fn missing_from(&self, available: &DeviceFeatures) -> Vec<String> {
+        self.combine(available, |lhs, rhs| lhs && !rhs).1
+    }
+
+    /// Names of all enabled features, prefixed with the field of the struct they belong to.
+    pub fn names(&self) -> Vec<String> {
+        self.combine(self, |lhs, _| lhs).1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names().is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names().iter().any(|enabled| enabled == name)
+    }
+
+    /// Extensions that have to be enabled for the enabled extension features to be usable.
+    pub fn required_extensions(&self) -> Vec<&'static CStr> {
+        let uses = self.used_structs();
+        let mut extensions = vec![];
+        if uses("acceleration_structure") {
+            extensions.push(khr::AccelerationStructure::name());
+            extensions.push(khr::DeferredHostOperations::name());
+        }
+        if uses("ray_tracing_pipeline") {
+            extensions.push(khr::RayTracingPipeline::name());
+        }
+        if uses("dynamic_rendering") {
+            extensions.push(khr::DynamicRendering::name());
+        }
+        extensions
+    }
+
+    /// Chains the non empty feature structs to a `VkPhysicalDeviceFeatures2` for device creation.
+    pub(crate) fn features2(&mut self) -> vk::PhysicalDeviceFeatures2Builder<'_> {
+        let uses = self.used_structs();
+        let mut features2 = vk::PhysicalDeviceFeatures2::builder().features(self.core);
+        if uses("vulkan_11") {
+            features2 = features2.push_next(&mut self.vulkan_11);
+        }
+        if uses("vulkan_12") {
+            features2 = features2.push_next(&mut self.vulkan_12);
+        }
+        if uses("acceleration_structure") {
+            features2 = features2.push_next(&mut self.acceleration_structure);
+        }
+        if uses("ray_tracing_pipeline") {
+            features2 = features2.push_next(&mut self.ray_tracing_pipeline);
+        }
+        if uses("dynamic_rendering") {
+            features2 = features2.push_next(&mut self.dynamic_rendering);
+        }
+        features2
+    }
+
+    fn used_structs(&self) -> impl Fn(&str) -> bool {
+        let names = self.names();
+        move |prefix| names.iter().any(|name| name.split('.').next() == Some(prefix))
+    }
+
+    fn combine(&self, other: &DeviceFeatures, op: fn(bool, bool) -> bool) -> (DeviceFeatures, Vec<String>) {
+        let mut names = vec![];
+        let mut features = DeviceFeatures {
+            core: core_fields(&self.core, &other.core, op, "core", &mut names),
+            vulkan_11: vulkan_11_fields(&self.vulkan_11, &other.vulkan_11, op, "vulkan_11", &mut names),
+            vulkan_12: vulkan_12_fields(&self.vulkan_12, &other.vulkan_12, op, "vulkan_12", &mut names),
+            acceleration_structure: acceleration_structure_fields(
+                &self.acceleration_structure, &other.acceleration_structure, op, "acceleration_structure", &mut names),
+            ray_tracing_pipeline: ray_tracing_pipeline_fields(
+                &self.ray_tracing_pipeline, &other.ray_tracing_pipeline, op, "ray_tracing_pipeline", &mut names),
+            dynamic_rendering: dynamic_rendering_fields(
+                &self.dynamic_rendering, &other.dynamic_rendering, op, "dynamic_rendering", &mut names),
+        };
+        features.clear_chain();
+        (features, names)
+    }
+
+    fn clear_chain(&mut self) {
+        self.vulkan_11.p_next = ptr::null_mut();
+        self.vulkan_12.p_next = ptr::null_mut();
+        self.acceleration_structure.p_next = ptr::null_mut();
+        self.ray_tracing_pipeline.p_next = ptr::null_mut();
+        self.dynamic_rendering.p_next = ptr::null_mut();
+    }
+}
+
+/// Extensions and features a device has to support, and those that are enabled only when available.
+#[derive(Clone, Default)]
+pub struct DeviceRequirements {
+    required_extensions: Vec<&'static CStr>,
+    optional_extensions: Vec<&'static CStr>,
+    required_features: DeviceFeatures,
+    optional_features: DeviceFeatures,
+}
+
+impl DeviceRequirements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn require_extension(mut self, name: &'static CStr) -> Self {
+        if !self.required_extensions.contains(&name) {
+            self.required_extensions.push(name);
+        }
+        self
+    }
+
+    pub fn prefer_extension(mut self, name: &'static CStr) -> Self {
+        if !self.optional_extensions.contains(&name) {
+            self.optional_extensions.push(name);
+        }
+        self
+    }
+
+    /// Enables features in `f` that a device must support to be selected, e.g.
+    /// `.require_features(|features| features.core.sampler_anisotropy = vk::TRUE)`.
+    pub fn require_features<F: FnOnce(&mut DeviceFeatures)>(mut self, f: F) -> Self {
+        f(&mut self.required_features);
+        self
+    }
+
+    /// Enables features in `f` that are turned on only when the selected device supports them.
+    pub fn prefer_features<F: FnOnce(&mut DeviceFeatures)>(mut self, f: F) -> Self {
+        f(&mut self.optional_features);
+        self
+    }
+
+    /// Required extensions including those implied by the required extension features.
+    pub fn required_extensions(&self) -> Vec<&'static CStr> {
+        let mut extensions = self.required_extensions.clone();
+        for extension in self.required_features.required_extensions() {
+            if !extensions.contains(&extension) {
+                extensions.push(extension);
+            }
+        }
+        extensions
+    }
+
+    pub fn optional_extensions(&self) -> &[&'static CStr] {
+        &self.optional_extensions
+    }
+
+    pub fn required_features(&self) -> &DeviceFeatures {
+        &self.required_features
+    }
+
+    pub fn optional_features(&self) -> &DeviceFeatures {
+        &self.optional_features
+    }
+
+    /// Resolves the extensions and features to enable on a device supporting all required ones.
+    pub fn negotiate(&self,
+                     available_extensions: &[&CStr],
+                     available_features: &DeviceFeatures) -> (Vec<&'static CStr>, DeviceFeatures) {
+        let mut enabled_extensions = self.required_extensions();
+        for extension in self.optional_extensions.iter() {
+            if available_extensions.contains(extension) && !enabled_extensions.contains(extension) {
+                enabled_extensions.push(extension);
+            }
+        }
+
+        // Extension features are only reported as available when their extension is, so the extensions implied by
+        // the enabled optional features are always supported.
+        let enabled_features = self.required_features
+            .union(&self.optional_features.intersection(available_features));
+        for extension in enabled_features.required_extensions() {
+            if !enabled_extensions.contains(&extension) {
+                enabled_extensions.push(extension);
+            }
+        }
+
+        (enabled_extensions, enabled_features)
+    }
+}
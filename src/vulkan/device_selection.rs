@@ -0,0 +1,180 @@
+use std::env;
+use std::fmt;
+
+use ash::vk;
+
+use crate::vulkan::{VisionError, VisionResult};
+
+/// Environment variable consulted when no `DeviceSelector` is given through the API.
+///
+/// Accepts `index:<n>`, `vendor:<id>` (decimal or `0x` prefixed hex), `name:<substring>`,
+/// a bare number as index or any other string as name.
+pub const DEVICE_SELECTOR_ENV: &str = "VISION_DEVICE";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Case insensitive substring of the device name.
+    Name(String),
+    /// Index in the order returned by `vkEnumeratePhysicalDevices`.
+    Index(usize),
+    VendorId(u32),
+}
+
+impl DeviceSelector {
+    pub fn parse(value: &str) -> VisionResult<Self> {
+        let value = value.trim();
+        let invalid = || VisionError::InvalidDeviceSelector(value.to_string());
+
+        if let Some(index) = value.strip_prefix("index:") {
+            index.trim().parse().map(DeviceSelector::Index).map_err(|_| invalid())
+        } else if let Some(vendor) = value.strip_prefix("vendor:") {
+            let vendor = vendor.trim();
+            let vendor_id = match vendor.strip_prefix("0x").or_else(|| vendor.strip_prefix("0X")) {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => vendor.parse(),
+            };
+            vendor_id.map(DeviceSelector::VendorId).map_err(|_| invalid())
+        } else if let Some(name) = value.strip_prefix("name:") {
+            Ok(DeviceSelector::Name(name.trim().to_string()))
+        } else if let Ok(index) = value.parse() {
+            Ok(DeviceSelector::Index(index))
+        } else if value.is_empty() {
+            Err(invalid())
+        } else {
+            Ok(DeviceSelector::Name(value.to_string()))
+        }
+    }
+
+    /// Reads the selector from `VISION_DEVICE`, `None` if the variable is unset or empty.
+    pub fn from_env() -> VisionResult<Option<Self>> {
+        match env::var(DEVICE_SELECTOR_ENV) {
+            Ok(value) if !value.trim().is_empty() => DeviceSelector::parse(&value).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn matches(&self, index: usize, properties: &vk::PhysicalDeviceProperties, name: &str) -> bool {
+        match self {
+            DeviceSelector::Name(needle) => name.to_lowercase().contains(&needle.to_lowercase()),
+            DeviceSelector::Index(selected) => *selected == index,
+            DeviceSelector::VendorId(vendor_id) => *vendor_id == properties.vendor_id,
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::Name(name) => write!(f, "name:{}", name),
+            DeviceSelector::Index(index) => write!(f, "index:{}", index),
+            DeviceSelector::VendorId(vendor_id) => write!(f, "vendor:{:#06x}", vendor_id),
+        }
+    }
+}
+
+/// Fields are compared in declaration order, the device type dominates and memory breaks ties.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DeviceScore {
+    pub device_type_rank: u32,
+    pub device_local_memory: vk::DeviceSize,
+    pub supported_features: u32,
+}
+
+impl DeviceScore {
+    pub fn new(properties: &vk::PhysicalDeviceProperties,
+               memory_properties: &vk::PhysicalDeviceMemoryProperties,
+               features: &vk::PhysicalDeviceFeatures) -> Self {
+        let device_type_rank = match properties.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 1,
+            _ => 0,
+        };
+
+        let device_local_memory = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum();
+
+        let supported_features = [
+            features.sampler_anisotropy,
+            features.geometry_shader,
+            features.tessellation_shader,
+            features.multi_draw_indirect,
+            features.sample_rate_shading,
+            features.fill_mode_non_solid,
+            features.wide_lines,
+            features.texture_compression_bc,
+            features.shader_float64,
+            features.shader_int64,
+        ].iter()
+            .filter(|feature| **feature == vk::TRUE)
+            .count() as u32;
+
+        Self {
+            device_type_rank,
+            device_local_memory,
+            supported_features,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceRejection {
+    MissingExtensions(Vec<String>),
+    MissingFeatures(Vec<String>),
+    MissingGraphicsQueue,
+    MissingPresentQueue,
+    /// The device is suitable but does not match the requested `DeviceSelector`.
+    NotSelected(DeviceSelector),
+}
+
+impl fmt::Display for DeviceRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceRejection::MissingExtensions(extensions) => write!(f, "missing extensions {}", extensions.join(", ")),
+            DeviceRejection::MissingFeatures(features) => write!(f, "missing features {}", features.join(", ")),
+            DeviceRejection::MissingGraphicsQueue => write!(f, "no graphics queue family"),
+            DeviceRejection::MissingPresentQueue => write!(f, "no queue family can present to the surface"),
+            DeviceRejection::NotSelected(selector) => write!(f, "does not match selector {}", selector),
+        }
+    }
+}
+
+/// A physical device considered during selection, `rejection` is `None` for suitable devices.
+#[derive(Clone, Debug)]
+pub struct DeviceCandidate {
+    pub index: usize,
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub device_type: vk::PhysicalDeviceType,
+    pub score: DeviceScore,
+    /// Whether the device matches the `DeviceSelector`, always `true` without one.
+    pub selected: bool,
+    pub rejection: Option<DeviceRejection>,
+    pub(crate) physical_device: vk::PhysicalDevice,
+}
+
+impl DeviceCandidate {
+    pub fn is_suitable(&self) -> bool {
+        self.rejection.is_none()
+    }
+}
+
+impl fmt::Display for DeviceCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {} ({:?}, vendor {:#06x}, {} MiB)",
+               self.index,
+               self.name,
+               self.device_type,
+               self.vendor_id,
+               self.score.device_local_memory / (1024 * 1024))?;
+        match &self.rejection {
+            Some(rejection) => write!(f, ": rejected, {}", rejection),
+            None => write!(f, ": suitable"),
+        }
+    }
+}
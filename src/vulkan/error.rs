@@ -0,0 +1,198 @@
+use std::error::Error;
+use std::fmt;
+
+use ash::{LoadingError, vk};
+
+pub type VisionResult<T> = Result<T, VisionError>;
+
+#[derive(Debug)]
+pub enum VisionError {
+    /// The Vulkan library could not be loaded.
+    Loading(String),
+    /// A Vulkan call returned an error code.
+    Vulkan { call: &'static str, result: vk::Result },
+    NoSuitableDevice,
+    /// The device requested through a `DeviceSelector` does not exist or is not suitable.
+    SelectedDeviceUnavailable(String),
+    InvalidDeviceSelector(String),
+    NoSuitableMemoryType { type_bits: u32, properties: vk::MemoryPropertyFlags },
+    UnsupportedFormat { format: vk::Format, features: vk::FormatFeatureFlags },
+    NoSurfaceFormat,
+    /// None of the depth formats can be used as depth attachment.
+    NoDepthFormat,
+    /// The operation requires a surface but the context was created headless.
+    MissingSurface,
+    /// The surface has a zero sized extent, e.g. because the window is minimized.
+    ZeroSizedSurface,
+    /// An access of `len` elements at `offset` exceeds the `capacity` of a buffer.
+    OutOfBounds { offset: usize, len: usize, capacity: usize },
+    /// The size in bytes of `len` elements does not fit into `usize`.
+    BufferTooLarge { len: usize, element_size: usize },
+    MemoryNotHostVisible,
+    InvalidSubresource { mip_level: u32, array_layer: u32 },
+    /// Multisampled images cannot be copied to a buffer, they have to be resolved first.
+    MultisampledImage(vk::SampleCountFlags),
+    /// Texels of `format` cannot be split into elements of `element_size` bytes.
+    TexelSizeMismatch { format: vk::Format, element_size: usize },
+    /// The render pass does not own color and depth attachments as it was not created with `RenderPass::create`.
+    MissingRenderTargets,
+    /// Attachments passed to begin rendering do not match the rendering layout.
+    AttachmentMismatch(String),
+    /// The operation requires a device feature that is not enabled, e.g. `ray_tracing_pipeline`.
+    MissingFeature(&'static str),
+    /// A workgroup size has a dimension of 0.
+    InvalidWorkgroupSize([u32; 3]),
+    /// The operation is not available for the object, e.g. `ComputePipeline::run` with a layout from the shader.
+    UnsupportedOperation(&'static str),
+    /// The number of buffers passed to `ComputePipeline::run` differs from its storage buffer count.
+    StorageBufferCountMismatch { expected: u32, actual: usize },
+    /// An acceleration structure cannot be built or does not support the operation, e.g. an update without
+    /// `ALLOW_UPDATE`.
+    InvalidAccelerationStructure(String),
+    /// A subpass index is not below the number of subpasses of the render pass.
+    SubpassOutOfRange { subpass: u32, count: u32 },
+    /// An image index is not below the number of images of a swapchain.
+    ImageIndexOutOfRange { index: u32, count: u32 },
+    /// A raygen group index is not below the number of raygen groups of the pipeline.
+    RaygenIndexOutOfRange { index: u32, count: u32 },
+    /// Shader code is not valid SPIR-V or could not be read.
+    InvalidShader(String),
+    /// A pipeline or layout does not match the interface of its shaders.
+    ShaderMismatch(String),
+    /// Shader source could not be read or compiled, messages are prefixed with the file and line.
+    ShaderCompilation(String),
+    /// An image could not be encoded or written to disk.
+    ImageEncoding(String),
+}
+
+impl VisionError {
+    pub fn vulkan(call: &'static str, result: vk::Result) -> Self {
+        VisionError::Vulkan { call, result }
+    }
+
+    /// Returns the `vk::Result` if the error originates from a Vulkan call.
+    pub fn vk_result(&self) -> Option<vk::Result> {
+        match self {
+            VisionError::Vulkan { result, .. } => Some(*result),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VisionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VisionError::Loading(message) => write!(f, "Failed to load Vulkan library: {}", message),
+            VisionError::Vulkan { call, result } => write!(f, "{} failed with {}", call, result),
+            VisionError::NoSuitableDevice => write!(f, "Failed to find a suitable physical device"),
+            VisionError::SelectedDeviceUnavailable(reason) => write!(f, "Selected device is unavailable, {}", reason),
+            VisionError::InvalidDeviceSelector(value) => write!(f, "Invalid device selector '{}'", value),
+            VisionError::NoSuitableMemoryType { type_bits, properties } => write!(
+                f,
+                "Failed to find a memory type in {:#b} with properties {:?}",
+                type_bits,
+                properties
+            ),
+            VisionError::UnsupportedFormat { format, features } => write!(
+                f,
+                "Format {:?} does not support {:?}",
+                format,
+                features
+            ),
+            VisionError::NoSurfaceFormat => write!(f, "Surface does not report any format"),
+            VisionError::NoDepthFormat => write!(f, "Device does not support any depth attachment format"),
+            VisionError::MissingSurface => write!(f, "Operation requires a surface but the context is headless"),
+            VisionError::ZeroSizedSurface => write!(f, "Surface has a zero sized extent"),
+            VisionError::OutOfBounds { offset, len, capacity } => write!(
+                f,
+                "Access of {} elements at offset {} is out of bounds for a buffer of {} elements",
+                len,
+                offset,
+                capacity
+            ),
+            VisionError::BufferTooLarge { len, element_size } => write!(
+                f,
+                "Buffer of {} elements of {} bytes exceeds the addressable size",
+                len,
+                element_size
+            ),
+            VisionError::MemoryNotHostVisible => write!(f, "Memory is not host visible and cannot be mapped"),
+            VisionError::InvalidSubresource { mip_level, array_layer } => write!(
+                f,
+                "Image has no mip level {} in array layer {}",
+                mip_level,
+                array_layer
+            ),
+            VisionError::MultisampledImage(samples) => write!(
+                f,
+                "Image with {:?} samples cannot be copied to a buffer, resolve it first",
+                samples
+            ),
+            VisionError::TexelSizeMismatch { format, element_size } => write!(
+                f,
+                "Texels of format {:?} are not a multiple of {} bytes",
+                format,
+                element_size
+            ),
+            VisionError::MissingRenderTargets => write!(f, "Render pass does not own color and depth attachments"),
+            VisionError::AttachmentMismatch(message) => write!(f, "Attachments do not match the layout: {}", message),
+            VisionError::MissingFeature(feature) => write!(f, "Device feature {} is not enabled", feature),
+            VisionError::InvalidWorkgroupSize(size) => write!(f, "Workgroup size {:?} has a dimension of 0", size),
+            VisionError::UnsupportedOperation(operation) => write!(f, "Unsupported operation: {}", operation),
+            VisionError::StorageBufferCountMismatch { expected, actual } => write!(
+                f,
+                "Pipeline has {} storage buffers but {} buffers were passed",
+                expected,
+                actual
+            ),
+            VisionError::InvalidAccelerationStructure(message) => write!(f, "Invalid acceleration structure: {}", message),
+            VisionError::SubpassOutOfRange { subpass, count } => write!(
+                f,
+                "Subpass {} is out of range for a render pass with {} subpasses",
+                subpass,
+                count
+            ),
+            VisionError::ImageIndexOutOfRange { index, count } => write!(
+                f,
+                "Image {} is out of range for a swapchain with {} images",
+                index,
+                count
+            ),
+            VisionError::RaygenIndexOutOfRange { index, count } => write!(
+                f,
+                "Raygen group {} is out of range for a pipeline with {} raygen groups",
+                index,
+                count
+            ),
+            VisionError::InvalidShader(message) => write!(f, "Invalid shader: {}", message),
+            VisionError::ShaderMismatch(message) => write!(f, "Shader interface mismatch: {}", message),
+            VisionError::ShaderCompilation(message) => write!(f, "Shader compilation failed: {}", message),
+            VisionError::ImageEncoding(message) => write!(f, "Failed to save image: {}", message),
+        }
+    }
+}
+
+impl Error for VisionError {}
+
+impl From<LoadingError> for VisionError {
+    fn from(error: LoadingError) -> Self {
+        VisionError::Loading(error.to_string())
+    }
+}
+
+impl From<image::ImageError> for VisionError {
+    fn from(error: image::ImageError) -> Self {
+        VisionError::ImageEncoding(error.to_string())
+    }
+}
+
+/// Attaches the name of the failing call to a raw `vk::Result` error.
+pub(crate) trait VkResultExt<T> {
+    fn map_vk_err(self, call: &'static str) -> VisionResult<T>;
+}
+
+impl<T> VkResultExt<T> for Result<T, vk::Result> {
+    fn map_vk_err(self, call: &'static str) -> VisionResult<T> {
+        self.map_err(|result| VisionError::vulkan(call, result))
+    }
+}
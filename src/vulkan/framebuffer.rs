@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::{Context, VisionResult};
+use crate::vulkan::error::VkResultExt;
+
+/// Framebuffer over image views owned elsewhere, e.g. the views of a swapchain. The views have to outlive the
+/// framebuffer's use on the GPU.
+pub struct Framebuffer {
+    context: Arc<Context>,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+}
+
+impl Framebuffer {
+    pub fn new(context: Arc<Context>,
+               render_pass: vk::RenderPass,
+               attachments: &[vk::ImageView],
+               extent: vk::Extent2D) -> VisionResult<Self> {
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = unsafe {
+            context.device().vk_device().create_framebuffer(&framebuffer_info, None)
+        }.map_vk_err("vkCreateFramebuffer")?;
+
+        Ok(Self {
+            context,
+            framebuffer,
+            extent,
+        })
+    }
+
+    pub fn vk_framebuffer(&self) -> vk::Framebuffer {
+        self.framebuffer
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device().vk_device().destroy_framebuffer(self.framebuffer, None);
+        }
+    }
+}
@@ -1,44 +1,50 @@
+use std::mem::size_of;
 use std::sync::Arc;
 
-use ash::version::{DeviceV1_0, InstanceV1_0};
 use ash::vk;
+use bytemuck::Pod;
 
-use crate::vulkan::buffer::Buffer;
-use crate::vulkan::Context;
+use crate::vulkan::{Buffer, Context, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::memory::Allocation;
 
 pub struct Image {
     context: Arc<Context>,
     image: vk::Image,
-    memory: Option<vk::DeviceMemory>,
+    allocation: Option<Allocation>,
     extent: vk::Extent3D,
     format: vk::Format,
     mip_levels: u32,
     layers: u32,
+    sample_count: vk::SampleCountFlags,
     managed: bool,
 }
 
 impl Image {
+    #[allow(clippy::too_many_arguments)]
     fn new(context: Arc<Context>,
            image: vk::Image,
-           memory: Option<vk::DeviceMemory>,
+           allocation: Option<Allocation>,
            extent: vk::Extent3D,
            format: vk::Format,
            mip_levels: u32,
            layers: u32,
+           sample_count: vk::SampleCountFlags,
            managed: bool) -> Self {
         Self {
             context,
             image,
-            memory,
+            allocation,
             extent,
             format,
             mip_levels,
             layers,
+            sample_count,
             managed,
         }
     }
 
-    pub fn create(context: Arc<Context>, parameters: ImageParameters) -> Self {
+    pub fn create(context: Arc<Context>, parameters: ImageParameters) -> VisionResult<Self> {
         let extent = vk::Extent3D {
             width: parameters.extent.width,
             height: parameters.extent.height,
@@ -60,31 +66,36 @@ impl Image {
 
         let device = context.device().vk_device();
         let image = unsafe {
-            device.create_image(&image_info, None).expect("Failed to create image")
+            device.create_image(&image_info, None).map_vk_err("vkCreateImage")?
         };
-        let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
-        let mem_type_index = context.find_memory_type_index(mem_requirements, parameters.memory_properties);
-
-        let alloc_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(mem_requirements.size)
-            .memory_type_index(mem_type_index);
-        let memory = unsafe {
-            let mem = device.allocate_memory(&alloc_info, None)
-                .expect("Failed to allocate image memory");
-            device.bind_image_memory(image, mem, 0).expect("Failed to bind image memory");
-            mem
+        let allocation = context.allocator()
+            .allocate_for_image(image, parameters.memory_properties, parameters.tiling);
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe { device.destroy_image(image, None) };
+                return Err(error);
+            }
         };
 
-        Image::new(
-            context,
+        let (memory, memory_offset) = (allocation.memory(), allocation.offset());
+        let image = Image::new(
+            Arc::clone(&context),
             image,
-            Some(memory),
+            Some(allocation),
             extent,
             parameters.format,
             parameters.mip_levels,
             parameters.layers,
+            parameters.sample_count,
             false,
-        )
+        );
+
+        unsafe {
+            device.bind_image_memory(image.image, memory, memory_offset).map_vk_err("vkBindImageMemory")?
+        };
+
+        Ok(image)
     }
 
     pub fn create_swapchain_image(context: Arc<Context>, image: vk::Image, format: vk::SurfaceFormatKHR, extent: vk::Extent2D) -> Self {
@@ -100,15 +111,16 @@ impl Image {
             format.format,
             1,
             1,
+            vk::SampleCountFlags::TYPE_1,
             true,
         )
     }
 
 
-    pub fn transition_image_layout(&self, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) {
+    pub fn transition_image_layout(&self, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) -> VisionResult<()> {
         self.context.execute_transient(|buffer| {
             self.cmd_transition_image_layout(buffer, old_layout, new_layout)
-        });
+        })
     }
 
     pub fn cmd_transition_image_layout(&self,
@@ -175,11 +187,7 @@ impl Image {
             };
 
         let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL {
-            let mut mask = vk::ImageAspectFlags::DEPTH;
-            if has_stencil_component(self.format) {
-                mask |= vk::ImageAspectFlags::STENCIL;
-            }
-            mask
+            self.aspect_mask()
         } else {
             vk::ImageAspectFlags::COLOR
         };
@@ -215,16 +223,16 @@ impl Image {
         };
     }
 
-    pub fn copy_buffer(&self, buffer: &Buffer, extent: vk::Extent2D) {
+    pub fn copy_buffer<T: Pod>(&self, buffer: &Buffer<T>, extent: vk::Extent2D) -> VisionResult<()> {
         self.context.execute_transient(|command_buffer| {
             self.cmd_copy_buffer(command_buffer, buffer, extent)
         })
     }
 
-    pub fn cmd_copy_buffer(
+    pub fn cmd_copy_buffer<T: Pod>(
         &self,
         command_buffer: vk::CommandBuffer,
-        buffer: &Buffer,
+        buffer: &Buffer<T>,
         extent: vk::Extent2D,
     ) {
         let region = vk::BufferImageCopy::builder()
@@ -248,7 +256,7 @@ impl Image {
         unsafe {
             self.context.device().vk_device().cmd_copy_buffer_to_image(
                 command_buffer,
-                buffer.buffer,
+                buffer.vk_buffer(),
                 self.image,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 &regions,
@@ -256,6 +264,129 @@ impl Image {
         }
     }
 
+    /// Copies `mip_level` of `array_layer` into host memory. `layout` is the current layout of the image, it is
+    /// restored afterwards. Only the depth aspect of depth stencil formats is read, multisampled images have to be
+    /// resolved first.
+    pub fn readback<T: Pod>(&self, layout: vk::ImageLayout, mip_level: u32, array_layer: u32) -> VisionResult<Vec<T>> {
+        if self.sample_count != vk::SampleCountFlags::TYPE_1 {
+            return Err(VisionError::MultisampledImage(self.sample_count));
+        }
+        let extent = self.mip_extent(mip_level, array_layer)?;
+        let texel_size = texel_size(self.format).ok_or(VisionError::UnsupportedFormat {
+            format: self.format,
+            features: vk::FormatFeatureFlags::TRANSFER_SRC,
+        })?;
+        if texel_size % size_of::<T>() != 0 {
+            return Err(VisionError::TexelSizeMismatch {
+                format: self.format,
+                element_size: size_of::<T>(),
+            });
+        }
+
+        let len = image_byte_size(extent, texel_size)? / size_of::<T>();
+        let buffer = Buffer::staging(Arc::clone(&self.context), len)?;
+        self.context.execute_transient(|command_buffer| {
+            self.cmd_copy_to_buffer(command_buffer, &buffer, layout, mip_level, array_layer);
+            self.context.cmd_memory_barrier(command_buffer,
+                                            vk::PipelineStageFlags::TRANSFER,
+                                            vk::AccessFlags::TRANSFER_WRITE,
+                                            vk::PipelineStageFlags::HOST,
+                                            vk::AccessFlags::HOST_READ);
+        })?;
+        buffer.read(0, len)
+    }
+
+    /// Copies `mip_level` of `array_layer` tightly packed to the start of `buffer`, transitioning the subresource
+    /// from `layout` to `TRANSFER_SRC_OPTIMAL` and back.
+    pub fn cmd_copy_to_buffer<T: Pod>(&self,
+                                      command_buffer: vk::CommandBuffer,
+                                      buffer: &Buffer<T>,
+                                      layout: vk::ImageLayout,
+                                      mip_level: u32,
+                                      array_layer: u32) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: self.aspect_mask(),
+            base_mip_level: mip_level,
+            level_count: 1,
+            base_array_layer: array_layer,
+            layer_count: 1,
+        };
+        let copy_aspect = if self.aspect_mask().contains(vk::ImageAspectFlags::DEPTH) {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        };
+
+        self.cmd_transition_subresource_layout(command_buffer, subresource_range, layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: copy_aspect,
+                mip_level,
+                base_array_layer: array_layer,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(Self::mip_level_extent(self.extent, mip_level))
+            .build();
+        let regions = [region];
+        unsafe {
+            self.context.device().vk_device().cmd_copy_image_to_buffer(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.vk_buffer(),
+                &regions,
+            )
+        }
+
+        // Contents of an undefined image are meaningless, it is left in the transfer layout.
+        if layout != vk::ImageLayout::UNDEFINED && layout != vk::ImageLayout::TRANSFER_SRC_OPTIMAL {
+            self.cmd_transition_subresource_layout(command_buffer, subresource_range, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, layout);
+        }
+    }
+
+    /// Transitions a range of mip levels and layers with access masks and stages derived from the layouts.
+    pub fn cmd_transition_subresource_layout(&self,
+                                             command_buffer: vk::CommandBuffer,
+                                             subresource_range: vk::ImageSubresourceRange,
+                                             old_layout: vk::ImageLayout,
+                                             new_layout: vk::ImageLayout) {
+        if old_layout == new_layout {
+            return;
+        }
+
+        let (src_access_mask, src_stage) = layout_access(old_layout);
+        let (dst_access_mask, dst_stage) = layout_access(new_layout);
+
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource_range)
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .build();
+        let barriers = [barrier];
+
+        unsafe {
+            self.context.device().vk_device().cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            )
+        };
+    }
+
     pub fn cmd_copy(
         &self,
         command_buffer: vk::CommandBuffer,
@@ -282,44 +413,16 @@ impl Image {
         };
     }
 
-    pub fn generate_mipmaps(&self, extent: vk::Extent2D) {
-        let format_properties = unsafe {
-            self.context
-                .instance()
-                .vk_instance()
-                .get_physical_device_format_properties(self.context.device().physical_device().vk_physical_device(), self.format)
-        };
-        if !format_properties
-            .optimal_tiling_features
-            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
-        {
-            panic!(
-                "Linear blitting is not supported for format {:?}.",
-                self.format
-            )
-        }
+    pub fn generate_mipmaps(&self, extent: vk::Extent2D) -> VisionResult<()> {
+        self.check_linear_blit_support()?;
 
         self.context.execute_transient(|buffer| {
-            self.cmd_generate_mipmaps(buffer, extent);
-        });
+            self.cmd_generate_mipmaps(buffer, extent)
+        })?
     }
 
-    pub fn cmd_generate_mipmaps(&self, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
-        let format_properties = unsafe {
-            self.context
-                .instance()
-                .vk_instance()
-                .get_physical_device_format_properties(self.context.device().physical_device().vk_physical_device(), self.format)
-        };
-        if !format_properties
-            .optimal_tiling_features
-            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
-        {
-            panic!(
-                "Linear blitting is not supported for format {:?}.",
-                self.format
-            )
-        }
+    pub fn cmd_generate_mipmaps(&self, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) -> VisionResult<()> {
+        self.check_linear_blit_support()?;
 
         let mut barrier = vk::ImageMemoryBarrier::builder()
             .image(self.image)
@@ -451,11 +554,27 @@ impl Image {
                 &barriers,
             )
         };
+
+        Ok(())
+    }
+
+    fn check_linear_blit_support(&self) -> VisionResult<()> {
+        let format_properties = self.context.format_properties(self.format);
+        if !format_properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+        {
+            return Err(VisionError::UnsupportedFormat {
+                format: self.format,
+                features: vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+            });
+        }
+        Ok(())
     }
 
     pub fn create_view(&self,
                        view_type: vk::ImageViewType,
-                       aspect_mask: vk::ImageAspectFlags) -> vk::ImageView {
+                       aspect_mask: vk::ImageAspectFlags) -> VisionResult<vk::ImageView> {
         let create_info = vk::ImageViewCreateInfo::builder()
             .image(self.image)
             .view_type(view_type)
@@ -471,13 +590,57 @@ impl Image {
         unsafe {
             self.context.device().vk_device()
                 .create_image_view(&create_info, None)
-                .expect("Failed to create image view")
+                .map_vk_err("vkCreateImageView")
         }
     }
 
+    pub(crate) fn context(&self) -> &Arc<Context> {
+        &self.context
+    }
+
     pub fn vk_image(&self) -> vk::Image {
         self.image
     }
+
+    pub fn extent(&self) -> vk::Extent3D {
+        self.extent
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+
+    pub fn sample_count(&self) -> vk::SampleCountFlags {
+        self.sample_count
+    }
+
+    /// Extent of `mip_level`, fails if the image has no such mip level or array layer.
+    pub fn mip_extent(&self, mip_level: u32, array_layer: u32) -> VisionResult<vk::Extent3D> {
+        if mip_level >= self.mip_levels || array_layer >= self.layers {
+            return Err(VisionError::InvalidSubresource { mip_level, array_layer });
+        }
+        Ok(Self::mip_level_extent(self.extent, mip_level))
+    }
+
+    fn mip_level_extent(extent: vk::Extent3D, mip_level: u32) -> vk::Extent3D {
+        vk::Extent3D {
+            width: (extent.width >> mip_level).max(1),
+            height: (extent.height >> mip_level).max(1),
+            depth: (extent.depth >> mip_level).max(1),
+        }
+    }
+
+    fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        format_aspect_mask(self.format)
+    }
 }
 
 
@@ -487,15 +650,86 @@ impl Drop for Image {
             if !self.managed {
                 self.context.device().vk_device().destroy_image(self.image, None);
             }
-            if let Some(memory) = self.memory {
-                self.context.device().vk_device().free_memory(memory, None);
-            }
         }
+        if let Some(allocation) = self.allocation.take() {
+            self.context.allocator().free(allocation);
+        }
+    }
+}
+
+/// Aspects of images of `format`, `COLOR` for all formats that are not depth or stencil formats.
+pub(crate) fn format_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => vk::ImageAspectFlags::DEPTH,
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        format if has_stencil_component(format) => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::COLOR,
     }
 }
 
-fn has_stencil_component(format: vk::Format) -> bool {
-    format == vk::Format::D32_SFLOAT_S8_UINT || format == vk::Format::D24_UNORM_S8_UINT
+/// Whether `format` has a stencil aspect, including the stencil only `S8_UINT`.
+pub(crate) fn has_stencil_component(format: vk::Format) -> bool {
+    matches!(format,
+             vk::Format::D16_UNORM_S8_UINT
+             | vk::Format::D24_UNORM_S8_UINT
+             | vk::Format::D32_SFLOAT_S8_UINT
+             | vk::Format::S8_UINT)
+}
+
+/// Size of a texel in a buffer copy, for depth stencil formats the size of the depth aspect.
+pub(crate) fn texel_size(format: vk::Format) -> Option<usize> {
+    let size = match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SNORM | vk::Format::R8_UINT | vk::Format::R8_SINT
+        | vk::Format::R8_SRGB => 1,
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SNORM | vk::Format::R8G8_UINT | vk::Format::R8G8_SINT
+        | vk::Format::R16_UNORM | vk::Format::R16_SFLOAT | vk::Format::R16_UINT | vk::Format::R16_SINT
+        | vk::Format::D16_UNORM => 2,
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SNORM | vk::Format::R8G8B8A8_UINT
+        | vk::Format::R8G8B8A8_SINT | vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::A2R10G10B10_UNORM_PACK32 | vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::R16G16_UNORM | vk::Format::R16G16_SFLOAT | vk::Format::R32_SFLOAT | vk::Format::R32_UINT
+        | vk::Format::R32_SINT | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT
+        | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT => 4,
+        vk::Format::R16G16B16A16_UNORM | vk::Format::R16G16B16A16_SFLOAT | vk::Format::R16G16B16A16_UINT
+        | vk::Format::R32G32_SFLOAT | vk::Format::R32G32_UINT | vk::Format::R32G32_SINT => 8,
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32_UINT | vk::Format::R32G32B32_SINT => 12,
+        vk::Format::R32G32B32A32_SFLOAT | vk::Format::R32G32B32A32_UINT | vk::Format::R32G32B32A32_SINT => 16,
+        _ => return None,
+    };
+    Some(size)
+}
+
+/// Bytes of `extent` tightly packed with `texel_size` bytes per texel, fails if it does not fit into memory.
+pub(crate) fn image_byte_size(extent: vk::Extent3D, texel_size: usize) -> VisionResult<usize> {
+    let texels = (extent.width as usize)
+        .checked_mul(extent.height as usize)
+        .and_then(|texels| texels.checked_mul(extent.depth as usize));
+    texels.and_then(|texels| texels.checked_mul(texel_size))
+        .ok_or(VisionError::BufferTooLarge { len: texels.unwrap_or(usize::MAX), element_size: texel_size })
+}
+
+/// Accesses and stages a layout is used with, as source or destination of a layout transition.
+fn layout_access(layout: vk::ImageLayout) -> (vk::AccessFlags, vk::PipelineStageFlags) {
+    match layout {
+        vk::ImageLayout::UNDEFINED => (vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (vk::AccessFlags::TRANSFER_READ, vk::PipelineStageFlags::TRANSFER),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::ALL_COMMANDS),
+        vk::ImageLayout::PRESENT_SRC_KHR => (vk::AccessFlags::empty(), vk::PipelineStageFlags::ALL_COMMANDS),
+        _ => (
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        ),
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -1,31 +1,34 @@
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 
 use ash::{Entry, Instance as VkInstance, vk};
-use ash::version::{EntryV1_0, InstanceV1_0};
 use winit::window::Window;
 
+use crate::vulkan::{Surface, VisionResult};
+use crate::vulkan::error::VkResultExt;
 use crate::vulkan::debug::{DebugMessenger, ValidationInfo};
-use crate::vulkan::Surface;
 
 pub struct Instance {
     instance: VkInstance,
     debug_messenger: Option<DebugMessenger>,
+    enabled_extensions: Vec<&'static CStr>,
 }
 
 impl Instance {
-    pub fn new(entry: &Entry, window: &Window, validation_info: ValidationInfo) -> Self {
+    pub fn new(entry: &Entry, window: Option<&Window>, validation_info: ValidationInfo) -> VisionResult<Self> {
         let app_name = CString::new("Vision").unwrap();
         let engine_name = CString::new("Vision Engine").unwrap();
         let app_info = vk::ApplicationInfo::builder()
             .application_name(app_name.as_c_str())
-            .application_version(vk::make_version(0, 1, 0))
+            .application_version(vk::make_api_version(0, 0, 1, 0))
             .engine_name(engine_name.as_c_str())
-            .engine_version(vk::make_version(0, 1, 0))
-            .api_version(vk::make_version(1, 1, 0));
+            .engine_version(vk::make_api_version(0, 0, 1, 0))
+            .api_version(vk::make_api_version(0, 1, 2, 0));
 
-        let mut extension_names = Instance::required_extensions(window);
+        let enabled_extensions = Instance::optional_extensions(entry, window)?;
+        let mut extension_names = Instance::required_extensions(window)?;
+        extension_names.extend(enabled_extensions.iter().map(|name| name.as_ptr()));
 
-        if validation_info.is_enabled && validation_info.required_validation_layers.len() > 0 {
+        if validation_info.is_enabled && !validation_info.required_validation_layers.is_empty() {
             extension_names.append(&mut DebugMessenger::required_extension_names());
         }
 
@@ -38,28 +41,62 @@ impl Instance {
             .map(|layer_name| layer_name.as_ptr())
             .collect();
 
-        if validation_info.check_validation_layer_support(entry) {
+        if validation_info.check_validation_layer_support(entry)? {
             create_info = create_info.enabled_layer_names(&layer_name_pointers);
         }
 
         let instance = unsafe { entry.create_instance(&create_info, None) }
-            .expect("Failed to create instance!");
+            .map_vk_err("vkCreateInstance")?;
 
         let mut debug_messenger = None;
-        if validation_info.is_enabled && validation_info.required_validation_layers.len() > 0 {
-            debug_messenger = Some(DebugMessenger::new(entry, &instance));
+        if validation_info.is_enabled && !validation_info.required_validation_layers.is_empty() {
+            match DebugMessenger::new(entry, &instance) {
+                Ok(messenger) => debug_messenger = Some(messenger),
+                Err(error) => {
+                    unsafe { instance.destroy_instance(None); }
+                    return Err(error);
+                }
+            }
         }
 
-        Self {
+        Ok(Self {
             instance,
             debug_messenger,
-        }
+            enabled_extensions,
+        })
     }
 
-    fn required_extensions(window: &Window) -> Vec<*const i8> {
+    fn required_extensions(window: Option<&Window>) -> VisionResult<Vec<*const i8>> {
         let mut extensions: Vec<*const i8> = vec![];
-        extensions.append(&mut Surface::required_extension_names(window));
-        extensions
+        if let Some(window) = window {
+            extensions.append(&mut Surface::required_extension_names(window)?);
+        }
+        Ok(extensions)
+    }
+
+    /// Extensions enabled when available, `VK_EXT_swapchain_colorspace` for HDR color spaces if there is a window.
+    fn optional_extensions(entry: &Entry, window: Option<&Window>) -> VisionResult<Vec<&'static CStr>> {
+        if window.is_none() {
+            return Ok(vec![]);
+        }
+
+        let available = entry.enumerate_instance_extension_properties(None)
+            .map_vk_err("vkEnumerateInstanceExtensionProperties")?;
+        let optional = [vk::ExtSwapchainColorspaceFn::name()];
+        Ok(optional.iter()
+            .copied()
+            .filter(|name| {
+                available.iter().any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == *name)
+            })
+            .collect())
+    }
+
+    pub fn enabled_extensions(&self) -> &[&'static CStr] {
+        &self.enabled_extensions
+    }
+
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.enabled_extensions.contains(&name)
     }
 
     pub fn vk_instance(&self) -> &VkInstance {
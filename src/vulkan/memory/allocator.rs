@@ -0,0 +1,593 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use ash::Device as VkDevice;
+use ash::vk;
+
+use crate::vulkan::{Device, Instance, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::memory::buddy::BuddyAllocator;
+use crate::vulkan::memory::linear::LinearAllocator;
+use crate::vulkan::util::align_up;
+
+const DEFAULT_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AllocationStrategy {
+    /// General purpose, freed memory is immediately reusable.
+    #[default]
+    Buddy,
+    /// Cheap bump allocation for short lived resources, a block is reused once all of its allocations are freed.
+    Linear,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct AllocationParameters {
+    pub requirements: vk::MemoryRequirements,
+    pub memory_properties: vk::MemoryPropertyFlags,
+    pub strategy: AllocationStrategy,
+    /// Buffers and linearly tiled images are placed in other blocks than optimally tiled images, which keeps them
+    /// apart as demanded by `bufferImageGranularity`.
+    pub linear_resource: bool,
+    /// Allocates a separate `VkDeviceMemory` instead of sub-allocating a block.
+    pub dedicated: bool,
+}
+
+struct MappedPointer(*mut u8);
+
+// The pointer refers to persistently mapped device memory that is only accessed through the owning allocation.
+unsafe impl Send for MappedPointer {}
+
+unsafe impl Sync for MappedPointer {}
+
+/// A range of device memory owned by a resource, it has to be returned with `Allocator::free`.
+pub struct Allocation {
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    reserved_size: vk::DeviceSize,
+    memory_type_index: u32,
+    memory_properties: vk::MemoryPropertyFlags,
+    mapped_pointer: Option<MappedPointer>,
+    kind: AllocationKind,
+}
+
+enum AllocationKind {
+    Block { key: PoolKey, block_index: usize },
+    Dedicated,
+}
+
+impl Allocation {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    /// Offset of the allocation inside `memory`, resources have to be bound at this offset.
+    pub fn offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    pub fn memory_type_index(&self) -> u32 {
+        self.memory_type_index
+    }
+
+    pub fn memory_properties(&self) -> vk::MemoryPropertyFlags {
+        self.memory_properties
+    }
+
+    /// Pointer to the start of the allocation, host visible memory is mapped for the whole lifetime of an allocation.
+    pub fn mapped_ptr(&self) -> Option<*mut u8> {
+        self.mapped_pointer.as_ref().map(|pointer| pointer.0)
+    }
+
+    pub fn is_dedicated(&self) -> bool {
+        matches!(self.kind, AllocationKind::Dedicated)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct PoolKey {
+    memory_type_index: u32,
+    strategy: AllocationStrategy,
+    linear_resource: bool,
+}
+
+enum SubAllocator {
+    Buddy(BuddyAllocator),
+    Linear(LinearAllocator),
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    mapped_pointer: Option<MappedPointer>,
+    sub_allocator: SubAllocator,
+    allocation_count: usize,
+}
+
+impl MemoryBlock {
+    fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<(vk::DeviceSize, vk::DeviceSize)> {
+        let chunk = match &mut self.sub_allocator {
+            SubAllocator::Buddy(allocator) => allocator.allocate(size, alignment),
+            SubAllocator::Linear(allocator) => allocator.allocate(size, alignment),
+        };
+        if chunk.is_some() {
+            self.allocation_count += 1;
+        }
+        chunk
+    }
+
+    fn free(&mut self, offset: vk::DeviceSize, chunk_size: vk::DeviceSize) {
+        match &mut self.sub_allocator {
+            SubAllocator::Buddy(allocator) => allocator.free(offset, chunk_size),
+            SubAllocator::Linear(allocator) => allocator.free(offset, chunk_size),
+        }
+        self.allocation_count -= 1;
+    }
+
+    fn used(&self) -> vk::DeviceSize {
+        match &self.sub_allocator {
+            SubAllocator::Buddy(allocator) => allocator.used(),
+            SubAllocator::Linear(allocator) => allocator.used(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct AllocatorState {
+    pools: HashMap<PoolKey, Vec<Option<MemoryBlock>>>,
+    dedicated: BTreeMap<u32, MemoryStats>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub block_count: usize,
+    pub dedicated_allocation_count: usize,
+    pub allocation_count: usize,
+    /// Bytes allocated from the driver.
+    pub allocated_bytes: vk::DeviceSize,
+    /// Bytes handed out to resources, including padding for alignment.
+    pub used_bytes: vk::DeviceSize,
+}
+
+impl MemoryStats {
+    fn add(&mut self, other: &MemoryStats) {
+        self.block_count += other.block_count;
+        self.dedicated_allocation_count += other.dedicated_allocation_count;
+        self.allocation_count += other.allocation_count;
+        self.allocated_bytes += other.allocated_bytes;
+        self.used_bytes += other.used_bytes;
+    }
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        write!(f, "{} allocations in {} blocks and {} dedicated allocations, {:.1} MiB used of {:.1} MiB",
+               self.allocation_count,
+               self.block_count,
+               self.dedicated_allocation_count,
+               self.used_bytes as f64 / MIB,
+               self.allocated_bytes as f64 / MIB)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AllocatorStats {
+    pub total: MemoryStats,
+    pub memory_types: BTreeMap<u32, MemoryStats>,
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total: {}", self.total)?;
+        for (memory_type_index, stats) in self.memory_types.iter() {
+            writeln!(f, "  Memory type {}: {}", memory_type_index, stats)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sub-allocates resources from large blocks of device memory, one pool of blocks per memory type and strategy.
+/// Large resources and those the driver prefers to be dedicated get their own `VkDeviceMemory`.
+pub struct Allocator {
+    device: VkDevice,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    non_coherent_atom_size: vk::DeviceSize,
+    // Memory is allocated with `DEVICE_ADDRESS` so buffers can be accessed through their device address.
+    device_address: bool,
+    state: Mutex<AllocatorState>,
+}
+
+impl Allocator {
+    pub fn new(instance: &Instance, device: &Device) -> Self {
+        let physical_device = device.physical_device();
+        let memory_properties = unsafe {
+            instance.vk_instance().get_physical_device_memory_properties(physical_device.vk_physical_device())
+        };
+
+        Self {
+            device: device.vk_device().clone(),
+            memory_properties,
+            non_coherent_atom_size: physical_device.properties().limits.non_coherent_atom_size,
+            device_address: device.enabled_features().vulkan_12.buffer_device_address == vk::TRUE,
+            state: Mutex::new(AllocatorState::default()),
+        }
+    }
+
+    pub fn find_memory_type_index(&self,
+                                  requirements: vk::MemoryRequirements,
+                                  required_properties: vk::MemoryPropertyFlags) -> VisionResult<u32> {
+        for i in 0..self.memory_properties.memory_type_count {
+            if requirements.memory_type_bits & (1 << i) != 0
+                && self.memory_properties.memory_types[i as usize]
+                .property_flags
+                .contains(required_properties)
+            {
+                return Ok(i);
+            }
+        }
+        Err(VisionError::NoSuitableMemoryType {
+            type_bits: requirements.memory_type_bits,
+            properties: required_properties,
+        })
+    }
+
+    pub fn allocate(&self, parameters: AllocationParameters) -> VisionResult<Allocation> {
+        self.allocate_with_dedicated_info(parameters, None)
+    }
+
+    /// Allocates memory for `buffer` without binding it.
+    pub fn allocate_for_buffer(&self,
+                               buffer: vk::Buffer,
+                               memory_properties: vk::MemoryPropertyFlags,
+                               strategy: AllocationStrategy) -> VisionResult<Allocation> {
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let requirements = {
+            let info = vk::BufferMemoryRequirementsInfo2::builder().buffer(buffer);
+            let mut requirements = vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+            unsafe { self.device.get_buffer_memory_requirements2(&info, &mut requirements) };
+            requirements.memory_requirements
+        };
+
+        let dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().buffer(buffer).build();
+        self.allocate_with_dedicated_info(
+            AllocationParameters {
+                requirements,
+                memory_properties,
+                strategy,
+                linear_resource: true,
+                dedicated: dedicated_requirements.prefers_dedicated_allocation == vk::TRUE,
+            },
+            Some(dedicated_info),
+        )
+    }
+
+    /// Allocates memory for `image` without binding it.
+    pub fn allocate_for_image(&self,
+                              image: vk::Image,
+                              memory_properties: vk::MemoryPropertyFlags,
+                              tiling: vk::ImageTiling) -> VisionResult<Allocation> {
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let requirements = {
+            let info = vk::ImageMemoryRequirementsInfo2::builder().image(image);
+            let mut requirements = vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+            unsafe { self.device.get_image_memory_requirements2(&info, &mut requirements) };
+            requirements.memory_requirements
+        };
+
+        let dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(image).build();
+        self.allocate_with_dedicated_info(
+            AllocationParameters {
+                requirements,
+                memory_properties,
+                strategy: AllocationStrategy::Buddy,
+                linear_resource: tiling == vk::ImageTiling::LINEAR,
+                dedicated: dedicated_requirements.prefers_dedicated_allocation == vk::TRUE,
+            },
+            Some(dedicated_info),
+        )
+    }
+
+    pub fn free(&self, allocation: Allocation) {
+        let mut state = self.lock_state();
+        match allocation.kind {
+            AllocationKind::Dedicated => {
+                unsafe { self.device.free_memory(allocation.memory, None) };
+                if let Some(stats) = state.dedicated.get_mut(&allocation.memory_type_index) {
+                    stats.dedicated_allocation_count -= 1;
+                    stats.allocation_count -= 1;
+                    stats.allocated_bytes -= allocation.reserved_size;
+                    stats.used_bytes -= allocation.reserved_size;
+                }
+            }
+            AllocationKind::Block { key, block_index } => {
+                let blocks = match state.pools.get_mut(&key) {
+                    Some(blocks) => blocks,
+                    None => return,
+                };
+                let block_count = blocks.iter().flatten().count();
+                if let Some(block) = blocks[block_index].as_mut() {
+                    block.free(allocation.offset, allocation.reserved_size);
+                    // Keep the last block of a pool around to avoid reallocating it for the next resource.
+                    if block.allocation_count == 0 && block_count > 1 {
+                        unsafe { self.device.free_memory(block.memory, None) };
+                        blocks[block_index] = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Makes host writes to `size` bytes at `offset` of the allocation visible to the device, does nothing for
+    /// host coherent memory.
+    pub fn flush(&self, allocation: &Allocation, offset: vk::DeviceSize, size: vk::DeviceSize) -> VisionResult<()> {
+        if let Some(range) = self.non_coherent_range(allocation, offset, size) {
+            unsafe {
+                self.device.flush_mapped_memory_ranges(&[range])
+                    .map_vk_err("vkFlushMappedMemoryRanges")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes device writes to `size` bytes at `offset` of the allocation visible to the host, does nothing for
+    /// host coherent memory.
+    pub fn invalidate(&self, allocation: &Allocation, offset: vk::DeviceSize, size: vk::DeviceSize) -> VisionResult<()> {
+        if let Some(range) = self.non_coherent_range(allocation, offset, size) {
+            unsafe {
+                self.device.invalidate_mapped_memory_ranges(&[range])
+                    .map_vk_err("vkInvalidateMappedMemoryRanges")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let state = self.lock_state();
+        let mut memory_types = state.dedicated.clone();
+
+        for (key, blocks) in state.pools.iter() {
+            let stats = memory_types.entry(key.memory_type_index).or_default();
+            for block in blocks.iter().flatten() {
+                stats.block_count += 1;
+                stats.allocation_count += block.allocation_count;
+                stats.allocated_bytes += block.size;
+                stats.used_bytes += block.used();
+            }
+        }
+
+        let mut total = MemoryStats::default();
+        for stats in memory_types.values() {
+            total.add(stats);
+        }
+
+        AllocatorStats {
+            total,
+            memory_types,
+        }
+    }
+
+    fn allocate_with_dedicated_info(&self,
+                                    parameters: AllocationParameters,
+                                    dedicated_info: Option<vk::MemoryDedicatedAllocateInfo>) -> VisionResult<Allocation> {
+        let memory_type_index = self.find_memory_type_index(parameters.requirements, parameters.memory_properties)?;
+        let memory_properties = self.memory_properties.memory_types[memory_type_index as usize].property_flags;
+
+        // Non coherent chunks are padded to whole atoms so flushing one never touches its neighbours.
+        let (size, alignment) = if Self::is_non_coherent(memory_properties) {
+            let atom_size = self.non_coherent_atom_size;
+            (align_up(parameters.requirements.size, atom_size),
+             parameters.requirements.alignment.max(atom_size))
+        } else {
+            (parameters.requirements.size, parameters.requirements.alignment)
+        };
+
+        let block_size = self.block_size(memory_type_index);
+        if parameters.dedicated || size > block_size / 2 {
+            return self.allocate_dedicated(memory_type_index, memory_properties, parameters.requirements.size, dedicated_info);
+        }
+
+        let key = PoolKey {
+            memory_type_index,
+            strategy: parameters.strategy,
+            linear_resource: parameters.linear_resource,
+        };
+
+        let mut state = self.lock_state();
+        let chunk = state.pools.entry(key).or_default()
+            .iter_mut()
+            .enumerate()
+            .find_map(|(block_index, block)| {
+                let (offset, chunk_size) = block.as_mut()?.allocate(size, alignment)?;
+                Some((block_index, offset, chunk_size))
+            });
+
+        let (block_index, offset, chunk_size) = match chunk {
+            Some(chunk) => chunk,
+            None => {
+                // Other threads keep sub-allocating while the new block is allocated from the driver.
+                drop(state);
+                let mut block = self.create_block(memory_type_index, memory_properties, parameters.strategy, block_size)?;
+                let (offset, chunk_size) = match block.allocate(size, alignment) {
+                    Some(chunk) => chunk,
+                    None => {
+                        unsafe { self.device.free_memory(block.memory, None) };
+                        return Err(VisionError::vulkan("vkAllocateMemory", vk::Result::ERROR_OUT_OF_DEVICE_MEMORY));
+                    }
+                };
+
+                state = self.lock_state();
+                let blocks = state.pools.entry(key).or_default();
+                let block_index = match blocks.iter().position(Option::is_none) {
+                    Some(index) => {
+                        blocks[index] = Some(block);
+                        index
+                    }
+                    None => {
+                        blocks.push(Some(block));
+                        blocks.len() - 1
+                    }
+                };
+                (block_index, offset, chunk_size)
+            }
+        };
+
+        let block = state.pools[&key][block_index].as_ref().expect("allocated block exists");
+        Ok(Allocation {
+            memory: block.memory,
+            offset,
+            size: parameters.requirements.size,
+            reserved_size: chunk_size,
+            memory_type_index,
+            memory_properties,
+            mapped_pointer: block.mapped_pointer.as_ref()
+                .map(|pointer| MappedPointer(unsafe { pointer.0.add(offset as usize) })),
+            kind: AllocationKind::Block { key, block_index },
+        })
+    }
+
+    /// Dedicated memory has exactly the size of the resource as required by `VkMemoryDedicatedAllocateInfo`.
+    fn allocate_dedicated(&self,
+                          memory_type_index: u32,
+                          memory_properties: vk::MemoryPropertyFlags,
+                          size: vk::DeviceSize,
+                          dedicated_info: Option<vk::MemoryDedicatedAllocateInfo>) -> VisionResult<Allocation> {
+        let (memory, mapped_pointer) = self.allocate_memory(memory_type_index, memory_properties, size, dedicated_info)?;
+
+        let mut state = self.lock_state();
+        let stats = state.dedicated.entry(memory_type_index).or_default();
+        stats.dedicated_allocation_count += 1;
+        stats.allocation_count += 1;
+        stats.allocated_bytes += size;
+        stats.used_bytes += size;
+
+        Ok(Allocation {
+            memory,
+            offset: 0,
+            size,
+            reserved_size: size,
+            memory_type_index,
+            memory_properties,
+            mapped_pointer,
+            kind: AllocationKind::Dedicated,
+        })
+    }
+
+    fn create_block(&self,
+                    memory_type_index: u32,
+                    memory_properties: vk::MemoryPropertyFlags,
+                    strategy: AllocationStrategy,
+                    size: vk::DeviceSize) -> VisionResult<MemoryBlock> {
+        let (memory, mapped_pointer) = self.allocate_memory(memory_type_index, memory_properties, size, None)?;
+        let sub_allocator = match strategy {
+            AllocationStrategy::Buddy => SubAllocator::Buddy(BuddyAllocator::new(size)),
+            AllocationStrategy::Linear => SubAllocator::Linear(LinearAllocator::new(size)),
+        };
+
+        Ok(MemoryBlock {
+            memory,
+            size,
+            mapped_pointer,
+            sub_allocator,
+            allocation_count: 0,
+        })
+    }
+
+    /// Allocates device memory and maps it if it is host visible.
+    fn allocate_memory(&self,
+                       memory_type_index: u32,
+                       memory_properties: vk::MemoryPropertyFlags,
+                       size: vk::DeviceSize,
+                       dedicated_info: Option<vk::MemoryDedicatedAllocateInfo>) -> VisionResult<(vk::DeviceMemory, Option<MappedPointer>)> {
+        let mut dedicated_info = dedicated_info;
+        let mut alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+        if let Some(dedicated_info) = dedicated_info.as_mut() {
+            alloc_info = alloc_info.push_next(dedicated_info);
+        }
+        let mut flags_info = vk::MemoryAllocateFlagsInfo::builder().flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        if self.device_address {
+            alloc_info = alloc_info.push_next(&mut flags_info);
+        }
+
+        let memory = unsafe {
+            self.device.allocate_memory(&alloc_info, None).map_vk_err("vkAllocateMemory")?
+        };
+
+        if !memory_properties.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            return Ok((memory, None));
+        }
+
+        let pointer = unsafe {
+            self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+        }.map_vk_err("vkMapMemory");
+        match pointer {
+            Ok(pointer) => Ok((memory, Some(MappedPointer(pointer as *mut u8)))),
+            Err(error) => {
+                unsafe { self.device.free_memory(memory, None) };
+                Err(error)
+            }
+        }
+    }
+
+    /// Blocks are a power of two so the buddy allocator can manage them, small heaps get smaller blocks.
+    fn block_size(&self, memory_type_index: u32) -> vk::DeviceSize {
+        let heap_index = self.memory_properties.memory_types[memory_type_index as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap_index as usize].size;
+        let block_size = DEFAULT_BLOCK_SIZE.min(heap_size / 8);
+        BuddyAllocator::block_size(if block_size.is_power_of_two() {
+            block_size
+        } else {
+            block_size.next_power_of_two() / 2
+        })
+    }
+
+    fn non_coherent_range(&self,
+                          allocation: &Allocation,
+                          offset: vk::DeviceSize,
+                          size: vk::DeviceSize) -> Option<vk::MappedMemoryRange> {
+        if allocation.mapped_pointer.is_none() || !Self::is_non_coherent(allocation.memory_properties) || size == 0 {
+            return None;
+        }
+
+        let atom_size = self.non_coherent_atom_size;
+        let start = (allocation.offset + offset) / atom_size * atom_size;
+        let end = align_up(allocation.offset + offset + size, atom_size);
+        let size = match allocation.kind {
+            // Dedicated memory is not padded to whole atoms, the last one is flushed up to the end of the memory.
+            AllocationKind::Dedicated if end >= allocation.reserved_size => vk::WHOLE_SIZE,
+            _ => end.min(allocation.offset + allocation.reserved_size) - start,
+        };
+
+        Some(vk::MappedMemoryRange::builder()
+            .memory(allocation.memory)
+            .offset(start)
+            .size(size)
+            .build())
+    }
+
+    fn is_non_coherent(memory_properties: vk::MemoryPropertyFlags) -> bool {
+        memory_properties.contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+            && !memory_properties.contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, AllocatorState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for Allocator {
+    fn drop(&mut self) {
+        let state = self.lock_state();
+        for block in state.pools.values().flatten().flatten() {
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+}
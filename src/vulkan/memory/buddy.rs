@@ -0,0 +1,79 @@
+use std::collections::BTreeSet;
+
+use ash::vk::DeviceSize;
+
+/// Smallest chunk handed out, smaller requests are rounded up.
+const MIN_CHUNK_SIZE: DeviceSize = 256;
+
+/// Buddy allocator over a block whose size is a power of two multiple of `MIN_CHUNK_SIZE`. Chunks are powers of two
+/// and aligned to their size, so any power of two alignment up to the chunk size is satisfied.
+pub struct BuddyAllocator {
+    max_order: usize,
+    free_lists: Vec<BTreeSet<DeviceSize>>,
+    used: DeviceSize,
+}
+
+impl BuddyAllocator {
+    pub fn new(size: DeviceSize) -> Self {
+        let max_order = Self::order(size);
+        let mut free_lists = vec![BTreeSet::new(); max_order + 1];
+        free_lists[max_order].insert(0);
+
+        Self {
+            max_order,
+            free_lists,
+            used: 0,
+        }
+    }
+
+    /// Size a block needs to have for the buddy allocator to manage `size` bytes.
+    pub fn block_size(size: DeviceSize) -> DeviceSize {
+        size.max(MIN_CHUNK_SIZE).next_power_of_two()
+    }
+
+    /// Returns the offset and the size of the reserved chunk.
+    pub fn allocate(&mut self, size: DeviceSize, alignment: DeviceSize) -> Option<(DeviceSize, DeviceSize)> {
+        let chunk_size = Self::block_size(size.max(alignment));
+        let order = Self::order(chunk_size);
+        if order > self.max_order {
+            return None;
+        }
+
+        let mut current_order = (order..=self.max_order).find(|order| !self.free_lists[*order].is_empty())?;
+        let offset = self.free_lists[current_order].pop_first()?;
+
+        // Split the chunk until it has the requested order, the upper halves become free buddies.
+        while current_order > order {
+            current_order -= 1;
+            self.free_lists[current_order].insert(offset + (MIN_CHUNK_SIZE << current_order));
+        }
+
+        self.used += chunk_size;
+        Some((offset, chunk_size))
+    }
+
+    pub fn free(&mut self, offset: DeviceSize, chunk_size: DeviceSize) {
+        self.used -= chunk_size;
+
+        let mut offset = offset;
+        let mut order = Self::order(chunk_size);
+        // Merge with the buddy as long as it is free as well.
+        while order < self.max_order {
+            let buddy = offset ^ (MIN_CHUNK_SIZE << order);
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+            offset = offset.min(buddy);
+            order += 1;
+        }
+        self.free_lists[order].insert(offset);
+    }
+
+    pub fn used(&self) -> DeviceSize {
+        self.used
+    }
+
+    fn order(chunk_size: DeviceSize) -> usize {
+        (chunk_size / MIN_CHUNK_SIZE).trailing_zeros() as usize
+    }
+}
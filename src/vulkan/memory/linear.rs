@@ -0,0 +1,48 @@
+use ash::vk::DeviceSize;
+
+use crate::vulkan::util::align_up;
+
+/// Bump allocator, memory is only reclaimed once every allocation of the block has been freed. Suited for short
+/// lived resources such as staging buffers.
+pub struct LinearAllocator {
+    size: DeviceSize,
+    offset: DeviceSize,
+    allocation_count: usize,
+    used: DeviceSize,
+}
+
+impl LinearAllocator {
+    pub fn new(size: DeviceSize) -> Self {
+        Self {
+            size,
+            offset: 0,
+            allocation_count: 0,
+            used: 0,
+        }
+    }
+
+    /// Returns the offset and the size of the reserved chunk.
+    pub fn allocate(&mut self, size: DeviceSize, alignment: DeviceSize) -> Option<(DeviceSize, DeviceSize)> {
+        let offset = align_up(self.offset, alignment);
+        if offset + size > self.size {
+            return None;
+        }
+
+        self.offset = offset + size;
+        self.allocation_count += 1;
+        self.used += size;
+        Some((offset, size))
+    }
+
+    pub fn free(&mut self, _offset: DeviceSize, chunk_size: DeviceSize) {
+        self.allocation_count -= 1;
+        self.used -= chunk_size;
+        if self.allocation_count == 0 {
+            self.offset = 0;
+        }
+    }
+
+    pub fn used(&self) -> DeviceSize {
+        self.used
+    }
+}
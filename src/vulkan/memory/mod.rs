@@ -0,0 +1,5 @@
+pub use self::allocator::{Allocation, AllocationParameters, AllocationStrategy, Allocator, AllocatorStats, MemoryStats};
+
+mod allocator;
+mod buddy;
+mod linear;
@@ -1,25 +1,48 @@
+pub use self::acceleration_structure::{AccelerationStructure, AccelerationStructureInstance, TriangleGeometry};
+pub use self::buffer::{Buffer, IndexType, MappedSlice};
 pub use self::command_pool::CommandPool;
 pub use self::context::Context;
 pub use self::device::Device;
-pub use self::image::Image;
+pub use self::device_features::{DeviceFeatures, DeviceRequirements};
+pub use self::device_selection::{DEVICE_SELECTOR_ENV, DeviceCandidate, DeviceRejection, DeviceScore, DeviceSelector};
+pub use self::error::{VisionError, VisionResult};
+pub use self::framebuffer::Framebuffer;
+pub use self::image::{Image, ImageParameters};
 pub use self::instance::Instance;
-pub use self::physical_device::PhysicalDevice;
+pub use self::physical_device::{PhysicalDevice, QueueFamilyIndices, QueueType};
+pub use self::render_pass::RenderPass;
+pub use self::render_pass_builder::{Attachment, RenderPassBuilder, Subpass};
+pub use self::rendering::{Rendering, RenderingAttachment, RenderingLayout};
+pub use self::screenshot::{PendingScreenshot, Screenshot, ScreenshotFormat};
+pub use self::shader::{DescriptorBinding, InterfaceVariable, ShaderCompiler, ShaderLanguage, ShaderModule, ShaderReflection, SpecializationConstant};
+pub use self::submission::Submission;
 pub use self::surface::Surface;
+pub use self::texture::Texture;
 
 mod instance;
 mod surface;
 mod physical_device;
 mod device;
+mod device_features;
+mod device_selection;
 mod shader;
+mod submission;
 mod context;
 mod render_pass;
+mod render_pass_builder;
+mod rendering;
+mod framebuffer;
 mod image;
 mod texture;
+mod screenshot;
 mod buffer;
+mod acceleration_structure;
 mod shared_context;
 mod command_pool;
-mod util;
+mod error;
 
 pub mod debug;
+pub mod memory;
 pub mod swapchain;
-pub mod pipeline;
\ No newline at end of file
+pub mod pipeline;
+pub mod util;
@@ -1,77 +1,281 @@
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 
-use ash::version::InstanceV1_0;
+use ash::extensions::khr::{AccelerationStructure, RayTracingPipeline};
 use ash::vk;
 use ash::vk::PhysicalDevice as VkPhysicalDevice;
 
-use crate::vulkan::{Instance, Surface};
+use crate::vulkan::{DeviceCandidate, DeviceFeatures, DeviceRejection, DeviceRequirements, DeviceScore, DeviceSelector, Instance, Surface, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QueueType {
+    Graphics,
+    /// Prefers a family without graphics support so compute work can run alongside rendering.
+    Compute,
+    /// Prefers a family without graphics and compute support, usually backed by a DMA engine.
+    Transfer,
+}
+
+/// Compute and transfer families fall back to the graphics family when the device has no dedicated ones.
 #[derive(Clone, Copy)]
 pub struct QueueFamilyIndices {
     pub graphics_family: u32,
-    pub present_family: u32,
+    pub present_family: Option<u32>,
+    pub compute_family: u32,
+    pub transfer_family: u32,
+}
+
+impl QueueFamilyIndices {
+    pub fn family(&self, queue_type: QueueType) -> u32 {
+        match queue_type {
+            QueueType::Graphics => self.graphics_family,
+            QueueType::Compute => self.compute_family,
+            QueueType::Transfer => self.transfer_family,
+        }
+    }
+
+    /// Distinct families a queue has to be created for.
+    pub fn unique_families(&self) -> Vec<u32> {
+        let mut families = vec![self.graphics_family, self.compute_family, self.transfer_family];
+        families.extend(self.present_family);
+        families.sort_unstable();
+        families.dedup();
+        families
+    }
 }
 
 pub struct PhysicalDevice {
     physical_device: VkPhysicalDevice,
+    properties: vk::PhysicalDeviceProperties,
     queue_family_indices: QueueFamilyIndices,
-    required_extensions: Vec<*const i8>,
+    requirements: DeviceRequirements,
+    enabled_extensions: Vec<&'static CStr>,
+    enabled_features: DeviceFeatures,
 }
 
 impl PhysicalDevice {
-    pub fn optimal_device(instance: &Instance, surface: &Surface, required_extensions: Vec<*const i8>) -> Self {
-        let mut devices = PhysicalDevice::physical_devices(instance);
-        devices.retain(|device| PhysicalDevice::is_device_suitable(instance, surface, device, &required_extensions));
-
-        let optimal_device = devices.into_iter().next();
-
-        match optimal_device {
-            None => panic!("Failed to find optimal device"),
-            Some(physical_device) => {
-                let (graphics_family, present_family) = PhysicalDevice::find_queue_families(instance, surface, &physical_device);
-                let queue_family_indices = QueueFamilyIndices {
-                    graphics_family: graphics_family.unwrap(),
-                    present_family: present_family.unwrap(),
-                };
-                Self {
-                    physical_device,
-                    queue_family_indices,
-                    required_extensions,
-                }
+    /// Picks the highest scoring device supporting graphics and the required extensions and features. Present
+    /// support is only demanded when a surface is given, so headless contexts pass `None`.
+    ///
+    /// When `selector` is `None` the `VISION_DEVICE` environment variable is consulted, a selected device is used
+    /// regardless of its score but still has to be suitable.
+    pub fn optimal_device(instance: &Instance,
+                          surface: Option<&Surface>,
+                          requirements: DeviceRequirements,
+                          selector: Option<DeviceSelector>) -> VisionResult<Self> {
+        let selector = match selector {
+            Some(selector) => Some(selector),
+            None => DeviceSelector::from_env()?,
+        };
+
+        let candidates = PhysicalDevice::candidates(instance, surface, &requirements, selector.as_ref())?;
+
+        let mut optimal_candidate: Option<&DeviceCandidate> = None;
+        for candidate in candidates.iter().filter(|candidate| candidate.is_suitable()) {
+            if optimal_candidate.is_none_or(|optimal| candidate.score > optimal.score) {
+                optimal_candidate = Some(candidate);
             }
         }
+
+        let physical_device = match (optimal_candidate, &selector) {
+            (Some(candidate), _) => candidate.physical_device,
+            (None, Some(selector)) => {
+                let rejection = candidates.iter()
+                    .find(|candidate| candidate.selected)
+                    .map(|candidate| candidate.to_string())
+                    .unwrap_or_else(|| "no matching device".to_string());
+                return Err(VisionError::SelectedDeviceUnavailable(format!("{}: {}", selector, rejection)));
+            }
+            (None, None) => return Err(VisionError::NoSuitableDevice),
+        };
+
+        let (graphics_family, present_family) = PhysicalDevice::find_queue_families(instance, surface, &physical_device)?;
+        let graphics_family = graphics_family.ok_or(VisionError::NoSuitableDevice)?;
+        let (compute_family, transfer_family) = PhysicalDevice::find_dedicated_queue_families(instance, &physical_device);
+        let queue_family_indices = QueueFamilyIndices {
+            graphics_family,
+            present_family,
+            compute_family: compute_family.unwrap_or(graphics_family),
+            transfer_family: transfer_family.unwrap_or(graphics_family),
+        };
+
+        let properties = unsafe { instance.vk_instance().get_physical_device_properties(physical_device) };
+
+        let available_extensions = PhysicalDevice::available_extensions(instance, &physical_device)?;
+        let available_extensions = available_extensions.iter().map(CString::as_c_str).collect::<Vec<_>>();
+        let available_features = DeviceFeatures::supported(instance, physical_device, &available_extensions);
+        let (enabled_extensions, enabled_features) = requirements.negotiate(&available_extensions, &available_features);
+
+        Ok(Self {
+            physical_device,
+            properties,
+            queue_family_indices,
+            requirements,
+            enabled_extensions,
+            enabled_features,
+        })
+    }
+
+    /// Lists every physical device with its score and, for unsuitable ones, the reason it was rejected.
+    pub fn candidates(instance: &Instance,
+                      surface: Option<&Surface>,
+                      requirements: &DeviceRequirements,
+                      selector: Option<&DeviceSelector>) -> VisionResult<Vec<DeviceCandidate>> {
+        let mut candidates = vec![];
+
+        for (index, physical_device) in PhysicalDevice::physical_devices(instance)?.into_iter().enumerate() {
+            let (properties, memory_properties, features) = unsafe {
+                (instance.vk_instance().get_physical_device_properties(physical_device),
+                 instance.vk_instance().get_physical_device_memory_properties(physical_device),
+                 instance.vk_instance().get_physical_device_features(physical_device))
+            };
+            let name = unsafe {
+                CStr::from_ptr(properties.device_name.as_ptr())
+            }.to_string_lossy().into_owned();
+
+            let available_extensions = PhysicalDevice::available_extensions(instance, &physical_device)?;
+            let available_extensions = available_extensions.iter().map(CString::as_c_str).collect::<Vec<_>>();
+            let missing_extensions = requirements.required_extensions()
+                .into_iter()
+                .filter(|extension| !available_extensions.contains(extension))
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            let available_features = DeviceFeatures::supported(instance, physical_device, &available_extensions);
+            let missing_features = requirements.required_features().missing_from(&available_features);
+            let (graphics_family, present_family) = PhysicalDevice::find_queue_families(instance, surface, &physical_device)?;
+
+            let selected = selector.is_none_or(|selector| selector.matches(index, &properties, &name));
+
+            let rejection = if !missing_extensions.is_empty() {
+                Some(DeviceRejection::MissingExtensions(missing_extensions))
+            } else if !missing_features.is_empty() {
+                Some(DeviceRejection::MissingFeatures(missing_features))
+            } else if graphics_family.is_none() {
+                Some(DeviceRejection::MissingGraphicsQueue)
+            } else if surface.is_some() && present_family.is_none() {
+                Some(DeviceRejection::MissingPresentQueue)
+            } else if !selected {
+                selector.cloned().map(DeviceRejection::NotSelected)
+            } else {
+                None
+            };
+
+            candidates.push(DeviceCandidate {
+                index,
+                name,
+                vendor_id: properties.vendor_id,
+                device_id: properties.device_id,
+                device_type: properties.device_type,
+                score: DeviceScore::new(&properties, &memory_properties, &features),
+                selected,
+                rejection,
+                physical_device,
+            });
+        }
+
+        Ok(candidates)
     }
 
     pub fn vk_physical_device(&self) -> VkPhysicalDevice {
         self.physical_device
     }
 
+    pub fn properties(&self) -> &vk::PhysicalDeviceProperties {
+        &self.properties
+    }
+
+    pub fn name(&self) -> String {
+        unsafe {
+            CStr::from_ptr(self.properties.device_name.as_ptr())
+        }.to_string_lossy().into_owned()
+    }
+
     pub fn queue_family_indices(&self) -> QueueFamilyIndices {
         self.queue_family_indices
     }
 
-    pub fn required_extensions(&self) -> &Vec<*const i8> {
-        &self.required_extensions
+    pub fn requirements(&self) -> &DeviceRequirements {
+        &self.requirements
     }
 
-    fn physical_devices(instance: &Instance) -> Vec<VkPhysicalDevice> {
-        let physical_devices = unsafe {
-            instance.vk_instance().enumerate_physical_devices()
-        }.expect("Failed to enumerate physical devices");
+    /// Required extensions and the optional ones the device supports.
+    pub fn enabled_extensions(&self) -> &[&'static CStr] {
+        &self.enabled_extensions
+    }
+
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.enabled_extensions.contains(&name)
+    }
+
+    /// Required features and the optional ones the device supports.
+    pub fn enabled_features(&self) -> &DeviceFeatures {
+        &self.enabled_features
+    }
+
+    pub fn format_properties(&self, instance: &Instance, format: vk::Format) -> vk::FormatProperties {
+        unsafe {
+            instance.vk_instance().get_physical_device_format_properties(self.physical_device, format)
+        }
+    }
 
-        physical_devices
+    /// Shader group handle sizes and alignments, only meaningful if the device supports ray tracing pipelines.
+    pub fn ray_tracing_pipeline_properties(&self, instance: &Instance) -> vk::PhysicalDeviceRayTracingPipelinePropertiesKHR {
+        unsafe { RayTracingPipeline::get_properties(instance.vk_instance(), self.physical_device) }
     }
 
-    fn is_device_suitable(instance: &Instance, surface: &Surface, physical_device: &VkPhysicalDevice, required_extensions: &Vec<*const i8>) -> bool {
-        let (graphics_family, present_family) = PhysicalDevice::find_queue_families(instance, surface, physical_device);
+    /// Acceleration structure limits, only meaningful if the device supports acceleration structures.
+    pub fn acceleration_structure_properties(&self, instance: &Instance) -> vk::PhysicalDeviceAccelerationStructurePropertiesKHR {
+        unsafe { AccelerationStructure::get_properties(instance.vk_instance(), self.physical_device) }
+    }
+
+    /// Returns the first of `candidates` supporting `features` with `tiling`.
+    pub fn find_supported_format(&self,
+                                 instance: &Instance,
+                                 candidates: &[vk::Format],
+                                 tiling: vk::ImageTiling,
+                                 features: vk::FormatFeatureFlags) -> Option<vk::Format> {
+        candidates.iter()
+            .copied()
+            .find(|format| {
+                let properties = self.format_properties(instance, *format);
+                match tiling {
+                    vk::ImageTiling::LINEAR => properties.linear_tiling_features.contains(features),
+                    _ => properties.optimal_tiling_features.contains(features),
+                }
+            })
+    }
+
+    /// Sample counts usable by framebuffers with both color and depth attachments.
+    pub fn framebuffer_sample_counts(&self) -> vk::SampleCountFlags {
+        let limits = &self.properties.limits;
+        limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts
+    }
+
+    /// Highest sample count usable by framebuffers with color and depth attachments that does not exceed
+    /// `max_samples`, at least `TYPE_1`.
+    pub fn max_usable_sample_count(&self, max_samples: vk::SampleCountFlags) -> vk::SampleCountFlags {
+        let supported = self.framebuffer_sample_counts();
+        [
+            vk::SampleCountFlags::TYPE_64,
+            vk::SampleCountFlags::TYPE_32,
+            vk::SampleCountFlags::TYPE_16,
+            vk::SampleCountFlags::TYPE_8,
+            vk::SampleCountFlags::TYPE_4,
+            vk::SampleCountFlags::TYPE_2,
+        ].iter()
+            .copied()
+            .find(|samples| samples.as_raw() <= max_samples.as_raw() && supported.contains(*samples))
+            .unwrap_or(vk::SampleCountFlags::TYPE_1)
+    }
 
-        PhysicalDevice::check_extension_support(instance, physical_device, required_extensions)
-            && graphics_family.is_some()
-            && present_family.is_some()
+    fn physical_devices(instance: &Instance) -> VisionResult<Vec<VkPhysicalDevice>> {
+        unsafe {
+            instance.vk_instance().enumerate_physical_devices()
+        }.map_vk_err("vkEnumeratePhysicalDevices")
     }
 
-    fn find_queue_families(instance: &Instance, surface: &Surface, physical_device: &VkPhysicalDevice)
-                           -> (Option<u32>, Option<u32>) {
+    fn find_queue_families(instance: &Instance, surface: Option<&Surface>, physical_device: &VkPhysicalDevice)
+                           -> VisionResult<(Option<u32>, Option<u32>)> {
         let queue_families = unsafe {
             instance.vk_instance().get_physical_device_queue_family_properties(*physical_device)
         };
@@ -84,36 +288,56 @@ impl PhysicalDevice {
             if queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
                 graphics_family = Some(index);
             }
-            let present_support = unsafe {
-                surface.vk_surface()
-                    .get_physical_device_surface_support(*physical_device, index, *surface.vk_surface_khr())
-            };
-            if present_support.unwrap() {
-                present_family = Some(index);
+            if let Some(surface) = surface {
+                let present_support = unsafe {
+                    surface.vk_surface()
+                        .get_physical_device_surface_support(*physical_device, index, *surface.vk_surface_khr())
+                }.map_vk_err("vkGetPhysicalDeviceSurfaceSupportKHR")?;
+                if present_support {
+                    present_family = Some(index);
+                }
             }
-            if graphics_family.is_some() && present_family.is_some() {
+            if graphics_family.is_some() && (surface.is_none() || present_family.is_some()) {
                 break;
             }
         }
 
-        (graphics_family, present_family)
+        Ok((graphics_family, present_family))
     }
 
-    fn check_extension_support(instance: &Instance, physical_device: &VkPhysicalDevice, required_extensions: &Vec<*const i8>) -> bool {
-        let available_extensions = unsafe {
+    /// Returns the first compute family without graphics support and the first transfer family supporting neither
+    /// graphics nor compute, a compute only family is used for transfers if there is no pure transfer family.
+    fn find_dedicated_queue_families(instance: &Instance, physical_device: &VkPhysicalDevice) -> (Option<u32>, Option<u32>) {
+        let queue_families = unsafe {
+            instance.vk_instance().get_physical_device_queue_family_properties(*physical_device)
+        };
+
+        let find_family = |included: vk::QueueFlags, excluded: vk::QueueFlags| {
+            queue_families.iter()
+                .position(|queue_family| {
+                    queue_family.queue_count > 0
+                        && queue_family.queue_flags.contains(included)
+                        && !queue_family.queue_flags.intersects(excluded)
+                })
+                .map(|index| index as u32)
+        };
+
+        let compute_family = find_family(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS);
+        let transfer_family = find_family(vk::QueueFlags::TRANSFER, vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            .or(compute_family);
+
+        (compute_family, transfer_family)
+    }
+
+    fn available_extensions(instance: &Instance, physical_device: &VkPhysicalDevice) -> VisionResult<Vec<CString>> {
+        let extension_properties = unsafe {
             instance.vk_instance().enumerate_device_extension_properties(*physical_device)
-        }.expect("Failed to get device extension properties");
-
-        for required_extension in required_extensions {
-            unsafe {
-                if !available_extensions
-                    .iter()
-                    .any(|extension| CStr::from_ptr((&extension.extension_name).as_ptr()) == CStr::from_ptr(*required_extension)) {
-                    return false;
-                }
-            }
-        }
+        }.map_vk_err("vkEnumerateDeviceExtensionProperties")?;
 
-        true
+        let extensions = extension_properties.iter()
+            .map(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) }.to_owned())
+            .collect();
+
+        Ok(extensions)
     }
-}
\ No newline at end of file
+}
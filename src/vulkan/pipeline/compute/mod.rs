@@ -0,0 +1,3 @@
+pub use self::pipeline::{ComputePipeline, ComputePipelineBuilder};
+
+mod pipeline;
@@ -0,0 +1,361 @@
+use std::sync::Arc;
+
+use ash::vk;
+use bytemuck::Pod;
+
+use crate::vulkan::{Buffer, Context, ShaderModule, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::pipeline::PipelineLayout;
+
+pub struct ComputePipeline {
+    context: Arc<Context>,
+    pipeline: vk::Pipeline,
+    layout: PipelineLayout,
+    // Set 0 of `storage_buffer_count` storage buffers, owned by the pipeline.
+    storage_set_layout: Option<vk::DescriptorSetLayout>,
+    storage_buffer_count: u32,
+    // Layouts derived from the shader have no storage set `run` could bind.
+    layout_from_shader: bool,
+}
+
+impl ComputePipeline {
+    pub fn builder(module: &ShaderModule) -> ComputePipelineBuilder<'_> {
+        ComputePipelineBuilder::new(module)
+    }
+
+    /// Number of workgroups of `local_size` covering `invocations` in each dimension, e.g. with the size of
+    /// `ShaderReflection::workgroup_size`. Fails if a dimension of `local_size` is 0.
+    pub fn group_count(invocations: [u32; 3], local_size: [u32; 3]) -> VisionResult<[u32; 3]> {
+        if local_size.contains(&0) {
+            return Err(VisionError::InvalidWorkgroupSize(local_size));
+        }
+        Ok([
+            invocations[0].div_ceil(local_size[0]),
+            invocations[1].div_ceil(local_size[1]),
+            invocations[2].div_ceil(local_size[2]),
+        ])
+    }
+
+    pub fn cmd_bind(&self, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            self.context.device().vk_device()
+                .cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        }
+    }
+
+    pub fn cmd_bind_descriptor_sets(&self,
+                                    command_buffer: vk::CommandBuffer,
+                                    first_set: u32,
+                                    descriptor_sets: &[vk::DescriptorSet]) {
+        unsafe {
+            self.context.device().vk_device().cmd_bind_descriptor_sets(command_buffer,
+                                                                       vk::PipelineBindPoint::COMPUTE,
+                                                                       self.layout.vk_pipeline_layout(),
+                                                                       first_set,
+                                                                       descriptor_sets,
+                                                                       &[]);
+        }
+    }
+
+    pub fn cmd_push_constants(&self, command_buffer: vk::CommandBuffer, data: &[u8]) {
+        self.layout.cmd_push_constants(command_buffer, vk::ShaderStageFlags::COMPUTE, 0, data);
+    }
+
+    pub fn cmd_dispatch(&self, command_buffer: vk::CommandBuffer, group_count: [u32; 3]) {
+        unsafe {
+            self.context.device().vk_device()
+                .cmd_dispatch(command_buffer, group_count[0], group_count[1], group_count[2]);
+        }
+    }
+
+    /// Dispatches the `VkDispatchIndirectCommand` at byte `offset` of `buffer`, e.g. one written by a previous
+    /// dispatch. The buffer needs `INDIRECT_BUFFER` usage.
+    pub fn cmd_dispatch_indirect<T: Pod>(&self, command_buffer: vk::CommandBuffer, buffer: &Buffer<T>, offset: vk::DeviceSize) {
+        unsafe {
+            self.context.device().vk_device()
+                .cmd_dispatch_indirect(command_buffer, buffer.vk_buffer(), offset);
+        }
+    }
+
+    /// Binds `buffers` as storage buffers of set 0 in order, dispatches `group_count` workgroups and waits for
+    /// completion. Writes are visible to the host and to later transfers, e.g. `Buffer::readback`, afterwards.
+    ///
+    /// Work is submitted to the graphics queue which buffers are uploaded and read back on, so no queue family
+    /// ownership transfers are needed. Fails for pipelines built with `layout_from_shader`.
+    pub fn run(&self,
+               buffers: &[vk::DescriptorBufferInfo],
+               push_constants: &[u8],
+               group_count: [u32; 3]) -> VisionResult<()> {
+        if self.layout_from_shader {
+            return Err(VisionError::UnsupportedOperation(
+                "ComputePipeline::run with a layout from the shader, bind its descriptor sets and dispatch instead"));
+        }
+        if buffers.len() != self.storage_buffer_count as usize {
+            return Err(VisionError::StorageBufferCountMismatch {
+                expected: self.storage_buffer_count,
+                actual: buffers.len(),
+            });
+        }
+        let device = self.context.device().vk_device();
+
+        let descriptor_pool = match self.storage_set_layout {
+            Some(set_layout) => {
+                let pool_sizes = [vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: self.storage_buffer_count,
+                }];
+                let pool_info = vk::DescriptorPoolCreateInfo::builder()
+                    .max_sets(1)
+                    .pool_sizes(&pool_sizes);
+                let descriptor_pool = unsafe {
+                    device.create_descriptor_pool(&pool_info, None)
+                }.map_vk_err("vkCreateDescriptorPool")?;
+                Some((descriptor_pool, set_layout))
+            }
+            None => None,
+        };
+
+        let result = self.run_in_pool(descriptor_pool, buffers, push_constants, group_count);
+
+        if let Some((descriptor_pool, _)) = descriptor_pool {
+            unsafe { device.destroy_descriptor_pool(descriptor_pool, None) };
+        }
+        result
+    }
+
+    /// Runs the kernel like `run` and reads back `output`, which is usually one of `buffers`.
+    pub fn run_and_read<T: Pod>(&self,
+                                buffers: &[vk::DescriptorBufferInfo],
+                                push_constants: &[u8],
+                                group_count: [u32; 3],
+                                output: &Buffer<T>) -> VisionResult<Vec<T>> {
+        self.run(buffers, push_constants, group_count)?;
+        output.readback()
+    }
+
+    fn run_in_pool(&self,
+                   descriptor_pool: Option<(vk::DescriptorPool, vk::DescriptorSetLayout)>,
+                   buffers: &[vk::DescriptorBufferInfo],
+                   push_constants: &[u8],
+                   group_count: [u32; 3]) -> VisionResult<()> {
+        let device = self.context.device().vk_device();
+        let descriptor_set = match descriptor_pool {
+            Some((descriptor_pool, set_layout)) => {
+                let set_layouts = [set_layout];
+                let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&set_layouts);
+                let descriptor_set = unsafe {
+                    device.allocate_descriptor_sets(&alloc_info)
+                }.map_vk_err("vkAllocateDescriptorSets")?[0];
+
+                let writes = buffers.iter()
+                    .enumerate()
+                    .map(|(binding, buffer)| {
+                        vk::WriteDescriptorSet::builder()
+                            .dst_set(descriptor_set)
+                            .dst_binding(binding as u32)
+                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                            .buffer_info(std::slice::from_ref(buffer))
+                            .build()
+                    })
+                    .collect::<Vec<_>>();
+                unsafe { device.update_descriptor_sets(&writes, &[]) };
+                Some(descriptor_set)
+            }
+            None => None,
+        };
+
+        self.context.execute_transient(|command_buffer| {
+            self.cmd_bind(command_buffer);
+            if let Some(descriptor_set) = descriptor_set {
+                self.cmd_bind_descriptor_sets(command_buffer, 0, &[descriptor_set]);
+            }
+            if !push_constants.is_empty() {
+                self.cmd_push_constants(command_buffer, push_constants);
+            }
+            self.cmd_dispatch(command_buffer, group_count);
+            self.context.cmd_memory_barrier(command_buffer,
+                                            vk::PipelineStageFlags::COMPUTE_SHADER,
+                                            vk::AccessFlags::SHADER_WRITE,
+                                            vk::PipelineStageFlags::HOST | vk::PipelineStageFlags::TRANSFER,
+                                            vk::AccessFlags::HOST_READ | vk::AccessFlags::TRANSFER_READ);
+        })
+    }
+
+    /// Layout of the storage buffers bound by `run`, set 0 of the pipeline layout.
+    pub fn storage_set_layout(&self) -> Option<vk::DescriptorSetLayout> {
+        self.storage_set_layout
+    }
+
+    pub fn layout(&self) -> &PipelineLayout {
+        &self.layout
+    }
+
+    pub fn vk_pipeline(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        let device = self.context.device().vk_device();
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            if let Some(set_layout) = self.storage_set_layout {
+                device.destroy_descriptor_set_layout(set_layout, None);
+            }
+        }
+    }
+}
+
+/// Describes a compute pipeline running the `main` entry point of a shader module unless another one is set.
+pub struct ComputePipelineBuilder<'a> {
+    module: &'a ShaderModule,
+    entry_point: String,
+    storage_buffer_count: u32,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_size: u32,
+    layout_from_shader: bool,
+}
+
+impl<'a> ComputePipelineBuilder<'a> {
+    pub fn new(module: &'a ShaderModule) -> Self {
+        Self {
+            module,
+            entry_point: "main".to_string(),
+            storage_buffer_count: 0,
+            set_layouts: vec![],
+            push_constant_size: 0,
+            layout_from_shader: false,
+        }
+    }
+
+    pub fn entry_point(mut self, entry_point: &str) -> Self {
+        self.entry_point = entry_point.to_string();
+        self
+    }
+
+    /// Creates set 0 with storage buffers at bindings `0..count`, as bound by `ComputePipeline::run`. Sets added with
+    /// `descriptor_set_layout` follow it.
+    pub fn storage_buffers(mut self, count: u32) -> Self {
+        self.storage_buffer_count = count;
+        self
+    }
+
+    pub fn descriptor_set_layout(mut self, set_layout: vk::DescriptorSetLayout) -> Self {
+        self.set_layouts.push(set_layout);
+        self
+    }
+
+    /// Size in bytes of the push constant block at offset 0.
+    pub fn push_constant_size(mut self, size: u32) -> Self {
+        self.push_constant_size = size;
+        self
+    }
+
+    /// Creates the pipeline layout from the descriptor bindings and push constants the shader declares, instead of
+    /// `storage_buffers`, `descriptor_set_layout` and `push_constant_size`. `ComputePipeline::run` is unavailable.
+    pub fn layout_from_shader(mut self) -> Self {
+        self.layout_from_shader = true;
+        self
+    }
+
+    pub fn build(self, context: Arc<Context>) -> VisionResult<ComputePipeline> {
+        let reflection = self.module.require_reflection(&self.entry_point)?;
+        if reflection.stage() != vk::ShaderStageFlags::COMPUTE {
+            return Err(VisionError::ShaderMismatch(format!("entry point '{}' is not a compute shader", reflection.entry_point())));
+        }
+        if self.layout_from_shader {
+            let layout = PipelineLayout::from_shaders(Arc::clone(&context), &[reflection])?;
+            return self.create(context, layout, None);
+        }
+
+        let device = context.device().vk_device();
+
+        let storage_set_layout = match self.storage_buffer_count {
+            0 => None,
+            count => {
+                let bindings = (0..count)
+                    .map(|binding| {
+                        vk::DescriptorSetLayoutBinding::builder()
+                            .binding(binding)
+                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                            .descriptor_count(1)
+                            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                            .build()
+                    })
+                    .collect::<Vec<_>>();
+                reflection.check_descriptor_set(0, &bindings)?;
+                let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+                let set_layout = unsafe {
+                    device.create_descriptor_set_layout(&layout_info, None)
+                }.map_vk_err("vkCreateDescriptorSetLayout")?;
+                Some(set_layout)
+            }
+        };
+
+        // The pipeline takes ownership of the storage set layout once created, it is released here before.
+        let destroy_set_layout = || {
+            if let Some(set_layout) = storage_set_layout {
+                unsafe { device.destroy_descriptor_set_layout(set_layout, None) };
+            }
+        };
+
+        let set_layouts = storage_set_layout.iter()
+            .chain(self.set_layouts.iter())
+            .copied()
+            .collect::<Vec<_>>();
+        let push_constant_ranges = match self.push_constant_size {
+            0 => vec![],
+            size => vec![vk::PushConstantRange { stage_flags: vk::ShaderStageFlags::COMPUTE, offset: 0, size }],
+        };
+        let layout = match reflection.check_push_constant_ranges(&push_constant_ranges)
+            .and_then(|_| PipelineLayout::new(Arc::clone(&context), &set_layouts, &push_constant_ranges)) {
+            Ok(layout) => layout,
+            Err(error) => {
+                destroy_set_layout();
+                return Err(error);
+            }
+        };
+
+        let result = self.create(Arc::clone(&context), layout, storage_set_layout);
+        if result.is_err() {
+            destroy_set_layout();
+        }
+        result
+    }
+
+    /// Creates the pipeline, which takes ownership of `storage_set_layout` on success.
+    fn create(&self,
+              context: Arc<Context>,
+              layout: PipelineLayout,
+              storage_set_layout: Option<vk::DescriptorSetLayout>) -> VisionResult<ComputePipeline> {
+        let entry_point = ShaderModule::entry_point_name(&self.entry_point)?;
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(*self.module.vk_shader_module())
+            .name(&entry_point);
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage.build())
+            .layout(layout.vk_pipeline_layout());
+
+        let pipeline = unsafe {
+            context.device().vk_device().create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+        }.map_err(|(_, result)| result).map_vk_err("vkCreateComputePipelines")?[0];
+
+        // `run` only binds the storage set created by the builder.
+        let storage_buffer_count = match storage_set_layout {
+            Some(_) => self.storage_buffer_count,
+            None => 0,
+        };
+        Ok(ComputePipeline {
+            context,
+            pipeline,
+            layout,
+            storage_set_layout,
+            storage_buffer_count,
+            layout_from_shader: self.layout_from_shader,
+        })
+    }
+}
@@ -1,2 +1,7 @@
-mod raytracing;
-mod rasterization;
\ No newline at end of file
+pub use self::pipeline_layout::PipelineLayout;
+
+mod pipeline_layout;
+
+pub mod compute;
+pub mod raytracing;
+pub mod rasterization;
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::{Context, ShaderReflection, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+
+pub struct PipelineLayout {
+    context: Arc<Context>,
+    pipeline_layout: vk::PipelineLayout,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    // Set layouts created by `from_shaders` are destroyed with the pipeline layout.
+    owns_set_layouts: bool,
+}
+
+impl PipelineLayout {
+    pub fn new(context: Arc<Context>,
+               set_layouts: &[vk::DescriptorSetLayout],
+               push_constant_ranges: &[vk::PushConstantRange]) -> VisionResult<Self> {
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+        let pipeline_layout = unsafe {
+            context.device().vk_device().create_pipeline_layout(&layout_info, None)
+        }.map_vk_err("vkCreatePipelineLayout")?;
+
+        Ok(Self {
+            context,
+            pipeline_layout,
+            set_layouts: set_layouts.to_vec(),
+            push_constant_ranges: push_constant_ranges.to_vec(),
+            owns_set_layouts: false,
+        })
+    }
+
+    /// Creates the descriptor set layouts and push constant ranges declared by `shaders`. Sets without bindings
+    /// between used ones get empty layouts, bindings shared by several stages must agree in type and count.
+    pub fn from_shaders(context: Arc<Context>, shaders: &[&ShaderReflection]) -> VisionResult<Self> {
+        let mut bindings = BTreeMap::<(u32, u32), vk::DescriptorSetLayoutBinding>::new();
+        let mut push_constant_ranges: Vec<vk::PushConstantRange> = vec![];
+        for shader in shaders.iter() {
+            for binding in shader.descriptor_bindings() {
+                if binding.count == 0 {
+                    return Err(VisionError::ShaderMismatch(format!(
+                        "runtime sized array '{}' needs a manual descriptor set layout", binding.name)));
+                }
+                let merged = bindings.entry((binding.set, binding.binding)).or_insert(vk::DescriptorSetLayoutBinding {
+                    binding: binding.binding,
+                    descriptor_type: binding.descriptor_type,
+                    descriptor_count: binding.count,
+                    stage_flags: vk::ShaderStageFlags::empty(),
+                    p_immutable_samplers: std::ptr::null(),
+                });
+                if merged.descriptor_type != binding.descriptor_type || merged.descriptor_count != binding.count {
+                    return Err(VisionError::ShaderMismatch(format!(
+                        "binding {}.{} is declared differently by several stages", binding.set, binding.binding)));
+                }
+                merged.stage_flags |= binding.stages;
+            }
+
+            if let Some(range) = shader.push_constant_range() {
+                // A stage may only appear in one range.
+                match push_constant_ranges.iter_mut().find(|merged| merged.stage_flags == range.stage_flags) {
+                    Some(merged) => {
+                        let end = (merged.offset + merged.size).max(range.offset + range.size);
+                        merged.offset = merged.offset.min(range.offset);
+                        merged.size = end - merged.offset;
+                    }
+                    None => push_constant_ranges.push(range),
+                }
+            }
+        }
+
+        let device = context.device().vk_device();
+        let set_count = bindings.keys().last().map_or(0, |(set, _)| set + 1);
+        let mut set_layouts = vec![];
+        for set in 0..set_count {
+            let set_bindings = bindings.range((set, 0)..=(set, u32::MAX))
+                .map(|(_, binding)| *binding)
+                .collect::<Vec<_>>();
+            let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&set_bindings);
+            match unsafe { device.create_descriptor_set_layout(&layout_info, None) } {
+                Ok(set_layout) => set_layouts.push(set_layout),
+                Err(result) => {
+                    Self::destroy_set_layouts(&context, &set_layouts);
+                    return Err(result).map_vk_err("vkCreateDescriptorSetLayout");
+                }
+            }
+        }
+
+        match Self::new(Arc::clone(&context), &set_layouts, &push_constant_ranges) {
+            Ok(mut layout) => {
+                layout.owns_set_layouts = true;
+                Ok(layout)
+            }
+            Err(error) => {
+                Self::destroy_set_layouts(&context, &set_layouts);
+                Err(error)
+            }
+        }
+    }
+
+    pub fn cmd_push_constants(&self,
+                              command_buffer: vk::CommandBuffer,
+                              stages: vk::ShaderStageFlags,
+                              offset: u32,
+                              data: &[u8]) {
+        unsafe {
+            self.context.device().vk_device()
+                .cmd_push_constants(command_buffer, self.pipeline_layout, stages, offset, data);
+        }
+    }
+
+    /// Descriptor set layouts in set order, e.g. to allocate descriptor sets for a layout created by `from_shaders`.
+    pub fn set_layouts(&self) -> &[vk::DescriptorSetLayout] {
+        &self.set_layouts
+    }
+
+    pub fn push_constant_ranges(&self) -> &[vk::PushConstantRange] {
+        &self.push_constant_ranges
+    }
+
+    pub fn vk_pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    fn destroy_set_layouts(context: &Context, set_layouts: &[vk::DescriptorSetLayout]) {
+        for &set_layout in set_layouts {
+            unsafe { context.device().vk_device().destroy_descriptor_set_layout(set_layout, None) };
+        }
+    }
+}
+
+impl Drop for PipelineLayout {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device().vk_device().destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        if self.owns_set_layouts {
+            Self::destroy_set_layouts(&self.context, &self.set_layouts);
+        }
+    }
+}
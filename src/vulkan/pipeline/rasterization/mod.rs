@@ -1 +1,3 @@
-mod pipeline;
\ No newline at end of file
+pub use self::pipeline::{ColorBlend, GraphicsPipeline, GraphicsPipelineBuilder};
+
+mod pipeline;
@@ -1,3 +1,402 @@
-struct RasterizationPipeline {
+use std::sync::Arc;
 
-}
\ No newline at end of file
+use ash::vk;
+
+use crate::vulkan::{Context, RenderPass, Rendering, RenderingLayout, ShaderModule, ShaderReflection, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::pipeline::PipelineLayout;
+
+/// Blending of a color attachment, the blended color is written to all channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorBlend {
+    Opaque,
+    /// `src * src_alpha + dst * (1 - src_alpha)`
+    Alpha,
+    /// `src + dst * (1 - src_alpha)`
+    PremultipliedAlpha,
+    /// `src + dst`
+    Additive,
+}
+
+impl ColorBlend {
+    pub fn vk_attachment_state(&self) -> vk::PipelineColorBlendAttachmentState {
+        let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+            ColorBlend::Opaque => (vk::BlendFactor::ONE, vk::BlendFactor::ZERO, vk::BlendFactor::ONE, vk::BlendFactor::ZERO),
+            ColorBlend::Alpha => (vk::BlendFactor::SRC_ALPHA,
+                                  vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                                  vk::BlendFactor::ONE,
+                                  vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+            ColorBlend::PremultipliedAlpha => (vk::BlendFactor::ONE,
+                                               vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                                               vk::BlendFactor::ONE,
+                                               vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+            ColorBlend::Additive => (vk::BlendFactor::ONE, vk::BlendFactor::ONE, vk::BlendFactor::ONE, vk::BlendFactor::ONE),
+        };
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable: (*self != ColorBlend::Opaque).into(),
+            src_color_blend_factor: src_color,
+            dst_color_blend_factor: dst_color,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: src_alpha,
+            dst_alpha_blend_factor: dst_alpha,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+}
+
+pub struct GraphicsPipeline {
+    context: Arc<Context>,
+    pipeline: vk::Pipeline,
+    layout: PipelineLayout,
+}
+
+impl GraphicsPipeline {
+    pub fn builder<'a>() -> GraphicsPipelineBuilder<'a> {
+        GraphicsPipelineBuilder::new()
+    }
+
+    pub fn cmd_bind(&self, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            self.context.device().vk_device()
+                .cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        }
+    }
+
+    pub fn layout(&self) -> &PipelineLayout {
+        &self.layout
+    }
+
+    pub fn vk_pipeline(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+}
+
+impl Drop for GraphicsPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device().vk_device().destroy_pipeline(self.pipeline, None);
+        }
+    }
+}
+
+/// Describes a graphics pipeline, the attachment count and sample count are taken from the render pass or rendering
+/// layout it is built for. Defaults are a triangle list without culling, a `LESS` depth test with depth writes,
+/// opaque color attachments and a dynamic viewport and scissor.
+pub struct GraphicsPipelineBuilder<'a> {
+    stages: Vec<(vk::ShaderStageFlags, &'a ShaderModule, String)>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    line_width: f32,
+    depth_bias: Option<(f32, f32, f32)>,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    color_blends: Vec<vk::PipelineColorBlendAttachmentState>,
+    dynamic_states: Vec<vk::DynamicState>,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    layout_from_shaders: bool,
+}
+
+impl<'a> GraphicsPipelineBuilder<'a> {
+    pub fn new() -> Self {
+        Self {
+            stages: vec![],
+            vertex_bindings: vec![],
+            vertex_attributes: vec![],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            depth_bias: None,
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: vk::CompareOp::LESS,
+            stencil: None,
+            color_blends: vec![],
+            dynamic_states: vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+            set_layouts: vec![],
+            push_constant_ranges: vec![],
+            layout_from_shaders: false,
+        }
+    }
+
+    /// Adds a stage using the `main` entry point of `module`.
+    pub fn stage(self, stage: vk::ShaderStageFlags, module: &'a ShaderModule) -> Self {
+        self.stage_with_entry_point(stage, module, "main")
+    }
+
+    pub fn stage_with_entry_point(mut self, stage: vk::ShaderStageFlags, module: &'a ShaderModule, entry_point: &str) -> Self {
+        self.stages.push((stage, module, entry_point.to_string()));
+        self
+    }
+
+    pub fn vertex_shader(self, module: &'a ShaderModule) -> Self {
+        self.stage(vk::ShaderStageFlags::VERTEX, module)
+    }
+
+    pub fn fragment_shader(self, module: &'a ShaderModule) -> Self {
+        self.stage(vk::ShaderStageFlags::FRAGMENT, module)
+    }
+
+    pub fn vertex_binding(mut self, binding: u32, stride: u32, input_rate: vk::VertexInputRate) -> Self {
+        self.vertex_bindings.push(vk::VertexInputBindingDescription { binding, stride, input_rate });
+        self
+    }
+
+    pub fn vertex_attribute(mut self, location: u32, binding: u32, format: vk::Format, offset: u32) -> Self {
+        self.vertex_attributes.push(vk::VertexInputAttributeDescription { location, binding, format, offset });
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn primitive_restart(mut self, enabled: bool) -> Self {
+        self.primitive_restart = enabled;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn line_width(mut self, line_width: f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn depth_bias(mut self, constant_factor: f32, clamp: f32, slope_factor: f32) -> Self {
+        self.depth_bias = Some((constant_factor, clamp, slope_factor));
+        self
+    }
+
+    pub fn depth_test(mut self, enabled: bool) -> Self {
+        self.depth_test = enabled;
+        self
+    }
+
+    pub fn depth_write(mut self, enabled: bool) -> Self {
+        self.depth_write = enabled;
+        self
+    }
+
+    pub fn depth_compare_op(mut self, compare_op: vk::CompareOp) -> Self {
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    pub fn stencil(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    /// Sets the blending of the next color attachment, attachments without one are opaque.
+    pub fn color_blend(self, blend: ColorBlend) -> Self {
+        self.color_blend_state(blend.vk_attachment_state())
+    }
+
+    pub fn color_blend_state(mut self, state: vk::PipelineColorBlendAttachmentState) -> Self {
+        self.color_blends.push(state);
+        self
+    }
+
+    /// Adds a state set while recording, viewport and scissor are always dynamic.
+    pub fn dynamic_state(mut self, state: vk::DynamicState) -> Self {
+        if !self.dynamic_states.contains(&state) {
+            self.dynamic_states.push(state);
+        }
+        self
+    }
+
+    pub fn descriptor_set_layout(mut self, set_layout: vk::DescriptorSetLayout) -> Self {
+        self.set_layouts.push(set_layout);
+        self
+    }
+
+    pub fn push_constant_range(mut self, stages: vk::ShaderStageFlags, offset: u32, size: u32) -> Self {
+        self.push_constant_ranges.push(vk::PushConstantRange { stage_flags: stages, offset, size });
+        self
+    }
+
+    /// Creates the pipeline layout from the descriptor bindings and push constants the shaders declare, instead of
+    /// the set layouts and ranges added with `descriptor_set_layout` and `push_constant_range`.
+    pub fn layout_from_shaders(mut self) -> Self {
+        self.layout_from_shaders = true;
+        self
+    }
+
+    /// Builds the pipeline for `subpass` of `render_pass` and render passes compatible with it.
+    pub fn build(self, context: Arc<Context>, render_pass: &RenderPass, subpass: u32) -> VisionResult<GraphicsPipeline> {
+        let description = render_pass.description();
+        if subpass >= description.subpass_count() {
+            return Err(VisionError::SubpassOutOfRange { subpass, count: description.subpass_count() });
+        }
+        let color_count = description.subpass_color_count(subpass);
+        let samples = description.subpass_samples(subpass);
+        self.create(context, render_pass.vk_render_pass(), subpass, color_count, samples, None)
+    }
+
+    /// Builds the pipeline for the attachment formats of `rendering`, or for its compatible render pass if dynamic
+    /// rendering is unavailable.
+    pub fn build_for_rendering(self, context: Arc<Context>, rendering: &Rendering) -> VisionResult<GraphicsPipeline> {
+        match rendering.compatible_render_pass() {
+            Some(render_pass) => self.build(context, render_pass, 0),
+            None => {
+                let layout = rendering.layout();
+                let color_count = layout.color_formats().len();
+                self.create(context, vk::RenderPass::null(), 0, color_count, layout.sample_count(), Some(layout))
+            }
+        }
+    }
+
+    fn create(self,
+              context: Arc<Context>,
+              render_pass: vk::RenderPass,
+              subpass: u32,
+              color_count: usize,
+              samples: vk::SampleCountFlags,
+              rendering_layout: Option<&RenderingLayout>) -> VisionResult<GraphicsPipeline> {
+        let entry_points = self.stages.iter()
+            .map(|(_, _, entry_point)| ShaderModule::entry_point_name(entry_point))
+            .collect::<VisionResult<Vec<_>>>()?;
+        let reflections = self.reflect_stages(color_count)?;
+        let layout = match self.layout_from_shaders {
+            true => PipelineLayout::from_shaders(Arc::clone(&context), &reflections)?,
+            false => {
+                for reflection in reflections.iter() {
+                    reflection.check_push_constant_ranges(&self.push_constant_ranges)?;
+                }
+                PipelineLayout::new(Arc::clone(&context), &self.set_layouts, &self.push_constant_ranges)?
+            }
+        };
+
+        let stages = self.stages.iter()
+            .zip(entry_points.iter())
+            .map(|((stage, module, _), entry_point)| {
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(*stage)
+                    .module(*module.vk_shader_module())
+                    .name(entry_point)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&self.vertex_bindings)
+            .vertex_attribute_descriptions(&self.vertex_attributes);
+
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(self.topology)
+            .primitive_restart_enable(self.primitive_restart);
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let (depth_bias_constant, depth_bias_clamp, depth_bias_slope) = self.depth_bias.unwrap_or_default();
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(self.polygon_mode)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .line_width(self.line_width)
+            .depth_bias_enable(self.depth_bias.is_some())
+            .depth_bias_constant_factor(depth_bias_constant)
+            .depth_bias_clamp(depth_bias_clamp)
+            .depth_bias_slope_factor(depth_bias_slope);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(samples);
+
+        let (stencil_front, stencil_back) = self.stencil.unwrap_or_default();
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_write)
+            .depth_compare_op(self.depth_compare_op)
+            .stencil_test_enable(self.stencil.is_some())
+            .front(stencil_front)
+            .back(stencil_back)
+            .max_depth_bounds(1.0);
+
+        let mut color_blends = self.color_blends.clone();
+        color_blends.resize(color_count, ColorBlend::Opaque.vk_attachment_state());
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blends);
+
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&self.dynamic_states);
+
+        let mut rendering_info = rendering_layout.map(RenderingLayout::vk_pipeline_rendering_info);
+
+        let mut pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(layout.vk_pipeline_layout())
+            .render_pass(render_pass)
+            .subpass(subpass);
+        if let Some(rendering_info) = rendering_info.as_mut() {
+            pipeline_info = pipeline_info.push_next(rendering_info);
+        }
+
+        let pipeline = unsafe {
+            context.device().vk_device()
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+        }.map_err(|(_, result)| result).map_vk_err("vkCreateGraphicsPipelines")?[0];
+
+        Ok(GraphicsPipeline {
+            context,
+            pipeline,
+            layout,
+        })
+    }
+
+    /// Reflections of the stages, checked against the vertex input and the `color_count` color attachments.
+    fn reflect_stages(&self, color_count: usize) -> VisionResult<Vec<&'a ShaderReflection>> {
+        let mut reflections = vec![];
+        for (stage, module, entry_point) in self.stages.iter() {
+            let reflection = module.require_reflection(entry_point)?;
+            if reflection.stage() != *stage {
+                return Err(VisionError::ShaderMismatch(format!(
+                    "entry point '{}' is a {:?} shader, not {:?}", reflection.entry_point(), reflection.stage(), stage)));
+            }
+            match *stage {
+                vk::ShaderStageFlags::VERTEX => reflection.check_vertex_attributes(&self.vertex_attributes)?,
+                vk::ShaderStageFlags::FRAGMENT => reflection.check_color_attachments(color_count)?,
+                _ => {}
+            }
+            reflections.push(reflection);
+        }
+        Ok(reflections)
+    }
+}
+
+impl Default for GraphicsPipelineBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
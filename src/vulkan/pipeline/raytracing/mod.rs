@@ -0,0 +1,5 @@
+pub use self::pipeline::{RayTracingPipeline, RayTracingPipelineBuilder};
+pub use self::shader_binding_table::ShaderBindingTable;
+
+mod pipeline;
+mod shader_binding_table;
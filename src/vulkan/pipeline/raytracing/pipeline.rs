@@ -0,0 +1,309 @@
+use std::ffi::CString;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::{Context, ShaderModule, ShaderReflection, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::pipeline::PipelineLayout;
+use crate::vulkan::pipeline::raytracing::ShaderBindingTable;
+
+/// Pipeline of `VK_KHR_ray_tracing_pipeline` with its shader binding table. The device needs the
+/// `ray_tracing_pipeline` and `vulkan_12.buffer_device_address` features.
+pub struct RayTracingPipeline {
+    context: Arc<Context>,
+    pipeline: vk::Pipeline,
+    layout: PipelineLayout,
+    shader_binding_table: ShaderBindingTable,
+}
+
+impl RayTracingPipeline {
+    pub fn builder<'a>() -> RayTracingPipelineBuilder<'a> {
+        RayTracingPipelineBuilder::new()
+    }
+
+    pub fn cmd_bind(&self, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            self.context.device().vk_device()
+                .cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::RAY_TRACING_KHR, self.pipeline);
+        }
+    }
+
+    pub fn cmd_bind_descriptor_sets(&self,
+                                    command_buffer: vk::CommandBuffer,
+                                    first_set: u32,
+                                    descriptor_sets: &[vk::DescriptorSet]) {
+        unsafe {
+            self.context.device().vk_device().cmd_bind_descriptor_sets(command_buffer,
+                                                                       vk::PipelineBindPoint::RAY_TRACING_KHR,
+                                                                       self.layout.vk_pipeline_layout(),
+                                                                       first_set,
+                                                                       descriptor_sets,
+                                                                       &[]);
+        }
+    }
+
+    /// Launches `extent` rays starting at raygen group `raygen_index`, the pipeline has to be bound.
+    pub fn cmd_trace_rays(&self,
+                          command_buffer: vk::CommandBuffer,
+                          raygen_index: u32,
+                          extent: [u32; 3]) -> VisionResult<()> {
+        let ray_tracing_pipeline = self.context.device().ray_tracing_pipeline()
+            .expect("pipeline was created with ray tracing enabled");
+        let table = &self.shader_binding_table;
+        let raygen_region = table.raygen_region(raygen_index)?;
+        unsafe {
+            ray_tracing_pipeline.cmd_trace_rays(command_buffer,
+                                                &raygen_region,
+                                                &table.miss_region(),
+                                                &table.hit_region(),
+                                                &table.callable_region(),
+                                                extent[0],
+                                                extent[1],
+                                                extent[2]);
+        }
+        Ok(())
+    }
+
+    pub fn layout(&self) -> &PipelineLayout {
+        &self.layout
+    }
+
+    pub fn shader_binding_table(&self) -> &ShaderBindingTable {
+        &self.shader_binding_table
+    }
+
+    pub fn vk_pipeline(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+}
+
+impl Drop for RayTracingPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device().vk_device().destroy_pipeline(self.pipeline, None);
+        }
+    }
+}
+
+/// Hit group, triangle geometry unless it has an intersection shader.
+#[derive(Clone, Copy)]
+struct HitGroup<'a> {
+    closest_hit: Option<&'a ShaderModule>,
+    any_hit: Option<&'a ShaderModule>,
+    intersection: Option<&'a ShaderModule>,
+}
+
+/// Describes a ray tracing pipeline, shaders use their `main` entry point. Groups of each kind are numbered in the
+/// order they are added, e.g. the `sbtRecordOffset` and `missIndex` of `traceRayEXT`.
+pub struct RayTracingPipelineBuilder<'a> {
+    raygen: Vec<&'a ShaderModule>,
+    miss: Vec<&'a ShaderModule>,
+    hit_groups: Vec<HitGroup<'a>>,
+    callable: Vec<&'a ShaderModule>,
+    max_recursion_depth: u32,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    layout_from_shaders: bool,
+}
+
+impl<'a> RayTracingPipelineBuilder<'a> {
+    pub fn new() -> Self {
+        Self {
+            raygen: vec![],
+            miss: vec![],
+            hit_groups: vec![],
+            callable: vec![],
+            max_recursion_depth: 1,
+            set_layouts: vec![],
+            push_constant_ranges: vec![],
+            layout_from_shaders: false,
+        }
+    }
+
+    pub fn raygen(mut self, module: &'a ShaderModule) -> Self {
+        self.raygen.push(module);
+        self
+    }
+
+    pub fn miss(mut self, module: &'a ShaderModule) -> Self {
+        self.miss.push(module);
+        self
+    }
+
+    /// Adds a triangle hit group with only a closest hit shader.
+    pub fn closest_hit(self, module: &'a ShaderModule) -> Self {
+        self.hit_group(Some(module), None, None)
+    }
+
+    /// Adds a hit group, groups with an intersection shader are procedural hit groups for AABB geometry.
+    pub fn hit_group(mut self,
+                     closest_hit: Option<&'a ShaderModule>,
+                     any_hit: Option<&'a ShaderModule>,
+                     intersection: Option<&'a ShaderModule>) -> Self {
+        self.hit_groups.push(HitGroup { closest_hit, any_hit, intersection });
+        self
+    }
+
+    pub fn callable(mut self, module: &'a ShaderModule) -> Self {
+        self.callable.push(module);
+        self
+    }
+
+    /// Clamped to the `maxRayRecursionDepth` of the device.
+    pub fn max_recursion_depth(mut self, depth: u32) -> Self {
+        self.max_recursion_depth = depth;
+        self
+    }
+
+    pub fn descriptor_set_layout(mut self, set_layout: vk::DescriptorSetLayout) -> Self {
+        self.set_layouts.push(set_layout);
+        self
+    }
+
+    pub fn push_constant_range(mut self, stages: vk::ShaderStageFlags, offset: u32, size: u32) -> Self {
+        self.push_constant_ranges.push(vk::PushConstantRange { stage_flags: stages, offset, size });
+        self
+    }
+
+    /// Creates the pipeline layout from the descriptor bindings and push constants the shaders declare, instead of
+    /// the set layouts and ranges added with `descriptor_set_layout` and `push_constant_range`.
+    pub fn layout_from_shaders(mut self) -> Self {
+        self.layout_from_shaders = true;
+        self
+    }
+
+    pub fn build(self, context: Arc<Context>) -> VisionResult<RayTracingPipeline> {
+        let ray_tracing_pipeline = context.device().ray_tracing_pipeline()
+            .ok_or(VisionError::MissingFeature("ray_tracing_pipeline.ray_tracing_pipeline"))?;
+        let reflections = self.reflect_stages()?;
+        let layout = match self.layout_from_shaders {
+            true => PipelineLayout::from_shaders(Arc::clone(&context), &reflections)?,
+            false => {
+                for reflection in reflections.iter() {
+                    reflection.check_push_constant_ranges(&self.push_constant_ranges)?;
+                }
+                PipelineLayout::new(Arc::clone(&context), &self.set_layouts, &self.push_constant_ranges)?
+            }
+        };
+
+        let entry_point = CString::new("main").expect("entry point has no nul bytes");
+        let mut stages = vec![];
+        let mut add_stage = |stage: vk::ShaderStageFlags, module: Option<&ShaderModule>| match module {
+            Some(module) => {
+                stages.push(vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(stage)
+                    .module(*module.vk_shader_module())
+                    .name(&entry_point)
+                    .build());
+                stages.len() as u32 - 1
+            }
+            None => vk::SHADER_UNUSED_KHR,
+        };
+
+        let general_group = |shader: u32| {
+            vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
+                .general_shader(shader)
+                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR)
+                .build()
+        };
+
+        // Groups are ordered by kind as the shader binding table expects.
+        let mut groups = vec![];
+        for module in self.raygen.iter() {
+            groups.push(general_group(add_stage(vk::ShaderStageFlags::RAYGEN_KHR, Some(module))));
+        }
+        for module in self.miss.iter() {
+            groups.push(general_group(add_stage(vk::ShaderStageFlags::MISS_KHR, Some(module))));
+        }
+        for hit_group in self.hit_groups.iter() {
+            let ty = match hit_group.intersection {
+                Some(_) => vk::RayTracingShaderGroupTypeKHR::PROCEDURAL_HIT_GROUP,
+                None => vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP,
+            };
+            groups.push(vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                .ty(ty)
+                .general_shader(vk::SHADER_UNUSED_KHR)
+                .closest_hit_shader(add_stage(vk::ShaderStageFlags::CLOSEST_HIT_KHR, hit_group.closest_hit))
+                .any_hit_shader(add_stage(vk::ShaderStageFlags::ANY_HIT_KHR, hit_group.any_hit))
+                .intersection_shader(add_stage(vk::ShaderStageFlags::INTERSECTION_KHR, hit_group.intersection))
+                .build());
+        }
+        for module in self.callable.iter() {
+            groups.push(general_group(add_stage(vk::ShaderStageFlags::CALLABLE_KHR, Some(module))));
+        }
+
+        let max_recursion_depth = self.max_recursion_depth
+            .min(context.ray_tracing_pipeline_properties().max_ray_recursion_depth);
+        let pipeline_info = vk::RayTracingPipelineCreateInfoKHR::builder()
+            .stages(&stages)
+            .groups(&groups)
+            .max_pipeline_ray_recursion_depth(max_recursion_depth)
+            .layout(layout.vk_pipeline_layout());
+
+        let pipeline = unsafe {
+            ray_tracing_pipeline.create_ray_tracing_pipelines(vk::DeferredOperationKHR::null(),
+                                                              vk::PipelineCache::null(),
+                                                              &[pipeline_info.build()],
+                                                              None)
+        }.map_vk_err("vkCreateRayTracingPipelinesKHR")?[0];
+
+        let group_counts = [
+            self.raygen.len() as u32,
+            self.miss.len() as u32,
+            self.hit_groups.len() as u32,
+            self.callable.len() as u32,
+        ];
+        let shader_binding_table = match ShaderBindingTable::new(Arc::clone(&context), pipeline, group_counts) {
+            Ok(shader_binding_table) => shader_binding_table,
+            Err(error) => {
+                unsafe { context.device().vk_device().destroy_pipeline(pipeline, None) };
+                return Err(error);
+            }
+        };
+
+        Ok(RayTracingPipeline {
+            context,
+            pipeline,
+            layout,
+            shader_binding_table,
+        })
+    }
+
+    /// Reflections of the `main` entry points of all shaders, checked against the stage they are used for.
+    fn reflect_stages(&self) -> VisionResult<Vec<&'a ShaderReflection>> {
+        let hit_stages = self.hit_groups.iter().flat_map(|hit_group| {
+            [
+                (vk::ShaderStageFlags::CLOSEST_HIT_KHR, hit_group.closest_hit),
+                (vk::ShaderStageFlags::ANY_HIT_KHR, hit_group.any_hit),
+                (vk::ShaderStageFlags::INTERSECTION_KHR, hit_group.intersection),
+            ]
+        });
+        let stages = self.raygen.iter().map(|module| (vk::ShaderStageFlags::RAYGEN_KHR, Some(*module)))
+            .chain(self.miss.iter().map(|module| (vk::ShaderStageFlags::MISS_KHR, Some(*module))))
+            .chain(hit_stages)
+            .chain(self.callable.iter().map(|module| (vk::ShaderStageFlags::CALLABLE_KHR, Some(*module))));
+
+        let mut reflections = vec![];
+        for (stage, module) in stages {
+            if let Some(module) = module {
+                let reflection = module.require_reflection("main")?;
+                if reflection.stage() != stage {
+                    return Err(VisionError::ShaderMismatch(format!(
+                        "entry point 'main' is a {:?} shader, not {:?}", reflection.stage(), stage)));
+                }
+                reflections.push(reflection);
+            }
+        }
+        Ok(reflections)
+    }
+}
+
+impl Default for RayTracingPipelineBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
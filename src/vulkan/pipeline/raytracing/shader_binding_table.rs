@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::{Buffer, Context, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::util::align_up;
+
+/// Shader group handles of a ray tracing pipeline, one record per group without inline data. Groups are expected in
+/// the order raygen, miss, hit and callable, each kind forms a region starting at `shaderGroupBaseAlignment`. Raygen
+/// records are traced one at a time, so each of them starts at its own base aligned slot.
+pub struct ShaderBindingTable {
+    buffer: Buffer,
+    raygen_count: u32,
+    raygen: vk::StridedDeviceAddressRegionKHR,
+    miss: vk::StridedDeviceAddressRegionKHR,
+    hit: vk::StridedDeviceAddressRegionKHR,
+    callable: vk::StridedDeviceAddressRegionKHR,
+}
+
+impl ShaderBindingTable {
+    /// `group_counts` are the number of raygen, miss, hit and callable groups of `pipeline`.
+    pub(crate) fn new(context: Arc<Context>, pipeline: vk::Pipeline, group_counts: [u32; 4]) -> VisionResult<Self> {
+        let ray_tracing_pipeline = context.device().ray_tracing_pipeline()
+            .ok_or(VisionError::MissingFeature("ray_tracing_pipeline.ray_tracing_pipeline"))?;
+        let properties = context.ray_tracing_pipeline_properties();
+        let handle_size = properties.shader_group_handle_size as vk::DeviceSize;
+        let stride = align_up(handle_size, properties.shader_group_handle_alignment as vk::DeviceSize);
+        let base_alignment = properties.shader_group_base_alignment as vk::DeviceSize;
+
+        let group_count = group_counts.iter().sum::<u32>();
+        let handles = unsafe {
+            ray_tracing_pipeline.get_ray_tracing_shader_group_handles(pipeline,
+                                                                      0,
+                                                                      group_count,
+                                                                      group_count as usize * handle_size as usize)
+        }.map_vk_err("vkGetRayTracingShaderGroupHandlesKHR")?;
+
+        let strides = [align_up(stride, base_alignment), stride, stride, stride];
+        let mut region_sizes = [0; 4];
+        for (kind, count) in group_counts.iter().enumerate() {
+            region_sizes[kind] = align_up(*count as vk::DeviceSize * strides[kind], base_alignment);
+        }
+        let table_size = region_sizes.iter().sum::<vk::DeviceSize>();
+
+        // The buffer is padded so that the table can start at an address aligned to the base alignment.
+        let buffer = Buffer::create(Arc::clone(&context),
+                                    (table_size + base_alignment) as usize,
+                                    vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR
+                                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                                        | vk::BufferUsageFlags::TRANSFER_DST,
+                                    vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        let buffer_address = buffer.device_address();
+        let table_address = align_up(buffer_address, base_alignment);
+
+        let mut data = vec![0u8; buffer.len()];
+        let mut regions = [vk::StridedDeviceAddressRegionKHR::default(); 4];
+        let mut region_offset = table_address - buffer_address;
+        let mut first_group = 0;
+        for (kind, count) in group_counts.iter().enumerate() {
+            for group in 0..*count as vk::DeviceSize {
+                let handle = (first_group + group) as usize * handle_size as usize;
+                let record = (region_offset + group * strides[kind]) as usize;
+                data[record..record + handle_size as usize].copy_from_slice(&handles[handle..handle + handle_size as usize]);
+            }
+            if *count > 0 {
+                regions[kind] = vk::StridedDeviceAddressRegionKHR {
+                    device_address: buffer_address + region_offset,
+                    stride: strides[kind],
+                    size: region_sizes[kind],
+                };
+            }
+            region_offset += region_sizes[kind];
+            first_group += *count as vk::DeviceSize;
+        }
+        buffer.upload(&data)?;
+
+        let [raygen, miss, hit, callable] = regions;
+        Ok(Self {
+            buffer,
+            raygen_count: group_counts[0],
+            raygen,
+            miss,
+            hit,
+            callable,
+        })
+    }
+
+    /// Region of the single raygen record `index` as required by `vkCmdTraceRaysKHR`, fails if `index` is not a
+    /// raygen group of the pipeline.
+    pub fn raygen_region(&self, index: u32) -> VisionResult<vk::StridedDeviceAddressRegionKHR> {
+        if index >= self.raygen_count {
+            return Err(VisionError::RaygenIndexOutOfRange { index, count: self.raygen_count });
+        }
+        Ok(vk::StridedDeviceAddressRegionKHR {
+            device_address: self.raygen.device_address + index as vk::DeviceSize * self.raygen.stride,
+            stride: self.raygen.stride,
+            size: self.raygen.stride,
+        })
+    }
+
+    pub fn miss_region(&self) -> vk::StridedDeviceAddressRegionKHR {
+        self.miss
+    }
+
+    pub fn hit_region(&self) -> vk::StridedDeviceAddressRegionKHR {
+        self.hit
+    }
+
+    pub fn callable_region(&self) -> vk::StridedDeviceAddressRegionKHR {
+        self.callable
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+}
@@ -1,54 +1,166 @@
 use std::sync::Arc;
 
-use ash::version::DeviceV1_0;
 use ash::vk;
-use ash::vk::RenderPass as VkRenderPass;
 
-use crate::vulkan::{Context, Device, Image};
-use crate::vulkan::image::ImageParameters;
-use crate::vulkan::texture::Texture;
+use crate::vulkan::{Attachment, Context, Framebuffer, Image, ImageParameters, RenderPassBuilder, Subpass, Texture,
+                    VisionError, VisionResult};
 
 pub struct RenderPass {
     context: Arc<Context>,
+    render_pass: vk::RenderPass,
+    description: RenderPassBuilder,
+    // Color and depth attachments owned by render passes created with `create`.
+    targets: Option<RenderTargets>,
+}
+
+struct RenderTargets {
     color_attachment: Option<Texture>,
     depth_attachment: Texture,
-    render_pass: vk::RenderPass,
+
+    format: vk::Format,
+    depth_format: vk::Format,
+    msaa_samples: vk::SampleCountFlags,
+    extent: vk::Extent2D,
+    present_layout: vk::ImageLayout,
 }
 
 impl RenderPass {
+    pub fn builder() -> RenderPassBuilder {
+        RenderPassBuilder::new()
+    }
+
+    pub(crate) fn from_raw(context: Arc<Context>, render_pass: vk::RenderPass, description: RenderPassBuilder) -> Self {
+        Self {
+            context,
+            render_pass,
+            description,
+            targets: None,
+        }
+    }
+
+    /// Render pass presenting to a swapchain with a depth attachment and an optional multisampled color attachment,
+    /// both owned by the render pass. The presented attachment ends in `present_layout`, the layout of the target
+    /// as returned by `PresentTarget::present_layout`.
     pub fn create(context: Arc<Context>,
                   extent: vk::Extent2D,
                   format: vk::Format,
                   depth_format: vk::Format,
-                  msaa_samples: vk::SampleCountFlags) -> Self {
-        let render_pass = create_render_pass(context.device(), format, depth_format, msaa_samples);
-
+                  msaa_samples: vk::SampleCountFlags,
+                  present_layout: vk::ImageLayout) -> VisionResult<Self> {
         let color_attachment = match msaa_samples {
             vk::SampleCountFlags::TYPE_1 => None,
-            _ => Some(create_color_texture(&context, format, extent, msaa_samples)),
+            _ => Some(create_color_texture(&context, format, extent, msaa_samples)?),
         };
 
-        let depth_attachment = create_depth_texture(&context, depth_format, extent, msaa_samples);
+        let depth_attachment = create_depth_texture(&context, depth_format, extent, msaa_samples)?;
 
-        Self {
-            context,
+        let mut render_pass = presentation_description(format, depth_format, msaa_samples, present_layout)
+            .build(context)?;
+        render_pass.targets = Some(RenderTargets {
             color_attachment,
             depth_attachment,
-            render_pass,
+            format,
+            depth_format,
+            msaa_samples,
+            extent,
+            present_layout,
+        });
+        Ok(render_pass)
+    }
+
+    /// Recreates the attachments for `extent`, e.g. before recreating the swapchain after a resize. The attachments
+    /// must not be in use by frames in flight.
+    pub fn resize(&mut self, extent: vk::Extent2D) -> VisionResult<()> {
+        let targets = self.targets.as_mut().ok_or(VisionError::MissingRenderTargets)?;
+        if extent == targets.extent {
+            return Ok(());
         }
+
+        targets.color_attachment = match targets.msaa_samples {
+            vk::SampleCountFlags::TYPE_1 => None,
+            _ => Some(create_color_texture(&self.context, targets.format, extent, targets.msaa_samples)?),
+        };
+        targets.depth_attachment = create_depth_texture(&self.context,
+                                                        targets.depth_format,
+                                                        extent,
+                                                        targets.msaa_samples)?;
+        targets.extent = extent;
+        Ok(())
+    }
+
+    /// Recreates the render pass for a new color format, e.g. after the swapchain configuration changed. The render
+    /// pass must not be in use by frames in flight.
+    pub fn set_format(&mut self, format: vk::Format) -> VisionResult<()> {
+        let targets = self.targets.as_mut().ok_or(VisionError::MissingRenderTargets)?;
+        if format == targets.format {
+            return Ok(());
+        }
+
+        let description = presentation_description(format,
+                                                   targets.depth_format,
+                                                   targets.msaa_samples,
+                                                   targets.present_layout);
+        let render_pass = description.create_vk_render_pass(&self.context)?;
+        if targets.msaa_samples != vk::SampleCountFlags::TYPE_1 {
+            let texture = create_color_texture(&self.context, format, targets.extent, targets.msaa_samples)?;
+            targets.color_attachment = Some(texture);
+        }
+        unsafe { self.context.device().vk_device().destroy_render_pass(self.render_pass, None) };
+        self.render_pass = render_pass;
+        self.description = description;
+        targets.format = format;
+        Ok(())
+    }
+
+    /// Creates a framebuffer per target view, e.g. of a swapchain, combined with the depth and multisampled color
+    /// attachments of the render pass. The views have to be of the render pass format and extent.
+    pub fn create_framebuffers(&self, target_views: &[vk::ImageView]) -> VisionResult<Vec<Framebuffer>> {
+        let targets = self.targets.as_ref().ok_or(VisionError::MissingRenderTargets)?;
+        target_views.iter()
+            .map(|target_view| {
+                let attachments = match &targets.color_attachment {
+                    Some(texture) => vec![texture.view(), targets.depth_attachment.view(), *target_view],
+                    None => vec![*target_view, targets.depth_attachment.view()],
+                };
+                self.create_framebuffer(&attachments, targets.extent)
+            })
+            .collect()
+    }
+
+    /// Creates a framebuffer with one view per attachment in the order they were added to the builder.
+    pub fn create_framebuffer(&self, attachments: &[vk::ImageView], extent: vk::Extent2D) -> VisionResult<Framebuffer> {
+        Framebuffer::new(Arc::clone(&self.context), self.render_pass, attachments, extent)
+    }
+
+    pub(crate) fn description(&self) -> &RenderPassBuilder {
+        &self.description
+    }
+
+    pub fn attachments(&self) -> &[Attachment] {
+        self.description.attachments()
     }
 
     pub fn color_attachment(&self) -> Option<&Texture> {
-        self.color_attachment.as_ref()
+        self.targets.as_ref().and_then(|targets| targets.color_attachment.as_ref())
     }
 
-    pub fn depth_attachment(&self) -> &Texture {
-        &self.depth_attachment
+    pub fn depth_attachment(&self) -> Option<&Texture> {
+        self.targets.as_ref().map(|targets| &targets.depth_attachment)
     }
 
     pub fn vk_render_pass(&self) -> vk::RenderPass {
         self.render_pass
     }
+
+    /// Extent of the owned attachments of render passes created with `create`.
+    pub fn extent(&self) -> Option<vk::Extent2D> {
+        self.targets.as_ref().map(|targets| targets.extent)
+    }
+
+    /// Color format of render passes created with `create`.
+    pub fn format(&self) -> Option<vk::Format> {
+        self.targets.as_ref().map(|targets| targets.format)
+    }
 }
 
 impl Drop for RenderPass {
@@ -62,96 +174,27 @@ impl Drop for RenderPass {
     }
 }
 
-
-fn create_render_pass(
-    device: &Device,
-    format: vk::Format,
-    depth_format: vk::Format,
-    msaa_samples: vk::SampleCountFlags,
-) -> VkRenderPass {
-    let final_image_layout = match msaa_samples {
-        vk::SampleCountFlags::TYPE_1 => vk::ImageLayout::PRESENT_SRC_KHR,
-        _ => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-    };
-
-    let mut attachment_descriptions = vec![
-        vk::AttachmentDescription::builder()
-            .format(format)
-            .samples(msaa_samples)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(final_image_layout)
-            .build(),
-        vk::AttachmentDescription::builder()
-            .format(depth_format)
-            .samples(msaa_samples)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-            .build(),
-    ];
-    if msaa_samples != vk::SampleCountFlags::TYPE_1 {
-        attachment_descriptions.push(
-            vk::AttachmentDescription::builder()
-                .format(format)
-                .samples(vk::SampleCountFlags::TYPE_1)
+/// Attachments are `[color, depth]`, with multisampling `[msaa color, depth, resolve]`, the presented attachment
+/// ends in `present_layout`.
+fn presentation_description(format: vk::Format,
+                            depth_format: vk::Format,
+                            msaa_samples: vk::SampleCountFlags,
+                            present_layout: vk::ImageLayout) -> RenderPassBuilder {
+    let depth = Attachment::depth(depth_format).samples(msaa_samples);
+    if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+        RenderPass::builder()
+            .attachment(Attachment::color(format).final_layout(present_layout))
+            .attachment(depth)
+            .subpass(Subpass::new().color(0).depth(1))
+    } else {
+        RenderPass::builder()
+            .attachment(Attachment::color(format).samples(msaa_samples))
+            .attachment(depth)
+            .attachment(Attachment::color(format)
                 .load_op(vk::AttachmentLoadOp::DONT_CARE)
-                .store_op(vk::AttachmentStoreOp::STORE)
-                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                .build(),
-        );
-    }
-
-    let color_attachment_references = [vk::AttachmentReference::builder()
-        .attachment(0)
-        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-        .build()];
-
-    let depth_attachment_reference = vk::AttachmentReference::builder()
-        .attachment(1)
-        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
-
-    let resolve_attachment_references = [vk::AttachmentReference::builder()
-        .attachment(2)
-        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-        .build()];
-
-    let subpass_descriptions = {
-        let mut subpass_desc = vk::SubpassDescription::builder()
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-            .color_attachments(&color_attachment_references)
-            .depth_stencil_attachment(&depth_attachment_reference);
-
-        if msaa_samples != vk::SampleCountFlags::TYPE_1 {
-            subpass_desc = subpass_desc.resolve_attachments(&resolve_attachment_references);
-        }[subpass_desc.build()]
-    };
-
-    let subpass_dependency = vk::SubpassDependency::builder()
-        .src_subpass(vk::SUBPASS_EXTERNAL)
-        .dst_subpass(0)
-        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-        .src_access_mask(vk::AccessFlags::empty())
-        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-        .dst_access_mask(
-            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-        )
-        .build();
-    let subpass_dependencies = [subpass_dependency];
-
-    let render_pass_info = vk::RenderPassCreateInfo::builder()
-        .attachments(&attachment_descriptions)
-        .subpasses(&subpass_descriptions)
-        .dependencies(&subpass_dependencies);
-
-    unsafe { device.vk_device().create_render_pass(&render_pass_info, None).expect("Failed to create render pass") }
+                .final_layout(present_layout))
+            .subpass(Subpass::new().color_resolved(0, 2).depth(1))
+    }
 }
 
 fn create_color_texture(
@@ -159,7 +202,7 @@ fn create_color_texture(
     format: vk::Format,
     extent: vk::Extent2D,
     msaa_samples: vk::SampleCountFlags,
-) -> Texture {
+) -> VisionResult<Texture> {
     let image = Image::create(
         Arc::clone(context),
         ImageParameters {
@@ -171,16 +214,16 @@ fn create_color_texture(
                 | vk::ImageUsageFlags::COLOR_ATTACHMENT,
             ..Default::default()
         },
-    );
+    )?;
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
         vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-    );
+    )?;
 
-    let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
+    let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR)?;
 
-    Texture::new(Arc::clone(context), image, view, None)
+    Ok(Texture::new(Arc::clone(context), image, view, None))
 }
 
 fn create_depth_texture(
@@ -188,7 +231,7 @@ fn create_depth_texture(
     format: vk::Format,
     extent: vk::Extent2D,
     msaa_samples: vk::SampleCountFlags,
-) -> Texture {
+) -> VisionResult<Texture> {
     let image = Image::create(
         Arc::clone(context),
         ImageParameters {
@@ -199,14 +242,14 @@ fn create_depth_texture(
             usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
             ..Default::default()
         },
-    );
+    )?;
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
         vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-    );
+    )?;
 
-    let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::DEPTH);
+    let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::DEPTH)?;
 
-    Texture::new(Arc::clone(context), image, view, None)
+    Ok(Texture::new(Arc::clone(context), image, view, None))
 }
\ No newline at end of file
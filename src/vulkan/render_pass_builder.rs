@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::{Context, RenderPass, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::image::format_aspect_mask;
+
+/// Attachment of a render pass, referenced by subpasses through the order it was added to the builder.
+#[derive(Clone, Copy, Debug)]
+pub struct Attachment {
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp,
+    stencil_load_op: vk::AttachmentLoadOp,
+    stencil_store_op: vk::AttachmentStoreOp,
+    initial_layout: vk::ImageLayout,
+    final_layout: vk::ImageLayout,
+}
+
+impl Attachment {
+    /// Cleared and stored, ends in `COLOR_ATTACHMENT_OPTIMAL`.
+    pub fn color(format: vk::Format) -> Self {
+        Self {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }
+    }
+
+    /// Cleared and discarded, ends in `DEPTH_STENCIL_ATTACHMENT_OPTIMAL`.
+    pub fn depth(format: vk::Format) -> Self {
+        Self {
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ..Self::color(format)
+        }
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn load_op(mut self, load_op: vk::AttachmentLoadOp) -> Self {
+        self.load_op = load_op;
+        self
+    }
+
+    pub fn store_op(mut self, store_op: vk::AttachmentStoreOp) -> Self {
+        self.store_op = store_op;
+        self
+    }
+
+    pub fn stencil_ops(mut self, load_op: vk::AttachmentLoadOp, store_op: vk::AttachmentStoreOp) -> Self {
+        self.stencil_load_op = load_op;
+        self.stencil_store_op = store_op;
+        self
+    }
+
+    pub fn initial_layout(mut self, layout: vk::ImageLayout) -> Self {
+        self.initial_layout = layout;
+        self
+    }
+
+    pub fn final_layout(mut self, layout: vk::ImageLayout) -> Self {
+        self.final_layout = layout;
+        self
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn sample_count(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    fn vk_attachment_description(&self) -> vk::AttachmentDescription {
+        vk::AttachmentDescription::builder()
+            .format(self.format)
+            .samples(self.samples)
+            .load_op(self.load_op)
+            .store_op(self.store_op)
+            .stencil_load_op(self.stencil_load_op)
+            .stencil_store_op(self.stencil_store_op)
+            .initial_layout(self.initial_layout)
+            .final_layout(self.final_layout)
+            .build()
+    }
+}
+
+/// Graphics subpass referencing attachments by index.
+#[derive(Clone, Debug, Default)]
+pub struct Subpass {
+    color_attachments: Vec<vk::AttachmentReference>,
+    resolve_attachments: Vec<vk::AttachmentReference>,
+    input_attachments: Vec<vk::AttachmentReference>,
+    depth_attachment: Option<vk::AttachmentReference>,
+    preserve_attachments: Vec<u32>,
+}
+
+impl Subpass {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a color output, the next fragment shader output location.
+    pub fn color(mut self, attachment: u32) -> Self {
+        self.color_attachments.push(reference(attachment, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL));
+        self.resolve_attachments.push(reference(vk::ATTACHMENT_UNUSED, vk::ImageLayout::UNDEFINED));
+        self
+    }
+
+    /// Adds a multisampled color output resolved into `resolve_attachment` at the end of the subpass.
+    pub fn color_resolved(mut self, attachment: u32, resolve_attachment: u32) -> Self {
+        self.color_attachments.push(reference(attachment, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL));
+        self.resolve_attachments.push(reference(resolve_attachment, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL));
+        self
+    }
+
+    /// Adds an input attachment read by the fragment shader, e.g. a G-buffer written by a previous subpass. Depth and
+    /// stencil attachments are read in `DEPTH_STENCIL_READ_ONLY_OPTIMAL`, all others in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn input(mut self, attachment: u32) -> Self {
+        self.input_attachments.push(reference(attachment, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL));
+        self
+    }
+
+    pub fn depth(mut self, attachment: u32) -> Self {
+        self.depth_attachment = Some(reference(attachment, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL));
+        self
+    }
+
+    /// Depth attachment that is tested against but not written, it can be read as input attachment at the same time.
+    pub fn depth_read_only(mut self, attachment: u32) -> Self {
+        self.depth_attachment = Some(reference(attachment, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL));
+        self
+    }
+
+    /// Keeps the contents of an attachment this subpass does not use for later subpasses.
+    pub fn preserve(mut self, attachment: u32) -> Self {
+        self.preserve_attachments.push(attachment);
+        self
+    }
+
+    fn has_resolve_attachments(&self) -> bool {
+        self.resolve_attachments.iter().any(|resolve| resolve.attachment != vk::ATTACHMENT_UNUSED)
+    }
+}
+
+/// Describes a render pass with any number of attachments, subpasses and dependencies. An external dependency into
+/// the first subpass covering color and depth writes is added unless one from `SUBPASS_EXTERNAL` to subpass 0 is
+/// added explicitly.
+#[derive(Clone, Debug, Default)]
+pub struct RenderPassBuilder {
+    attachments: Vec<Attachment>,
+    subpasses: Vec<Subpass>,
+    dependencies: Vec<vk::SubpassDependency>,
+}
+
+impl RenderPassBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an attachment, its index is the number of attachments added before.
+    pub fn attachment(mut self, attachment: Attachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    pub fn subpass(mut self, subpass: Subpass) -> Self {
+        self.subpasses.push(subpass);
+        self
+    }
+
+    /// Adds a dependency, one from `SUBPASS_EXTERNAL` to subpass 0 replaces the default external dependency.
+    pub fn dependency(mut self, dependency: vk::SubpassDependency) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    /// Adds a dependency making writes of `src_subpass` visible to reads of input attachments in `dst_subpass`.
+    pub fn input_dependency(self, src_subpass: u32, dst_subpass: u32) -> Self {
+        self.dependency(vk::SubpassDependency {
+            src_subpass,
+            dst_subpass,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::INPUT_ATTACHMENT_READ,
+            dependency_flags: vk::DependencyFlags::BY_REGION,
+        })
+    }
+
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    pub(crate) fn subpass_count(&self) -> u32 {
+        self.subpasses.len() as u32
+    }
+
+    pub(crate) fn subpass_color_count(&self, subpass: u32) -> usize {
+        self.subpasses[subpass as usize].color_attachments.len()
+    }
+
+    /// Sample count of the attachments rendered to in `subpass`.
+    pub(crate) fn subpass_samples(&self, subpass: u32) -> vk::SampleCountFlags {
+        let subpass = &self.subpasses[subpass as usize];
+        subpass.color_attachments.iter()
+            .chain(subpass.depth_attachment.as_ref())
+            .map(|reference| self.attachments[reference.attachment as usize].samples)
+            .next()
+            .unwrap_or(vk::SampleCountFlags::TYPE_1)
+    }
+
+    pub fn build(self, context: Arc<Context>) -> VisionResult<RenderPass> {
+        let render_pass = self.create_vk_render_pass(&context)?;
+        Ok(RenderPass::from_raw(context, render_pass, self))
+    }
+
+    pub(crate) fn create_vk_render_pass(&self, context: &Context) -> VisionResult<vk::RenderPass> {
+        let attachment_descriptions = self.attachments.iter()
+            .map(Attachment::vk_attachment_description)
+            .collect::<Vec<_>>();
+
+        let input_attachments = self.subpasses.iter()
+            .map(|subpass| {
+                subpass.input_attachments.iter().map(|input| self.input_reference(input)).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let subpass_descriptions = self.subpasses.iter()
+            .zip(input_attachments.iter())
+            .map(|(subpass, input_attachments)| {
+                let mut description = vk::SubpassDescription::builder()
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                    .color_attachments(&subpass.color_attachments)
+                    .input_attachments(input_attachments)
+                    .preserve_attachments(&subpass.preserve_attachments);
+                if subpass.has_resolve_attachments() {
+                    description = description.resolve_attachments(&subpass.resolve_attachments);
+                }
+                if let Some(depth_attachment) = subpass.depth_attachment.as_ref() {
+                    description = description.depth_stencil_attachment(depth_attachment);
+                }
+                description.build()
+            })
+            .collect::<Vec<_>>();
+
+        let default_dependency = vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            dst_subpass: 0,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dependency_flags: vk::DependencyFlags::empty(),
+        };
+        let replaces_default = self.dependencies.iter()
+            .any(|dependency| dependency.src_subpass == vk::SUBPASS_EXTERNAL && dependency.dst_subpass == 0);
+        let mut dependencies = self.dependencies.clone();
+        if !replaces_default {
+            dependencies.insert(0, default_dependency);
+        }
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachment_descriptions)
+            .subpasses(&subpass_descriptions)
+            .dependencies(&dependencies);
+
+        unsafe {
+            context.device().vk_device().create_render_pass(&render_pass_info, None).map_vk_err("vkCreateRenderPass")
+        }
+    }
+
+    /// Input attachments of depth or stencil formats are read in the depth stencil read only layout.
+    fn input_reference(&self, input: &vk::AttachmentReference) -> vk::AttachmentReference {
+        let is_color = self.attachments.get(input.attachment as usize)
+            .is_none_or(|attachment| format_aspect_mask(attachment.format) == vk::ImageAspectFlags::COLOR);
+        match is_color {
+            true => *input,
+            false => reference(input.attachment, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+        }
+    }
+}
+
+fn reference(attachment: u32, layout: vk::ImageLayout) -> vk::AttachmentReference {
+    vk::AttachmentReference { attachment, layout }
+}
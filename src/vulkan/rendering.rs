@@ -0,0 +1,382 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::{Attachment, Context, Framebuffer, RenderPass, RenderPassBuilder, Subpass, VisionError, VisionResult};
+use crate::vulkan::image::has_stencil_component;
+
+/// Formats and sample count of the attachments rendered to, graphics pipelines are created against it.
+#[derive(Clone, Debug, Default)]
+pub struct RenderingLayout {
+    color_formats: Vec<vk::Format>,
+    depth_format: Option<vk::Format>,
+    samples: vk::SampleCountFlags,
+}
+
+impl RenderingLayout {
+    pub fn new() -> Self {
+        Self {
+            samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a color attachment, the next fragment shader output location.
+    pub fn color(mut self, format: vk::Format) -> Self {
+        self.color_formats.push(format);
+        self
+    }
+
+    pub fn depth(mut self, format: vk::Format) -> Self {
+        self.depth_format = Some(format);
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn color_formats(&self) -> &[vk::Format] {
+        &self.color_formats
+    }
+
+    pub fn depth_format(&self) -> Option<vk::Format> {
+        self.depth_format
+    }
+
+    pub fn sample_count(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    /// Chained to `VkGraphicsPipelineCreateInfo` for pipelines used with dynamic rendering.
+    pub fn vk_pipeline_rendering_info(&self) -> vk::PipelineRenderingCreateInfoBuilder<'_> {
+        let depth_format = self.depth_format.unwrap_or(vk::Format::UNDEFINED);
+        let stencil_format = match self.depth_format {
+            Some(format) if has_stencil_component(format) => format,
+            _ => vk::Format::UNDEFINED,
+        };
+        vk::PipelineRenderingCreateInfoKHR::builder()
+            .color_attachment_formats(&self.color_formats)
+            .depth_attachment_format(depth_format)
+            .stencil_attachment_format(stencil_format)
+    }
+}
+
+/// Image view rendered to in `layout`, the image has to be transitioned to `layout` before rendering begins.
+#[derive(Clone, Copy)]
+pub struct RenderingAttachment {
+    view: vk::ImageView,
+    layout: vk::ImageLayout,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp,
+    clear_value: vk::ClearValue,
+    resolve_view: Option<vk::ImageView>,
+}
+
+impl RenderingAttachment {
+    /// Cleared to black and stored.
+    pub fn color(view: vk::ImageView) -> Self {
+        Self {
+            view,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value: vk::ClearValue { color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } },
+            resolve_view: None,
+        }
+    }
+
+    /// Cleared to 1.0 and discarded.
+    pub fn depth(view: vk::ImageView) -> Self {
+        Self {
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value: vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+            ..Self::color(view)
+        }
+    }
+
+    pub fn layout(mut self, layout: vk::ImageLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn load_op(mut self, load_op: vk::AttachmentLoadOp) -> Self {
+        self.load_op = load_op;
+        self
+    }
+
+    pub fn store_op(mut self, store_op: vk::AttachmentStoreOp) -> Self {
+        self.store_op = store_op;
+        self
+    }
+
+    pub fn clear_color(mut self, color: [f32; 4]) -> Self {
+        self.clear_value = vk::ClearValue { color: vk::ClearColorValue { float32: color } };
+        self
+    }
+
+    pub fn clear_depth(mut self, depth: f32, stencil: u32) -> Self {
+        self.clear_value = vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth, stencil } };
+        self
+    }
+
+    /// Averages a multisampled color attachment into `view`, which has to be in `COLOR_ATTACHMENT_OPTIMAL`.
+    pub fn resolve(mut self, view: vk::ImageView) -> Self {
+        self.resolve_view = Some(view);
+        self
+    }
+
+    fn vk_rendering_attachment_info(&self) -> vk::RenderingAttachmentInfoKHR {
+        let mut info = vk::RenderingAttachmentInfoKHR::builder()
+            .image_view(self.view)
+            .image_layout(self.layout)
+            .load_op(self.load_op)
+            .store_op(self.store_op)
+            .clear_value(self.clear_value);
+        if let Some(resolve_view) = self.resolve_view {
+            info = info
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(resolve_view)
+                .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        }
+        info.build()
+    }
+}
+
+/// Begins rendering directly with image views through `VK_KHR_dynamic_rendering` when the `dynamic_rendering`
+/// feature is enabled. Otherwise render passes matching the attachment operations and framebuffers matching the
+/// views are created on first use and cached.
+///
+/// Attachments are not transitioned in either case, images have to be in the layout of their attachment when
+/// rendering begins and remain in it afterwards.
+pub struct Rendering {
+    context: Arc<Context>,
+    layout: RenderingLayout,
+    // Render pass fallback, the compatible render pass is used to create pipelines.
+    framebuffers: RefCell<HashMap<FramebufferKey, Framebuffer>>,
+    render_passes: RefCell<HashMap<Vec<AttachmentKey>, RenderPass>>,
+    compatible_render_pass: Option<RenderPass>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct AttachmentKey {
+    layout: vk::ImageLayout,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp,
+    resolved: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct FramebufferKey {
+    render_pass: vk::RenderPass,
+    views: Vec<vk::ImageView>,
+    width: u32,
+    height: u32,
+}
+
+impl Rendering {
+    pub fn new(context: Arc<Context>, layout: RenderingLayout) -> VisionResult<Self> {
+        let compatible_render_pass = match context.device().dynamic_rendering() {
+            Some(_) => None,
+            None => {
+                let color_keys = layout.color_formats.iter().map(|_| AttachmentKey {
+                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                    resolved: false,
+                });
+                let depth_key = layout.depth_format.map(|_| AttachmentKey {
+                    layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    store_op: vk::AttachmentStoreOp::DONT_CARE,
+                    resolved: false,
+                });
+                let keys = color_keys.chain(depth_key).collect::<Vec<_>>();
+                Some(render_pass_description(&layout, &keys).build(Arc::clone(&context))?)
+            }
+        };
+
+        Ok(Self {
+            context,
+            layout,
+            framebuffers: RefCell::new(HashMap::new()),
+            render_passes: RefCell::new(HashMap::new()),
+            compatible_render_pass,
+        })
+    }
+
+    pub fn layout(&self) -> &RenderingLayout {
+        &self.layout
+    }
+
+    pub fn uses_dynamic_rendering(&self) -> bool {
+        self.compatible_render_pass.is_none()
+    }
+
+    /// Render pass graphics pipelines have to be created with when dynamic rendering is unavailable, pipelines
+    /// created with it can be used with all render passes of this rendering.
+    pub fn compatible_render_pass(&self) -> Option<&RenderPass> {
+        self.compatible_render_pass.as_ref()
+    }
+
+    /// Begins rendering to `color_attachments` in the order of the layout formats and `depth_attachment` if the layout
+    /// has a depth format, views have to be of the layout formats and sample count and cover `extent`.
+    pub fn cmd_begin(&self,
+                     command_buffer: vk::CommandBuffer,
+                     extent: vk::Extent2D,
+                     color_attachments: &[RenderingAttachment],
+                     depth_attachment: Option<&RenderingAttachment>) -> VisionResult<()> {
+        if color_attachments.len() != self.layout.color_formats.len() {
+            return Err(VisionError::AttachmentMismatch(format!(
+                "{} color attachments for {} color formats", color_attachments.len(), self.layout.color_formats.len())));
+        }
+        if depth_attachment.is_some() != self.layout.depth_format.is_some() {
+            return Err(VisionError::AttachmentMismatch(match depth_attachment {
+                Some(_) => "depth attachment without a depth format".to_string(),
+                None => "missing depth attachment".to_string(),
+            }));
+        }
+        let render_area = vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent };
+
+        if let Some(dynamic_rendering) = self.context.device().dynamic_rendering() {
+            let color_infos = color_attachments.iter()
+                .map(RenderingAttachment::vk_rendering_attachment_info)
+                .collect::<Vec<_>>();
+            let depth_info = depth_attachment.map(RenderingAttachment::vk_rendering_attachment_info);
+            let mut rendering_info = vk::RenderingInfoKHR::builder()
+                .render_area(render_area)
+                .layer_count(1)
+                .color_attachments(&color_infos);
+            if let Some(depth_info) = depth_info.as_ref() {
+                rendering_info = rendering_info.depth_attachment(depth_info);
+                if self.layout.depth_format.is_some_and(has_stencil_component) {
+                    rendering_info = rendering_info.stencil_attachment(depth_info);
+                }
+            }
+            unsafe { dynamic_rendering.cmd_begin_rendering(command_buffer, &rendering_info) };
+            return Ok(());
+        }
+
+        let attachments = color_attachments.iter().chain(depth_attachment).collect::<Vec<_>>();
+        let keys = attachments.iter()
+            .map(|attachment| AttachmentKey {
+                layout: attachment.layout,
+                load_op: attachment.load_op,
+                store_op: attachment.store_op,
+                resolved: attachment.resolve_view.is_some(),
+            })
+            .collect::<Vec<_>>();
+        let views = attachments.iter()
+            .map(|attachment| attachment.view)
+            .chain(color_attachments.iter().filter_map(|attachment| attachment.resolve_view))
+            .collect::<Vec<_>>();
+        let clear_values = attachments.iter().map(|attachment| attachment.clear_value).collect::<Vec<_>>();
+
+        let render_pass = self.render_pass(keys)?;
+        let framebuffer = self.framebuffer(FramebufferKey {
+            render_pass,
+            views,
+            width: extent.width,
+            height: extent.height,
+        })?;
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values);
+        unsafe {
+            self.context.device().vk_device()
+                .cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+        }
+        Ok(())
+    }
+
+    pub fn cmd_end(&self, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            match self.context.device().dynamic_rendering() {
+                Some(dynamic_rendering) => dynamic_rendering.cmd_end_rendering(command_buffer),
+                None => self.context.device().vk_device().cmd_end_render_pass(command_buffer),
+            }
+        }
+    }
+
+    /// Destroys the framebuffers cached by the render pass fallback. Has to be called once views rendered to are
+    /// destroyed, e.g. after recreating the swapchain, while none of the framebuffers are in use.
+    pub fn clear_framebuffers(&mut self) {
+        self.framebuffers.get_mut().clear();
+    }
+
+    fn render_pass(&self, keys: Vec<AttachmentKey>) -> VisionResult<vk::RenderPass> {
+        let mut render_passes = self.render_passes.borrow_mut();
+        if let Some(render_pass) = render_passes.get(&keys) {
+            return Ok(render_pass.vk_render_pass());
+        }
+        let render_pass = render_pass_description(&self.layout, &keys).build(Arc::clone(&self.context))?;
+        let vk_render_pass = render_pass.vk_render_pass();
+        render_passes.insert(keys, render_pass);
+        Ok(vk_render_pass)
+    }
+
+    fn framebuffer(&self, key: FramebufferKey) -> VisionResult<vk::Framebuffer> {
+        let mut framebuffers = self.framebuffers.borrow_mut();
+        if let Some(framebuffer) = framebuffers.get(&key) {
+            return Ok(framebuffer.vk_framebuffer());
+        }
+        let extent = vk::Extent2D { width: key.width, height: key.height };
+        let framebuffer = Framebuffer::new(Arc::clone(&self.context), key.render_pass, &key.views, extent)?;
+        let vk_framebuffer = framebuffer.vk_framebuffer();
+        framebuffers.insert(key, framebuffer);
+        Ok(vk_framebuffer)
+    }
+}
+
+/// Attachments are the color attachments, the depth attachment and the resolve attachments in this order. Layouts
+/// are kept across the render pass, as they are with dynamic rendering.
+fn render_pass_description(layout: &RenderingLayout, keys: &[AttachmentKey]) -> RenderPassBuilder {
+    let color_count = layout.color_formats.len();
+    let mut builder = RenderPass::builder();
+    let mut subpass = Subpass::new();
+    let mut resolve_formats = vec![];
+
+    for (index, (format, key)) in layout.color_formats.iter().zip(keys).enumerate() {
+        builder = builder.attachment(attachment(Attachment::color(*format), layout.samples, key));
+        if key.resolved {
+            let resolve_index = keys.len() + resolve_formats.len();
+            subpass = subpass.color_resolved(index as u32, resolve_index as u32);
+            resolve_formats.push(*format);
+        } else {
+            subpass = subpass.color(index as u32);
+        }
+    }
+    if let (Some(format), Some(key)) = (layout.depth_format, keys.get(color_count)) {
+        builder = builder.attachment(attachment(Attachment::depth(format), layout.samples, key));
+        subpass = subpass.depth(color_count as u32);
+    }
+    for format in resolve_formats {
+        builder = builder.attachment(Attachment::color(format)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL));
+    }
+
+    builder.subpass(subpass)
+}
+
+fn attachment(attachment: Attachment, samples: vk::SampleCountFlags, key: &AttachmentKey) -> Attachment {
+    // Contents of an undefined layout cannot be loaded, the image is transitioned to its attachment layout.
+    let initial_layout = match key.load_op {
+        vk::AttachmentLoadOp::LOAD => key.layout,
+        _ => vk::ImageLayout::UNDEFINED,
+    };
+    attachment
+        .samples(samples)
+        .load_op(key.load_op)
+        .store_op(key.store_op)
+        .stencil_ops(key.load_op, key.store_op)
+        .initial_layout(initial_layout)
+        .final_layout(key.layout)
+}
@@ -0,0 +1,315 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+use ash::vk;
+use image::{ImageFormat, Rgb, Rgba32FImage, RgbaImage};
+use image::codecs::hdr::HdrEncoder;
+
+use crate::vulkan::{Buffer, Context, Image, Texture, VisionError, VisionResult};
+use crate::vulkan::image::{image_byte_size, texel_size};
+use crate::vulkan::swapchain::{OutputEncoding, OutputParameters, PresentTarget};
+
+/// File format of a saved screenshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScreenshotFormat {
+    Png,
+    OpenExr,
+    /// Radiance RGBE, the alpha channel is dropped.
+    Radiance,
+}
+
+impl ScreenshotFormat {
+    /// Derives the format from the extension of `path`, `png`, `exr` or `hdr`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ScreenshotFormat::Png),
+            "exr" => Some(ScreenshotFormat::OpenExr),
+            "hdr" => Some(ScreenshotFormat::Radiance),
+            _ => None,
+        }
+    }
+}
+
+/// Pixels of an image converted to RGBA, 8 bit for LDR formats and linear 32 bit float for float formats and HDR
+/// outputs.
+pub enum Screenshot {
+    Ldr(RgbaImage),
+    Hdr(Rgba32FImage),
+}
+
+impl Screenshot {
+    /// Copies the first mip level and layer of `image` in `layout` and waits for the copy.
+    pub fn capture(image: &Image, layout: vk::ImageLayout) -> VisionResult<Self> {
+        Self::capture_output(image, layout, None)
+    }
+
+    pub fn from_texture(texture: &Texture, layout: vk::ImageLayout) -> VisionResult<Self> {
+        Self::capture(texture.image(), layout)
+    }
+
+    /// Copies `image_index` of a swapchain, which must be acquired and idle, e.g. after its frame completed and before
+    /// it is presented again. The image is expected in the present layout of the swapchain and needs `TRANSFER_SRC`
+    /// usage.
+    pub fn from_swapchain<T: PresentTarget + ?Sized>(swapchain: &T, image_index: u32) -> VisionResult<Self> {
+        let images = swapchain.images();
+        let image = images.get(image_index as usize)
+            .ok_or(VisionError::ImageIndexOutOfRange { index: image_index, count: images.len() as u32 })?;
+        Self::capture_output(image, swapchain.present_layout(), swapchain.output_parameters())
+    }
+
+    pub fn width(&self) -> u32 {
+        match self {
+            Screenshot::Ldr(image) => image.width(),
+            Screenshot::Hdr(image) => image.width(),
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        match self {
+            Screenshot::Ldr(image) => image.height(),
+            Screenshot::Hdr(image) => image.height(),
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, Screenshot::Hdr(_))
+    }
+
+    /// Saves in the format matching the extension of `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> VisionResult<()> {
+        let format = ScreenshotFormat::from_path(&path).ok_or_else(|| {
+            VisionError::ImageEncoding(format!("unknown screenshot format of {}", path.as_ref().display()))
+        })?;
+        self.save_with_format(path, format)
+    }
+
+    /// Float images saved as PNG are clamped and sRGB encoded, 8 bit images saved as float are sRGB decoded.
+    pub fn save_with_format<P: AsRef<Path>>(&self, path: P, format: ScreenshotFormat) -> VisionResult<()> {
+        match format {
+            ScreenshotFormat::Png => self.to_ldr().save_with_format(path, ImageFormat::Png)?,
+            ScreenshotFormat::OpenExr => self.to_hdr().save_with_format(path, ImageFormat::OpenExr)?,
+            ScreenshotFormat::Radiance => {
+                let image = self.to_hdr();
+                let pixels = image.pixels().map(|pixel| Rgb([pixel[0], pixel[1], pixel[2]])).collect::<Vec<_>>();
+                let file = File::create(path).map_err(|error| VisionError::ImageEncoding(error.to_string()))?;
+                HdrEncoder::new(BufWriter::new(file))
+                    .encode(&pixels, image.width() as usize, image.height() as usize)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn capture_output(image: &Image, layout: vk::ImageLayout, output: Option<OutputParameters>) -> VisionResult<Self> {
+        let context = image.context();
+        let capture = context.execute_transient(|command_buffer| {
+            PendingScreenshot::cmd_capture(context, command_buffer, image, layout)
+        })??;
+        capture.decode_output(output).finish()
+    }
+
+    fn to_ldr(&self) -> RgbaImage {
+        match self {
+            Screenshot::Ldr(image) => image.clone(),
+            Screenshot::Hdr(image) => RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+                let pixel = image.get_pixel(x, y);
+                let quantize = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+                let encode = |value: f32| quantize(linear_to_srgb(value.clamp(0.0, 1.0)));
+                image::Rgba([encode(pixel[0]), encode(pixel[1]), encode(pixel[2]), quantize(pixel[3])])
+            }),
+        }
+    }
+
+    fn to_hdr(&self) -> Rgba32FImage {
+        match self {
+            Screenshot::Hdr(image) => image.clone(),
+            Screenshot::Ldr(image) => Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+                let pixel = image.get_pixel(x, y);
+                let decode = |value: u8| srgb_to_linear(value as f32 / 255.0);
+                image::Rgba([decode(pixel[0]), decode(pixel[1]), decode(pixel[2]), pixel[3] as f32 / 255.0])
+            }),
+        }
+    }
+}
+
+/// Screenshot copy recorded into a command buffer, e.g. of a frame before it is presented.
+pub struct PendingScreenshot {
+    buffer: Buffer,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    output: Option<OutputParameters>,
+}
+
+impl PendingScreenshot {
+    /// Records the copy of the first mip level and layer of `image` in `layout` into a host visible buffer.
+    pub fn cmd_capture(context: &Arc<Context>,
+                       command_buffer: vk::CommandBuffer,
+                       image: &Image,
+                       layout: vk::ImageLayout) -> VisionResult<Self> {
+        let format = image.format();
+        if !is_supported_format(format) {
+            return Err(VisionError::UnsupportedFormat {
+                format,
+                features: vk::FormatFeatureFlags::TRANSFER_SRC,
+            });
+        }
+        let extent = image.mip_extent(0, 0)?;
+        let texel_size = texel_size(format).ok_or(VisionError::UnsupportedFormat {
+            format,
+            features: vk::FormatFeatureFlags::TRANSFER_SRC,
+        })?;
+        let buffer = Buffer::staging(Arc::clone(context), image_byte_size(extent, texel_size)?)?;
+
+        image.cmd_copy_to_buffer(command_buffer, &buffer, layout, 0, 0);
+        context.cmd_memory_barrier(command_buffer,
+                                   vk::PipelineStageFlags::TRANSFER,
+                                   vk::AccessFlags::TRANSFER_WRITE,
+                                   vk::PipelineStageFlags::HOST,
+                                   vk::AccessFlags::HOST_READ);
+
+        Ok(Self {
+            buffer,
+            format,
+            extent: vk::Extent2D { width: extent.width, height: extent.height },
+            output: None,
+        })
+    }
+
+    /// Undoes the HDR encoding of a swapchain image, e.g. from `Swapchain::output_parameters`, so that HDR10 and
+    /// extended sRGB outputs are captured as linear BT.709 where 1.0 is paper white.
+    pub fn decode_output(mut self, output: Option<OutputParameters>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Converts the copied texels, the command buffer has to be completed.
+    pub fn finish(self) -> VisionResult<Screenshot> {
+        let data = self.buffer.read(0, self.buffer.len())?;
+        let encoding = self.output.map(|output| output.encoding);
+        match self.format {
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => self.ldr(data),
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => {
+                let data = data.chunks_exact(4).flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]]).collect();
+                self.ldr(data)
+            }
+            vk::Format::A2B10G10R10_UNORM_PACK32 => {
+                let texels = data.chunks_exact(4).map(|bytes| {
+                    let texel = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    let channel = |shift: u32| ((texel >> shift) & 0x3ff) as f32 / 1023.0;
+                    [channel(0), channel(10), channel(20), (texel >> 30) as f32 / 3.0]
+                });
+                match self.output {
+                    Some(output) if encoding == Some(OutputEncoding::Hdr10 as u32) => {
+                        let data = texels.flat_map(|[r, g, b, a]| {
+                            let [r, g, b] = bt2020_to_bt709([pq_decode(r), pq_decode(g), pq_decode(b)]);
+                            let scale = 1.0 / output.paper_white_nits;
+                            [r * scale, g * scale, b * scale, a]
+                        });
+                        self.hdr(data.collect())
+                    }
+                    _ => {
+                        let quantize = |value: f32| (value * 255.0).round() as u8;
+                        self.ldr(texels.flat_map(|texel| texel.map(quantize)).collect())
+                    }
+                }
+            }
+            vk::Format::R16G16B16A16_SFLOAT => {
+                // Extended sRGB maps 1.0 to 80 nits.
+                let scale = match self.output {
+                    Some(output) if encoding == Some(OutputEncoding::ExtendedSrgbLinear as u32) => {
+                        80.0 / output.paper_white_nits
+                    }
+                    _ => 1.0,
+                };
+                let data = data.chunks_exact(2)
+                    .map(|bytes| f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]])))
+                    .enumerate()
+                    .map(|(index, value)| if index % 4 == 3 { value } else { value * scale })
+                    .collect();
+                self.hdr(data)
+            }
+            vk::Format::R32G32B32A32_SFLOAT => {
+                let data = data.chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect();
+                self.hdr(data)
+            }
+            format => Err(VisionError::UnsupportedFormat {
+                format,
+                features: vk::FormatFeatureFlags::TRANSFER_SRC,
+            }),
+        }
+    }
+
+    fn ldr(&self, data: Vec<u8>) -> VisionResult<Screenshot> {
+        RgbaImage::from_raw(self.extent.width, self.extent.height, data)
+            .map(Screenshot::Ldr)
+            .ok_or_else(|| VisionError::ImageEncoding("copied texels do not match the extent".to_string()))
+    }
+
+    fn hdr(&self, data: Vec<f32>) -> VisionResult<Screenshot> {
+        Rgba32FImage::from_raw(self.extent.width, self.extent.height, data)
+            .map(Screenshot::Hdr)
+            .ok_or_else(|| VisionError::ImageEncoding("copied texels do not match the extent".to_string()))
+    }
+}
+
+fn is_supported_format(format: vk::Format) -> bool {
+    matches!(format,
+             vk::Format::R8G8B8A8_SRGB
+             | vk::Format::R8G8B8A8_UNORM
+             | vk::Format::B8G8R8A8_SRGB
+             | vk::Format::B8G8R8A8_UNORM
+             | vk::Format::A2B10G10R10_UNORM_PACK32
+             | vk::Format::R16G16B16A16_SFLOAT
+             | vk::Format::R32G32B32A32_SFLOAT)
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// SMPTE ST 2084 EOTF, returns nits.
+fn pq_decode(value: f32) -> f32 {
+    const M1: f32 = 0.159_301_76;
+    const M2: f32 = 78.843_75;
+    const C1: f32 = 0.835_937_5;
+    const C2: f32 = 18.851_563;
+    const C3: f32 = 18.687_5;
+    let p = value.clamp(0.0, 1.0).powf(1.0 / M2);
+    ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1) * 10000.0
+}
+
+fn bt2020_to_bt709([r, g, b]: [f32; 3]) -> [f32; 3] {
+    [
+        1.6605 * r - 0.5876 * g - 0.0728 * b,
+        -0.1246 * r + 1.1329 * g - 0.0083 * b,
+        -0.0182 * r - 0.1006 * g + 1.1187 * b,
+    ]
+}
@@ -0,0 +1,513 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ash::vk;
+use naga::back::spv;
+use naga::front::glsl;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+use crate::vulkan::{Context, ShaderModule, VisionError, VisionResult};
+
+/// Part of the cache key, bump them when a compiler or its options change the generated code.
+const NAGA_CACHE_VERSION: &str = "naga-30";
+#[cfg(feature = "shaderc")]
+const SHADERC_CACHE_VERSION: &str = "shaderc-0.7";
+
+/// Input file name passed to shaderc, its messages are mapped back to the original files.
+#[cfg(feature = "shaderc")]
+const SHADERC_INPUT_NAME: &str = "expanded";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderLanguage {
+    Glsl,
+    Hlsl,
+}
+
+impl ShaderLanguage {
+    /// `.hlsl` files are HLSL, all others GLSL.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("hlsl") => ShaderLanguage::Hlsl,
+            _ => ShaderLanguage::Glsl,
+        }
+    }
+}
+
+/// Compiles shader sources below a root directory to SPIR-V at runtime. `#include "file"` is resolved relative to
+/// the including file and then to the root, `#include <file>` only relative to the root, and `#pragma once` is
+/// honored. Errors name the file and line of the original source.
+///
+/// Directives in comments are ignored, but conditionals are not evaluated: includes in inactive `#if` blocks are
+/// expanded as well. An include that cannot be found becomes an `#error`, so it only fails if its block is active.
+///
+/// GLSL vertex, fragment and compute shaders are compiled with naga. HLSL and all other stages, e.g. ray tracing,
+/// are compiled with shaderc, which needs the `shaderc` feature and links libshaderc.
+pub struct ShaderCompiler {
+    root: PathBuf,
+    cache_dir: Option<PathBuf>,
+    defines: BTreeMap<String, String>,
+}
+
+impl ShaderCompiler {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            cache_dir: None,
+            defines: BTreeMap::new(),
+        }
+    }
+
+    /// Stores compiled SPIR-V in `dir`, keyed by a hash of the preprocessed source, its language, stage and compiler
+    /// and the defines.
+    pub fn cache_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.cache_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Defines `name` as `value` in every compiled shader.
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Compiles `path` relative to the root, the stage is taken from its extension, e.g. `.vert` or `.comp`.
+    /// `defines` are added to those of the compiler.
+    pub fn compile<P: AsRef<Path>>(&self, path: P, defines: &[(&str, &str)]) -> VisionResult<Vec<u32>> {
+        let path = path.as_ref();
+        let stage = stage_from_path(path).ok_or_else(|| {
+            VisionError::ShaderCompilation(format!("{}: unknown shader stage, use compile_stage", path.display()))
+        })?;
+        self.compile_stage(path, stage, defines)
+    }
+
+    pub fn compile_stage<P: AsRef<Path>>(&self,
+                                         path: P,
+                                         stage: vk::ShaderStageFlags,
+                                         defines: &[(&str, &str)]) -> VisionResult<Vec<u32>> {
+        let path = self.root.join(path);
+        let language = ShaderLanguage::from_path(&path);
+        let backend = Backend::select(&path, language, stage)?;
+
+        let mut all_defines = self.defines.clone();
+        all_defines.extend(defines.iter().map(|(name, value)| (name.to_string(), value.to_string())));
+
+        let mut source = ExpandedSource::default();
+        self.expand(&path, &mut vec![], &mut HashSet::new(), &mut source)?;
+
+        let cache_path = self.cache_dir.as_ref().map(|dir| {
+            let mut hasher = Fnv1a::new();
+            hasher.write(backend.cache_version().as_bytes());
+            hasher.write(format!("{:?} {:?}", language, stage).as_bytes());
+            for (name, value) in all_defines.iter() {
+                hasher.write(name.as_bytes());
+                hasher.write(b"=");
+                hasher.write(value.as_bytes());
+                hasher.write(b"\n");
+            }
+            hasher.write(source.text.as_bytes());
+            dir.join(format!("{:016x}.spv", hasher.finish()))
+        });
+
+        // Unreadable cache entries are compiled again.
+        if let Some(code) = cache_path.as_ref().and_then(|cache_path| read_cached(cache_path)) {
+            return Ok(code);
+        }
+
+        let code = match backend {
+            Backend::Naga(stage) => source.compile(stage, all_defines)?,
+            #[cfg(feature = "shaderc")]
+            Backend::Shaderc(kind, language) => source.compile_shaderc(kind, language, all_defines)?,
+        };
+        if let Some(cache_path) = cache_path {
+            // A failed cache write only costs a compilation next time.
+            let _ = write_cached(&cache_path, &code);
+        }
+        Ok(code)
+    }
+
+    /// Compiles `path` like `compile` and creates a shader module from it.
+    pub fn load<P: AsRef<Path>>(&self,
+                                context: Arc<Context>,
+                                path: P,
+                                defines: &[(&str, &str)]) -> VisionResult<ShaderModule> {
+        let code = self.compile(path, defines)?;
+        ShaderModule::new(context, &code)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Appends the lines of `path` to `source`, replacing include directives with the included files.
+    fn expand(&self,
+              path: &Path,
+              stack: &mut Vec<PathBuf>,
+              included_once: &mut HashSet<PathBuf>,
+              source: &mut ExpandedSource) -> VisionResult<()> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if included_once.contains(&canonical) {
+            return Ok(());
+        }
+        let text = fs::read_to_string(path)
+            .map_err(|error| VisionError::ShaderCompilation(format!("{}: {}", path.display(), error)))?;
+        if stack.contains(&canonical) {
+            return Err(VisionError::ShaderCompilation(format!("{}: recursive include", path.display())));
+        }
+        stack.push(canonical.clone());
+
+        let mut in_block_comment = false;
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index as u32 + 1;
+            let directive = match in_block_comment {
+                true => None,
+                false => Directive::parse(line),
+            };
+            in_block_comment = ends_in_block_comment(line, in_block_comment);
+            match directive {
+                Some(Directive::Include { target, quoted }) => match self.resolve_include(path, target, quoted) {
+                    Some(included) => self.expand(&included, stack, included_once, source)?,
+                    None => source.push_missing_include(target, path, line_number),
+                },
+                Some(Directive::PragmaOnce) => {
+                    included_once.insert(canonical.clone());
+                }
+                // Resolved here, naga does not know the extension.
+                Some(Directive::IncludeExtension) => source.push_line("", path, line_number),
+                None => source.push_line(line, path, line_number),
+            }
+        }
+
+        stack.pop();
+        Ok(())
+    }
+
+    fn resolve_include(&self, including: &Path, target: &str, quoted: bool) -> Option<PathBuf> {
+        let relative = including.parent()
+            .filter(|_| quoted)
+            .map(|directory| directory.join(target));
+        relative.into_iter()
+            .chain(Some(self.root.join(target)))
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+impl Default for ShaderCompiler {
+    /// Compiler for `assets/shaders` relative to the working directory, without cache.
+    fn default() -> Self {
+        Self::new("assets/shaders")
+    }
+}
+
+enum Directive<'a> {
+    Include { target: &'a str, quoted: bool },
+    PragmaOnce,
+    IncludeExtension,
+}
+
+impl<'a> Directive<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let directive = line.trim().strip_prefix('#')?.trim_start();
+        if let Some(target) = directive.strip_prefix("include") {
+            let target = target.trim();
+            let quoted = target.starts_with('"') && target.ends_with('"');
+            let angled = target.starts_with('<') && target.ends_with('>');
+            return match (quoted || angled) && target.len() > 2 {
+                true => Some(Directive::Include { target: &target[1..target.len() - 1], quoted }),
+                false => None,
+            };
+        }
+        let words = directive.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            ["pragma", "once"] => Some(Directive::PragmaOnce),
+            ["extension", extension, ..] if extension.trim_end_matches(':') == "GL_GOOGLE_include_directive" => {
+                Some(Directive::IncludeExtension)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Source with includes expanded, each line remembers the file and line it came from.
+#[derive(Default)]
+struct ExpandedSource {
+    text: String,
+    lines: Vec<(PathBuf, u32)>,
+    // Targets of includes that were not found by expanded line index.
+    missing_includes: HashMap<usize, String>,
+}
+
+impl ExpandedSource {
+    /// Left to the compiler as `#error`, which it skips in inactive conditional blocks.
+    fn push_missing_include(&mut self, target: &str, path: &Path, line_number: u32) {
+        self.missing_includes.insert(self.lines.len(), target.to_string());
+        self.push_line(&format!("#error cannot find include {}", target), path, line_number);
+    }
+
+    fn push_line(&mut self, line: &str, path: &Path, line_number: u32) {
+        self.text.push_str(line);
+        self.text.push('\n');
+        self.lines.push((path.to_path_buf(), line_number));
+    }
+
+    fn compile(&self, stage: naga::ShaderStage, defines: BTreeMap<String, String>) -> VisionResult<Vec<u32>> {
+        let mut options = glsl::Options::from(stage);
+        options.defines.extend(defines);
+        let module = glsl::Frontend::default()
+            .parse(&options, &self.text)
+            .map_err(|errors| {
+                let mut messages = errors.errors.iter()
+                    .map(|error| self.message(error.meta, &error.kind.to_string()))
+                    .collect::<Vec<_>>();
+                // The tokens of a missing include's `#error` line are reported as well.
+                messages.dedup();
+                VisionError::ShaderCompilation(messages.join("\n"))
+            })?;
+
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .map_err(|error| {
+                let span = error.spans().next().map_or(naga::Span::UNDEFINED, |(span, _)| *span);
+                VisionError::ShaderCompilation(self.message(span, &error_chain(error.as_inner())))
+            })?;
+
+        // Vulkan GLSL is already in Vulkan's coordinate space, it must not be flipped.
+        let options = spv::Options {
+            flags: spv::WriterFlags::DEBUG | spv::WriterFlags::LABEL_VARYINGS | spv::WriterFlags::CLAMP_FRAG_DEPTH,
+            ..spv::Options::default()
+        };
+        let pipeline_options = spv::PipelineOptions {
+            shader_stage: stage,
+            entry_point: "main".to_string(),
+        };
+        spv::write_vec(&module, &info, &options, Some(&pipeline_options))
+            .map_err(|error| VisionError::ShaderCompilation(error_chain(&error)))
+    }
+
+    #[cfg(feature = "shaderc")]
+    fn compile_shaderc(&self,
+                       kind: shaderc::ShaderKind,
+                       language: ShaderLanguage,
+                       defines: BTreeMap<String, String>) -> VisionResult<Vec<u32>> {
+        let mut compiler = shaderc::Compiler::new()
+            .ok_or_else(|| VisionError::ShaderCompilation("failed to initialize shaderc".to_string()))?;
+        let mut options = shaderc::CompileOptions::new()
+            .ok_or_else(|| VisionError::ShaderCompilation("failed to initialize shaderc options".to_string()))?;
+        options.set_source_language(match language {
+            ShaderLanguage::Glsl => shaderc::SourceLanguage::GLSL,
+            ShaderLanguage::Hlsl => shaderc::SourceLanguage::HLSL,
+        });
+        options.set_target_env(shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_2 as u32);
+        options.set_generate_debug_info();
+        for (name, value) in defines.iter() {
+            options.add_macro_definition(name, Some(value));
+        }
+
+        let artifact = compiler.compile_into_spirv(&self.text, kind, SHADERC_INPUT_NAME, "main", Some(&options))
+            .map_err(|error| match error {
+                shaderc::Error::CompilationError(_, messages) => {
+                    let messages = messages.lines().map(|message| self.shaderc_message(message)).collect::<Vec<_>>();
+                    VisionError::ShaderCompilation(messages.join("\n"))
+                }
+                error => VisionError::ShaderCompilation(error.to_string()),
+            })?;
+        Ok(artifact.as_binary().to_vec())
+    }
+
+    /// Replaces the `expanded:line:` prefix of a shaderc message with the file and line of the original source.
+    #[cfg(feature = "shaderc")]
+    fn shaderc_message(&self, message: &str) -> String {
+        let original = message.strip_prefix(SHADERC_INPUT_NAME)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .and_then(|rest| rest.split_once(':'))
+            .and_then(|(line, rest)| {
+                let index = line.parse::<usize>().ok()?.checked_sub(1)?;
+                let (path, line) = self.lines.get(index)?;
+                match self.missing_includes.get(&index) {
+                    Some(target) => Some(format!("{}:{}: cannot find include '{}'", path.display(), line, target)),
+                    None => Some(format!("{}:{}:{}", path.display(), line, rest)),
+                }
+            });
+        original.unwrap_or_else(|| message.to_string())
+    }
+
+    /// Formats `message` as `file:line:column: message` for the original line of `span`.
+    fn message(&self, span: naga::Span, message: &str) -> String {
+        if !span.is_defined() {
+            return message.to_string();
+        }
+        let location = span.location(&self.text);
+        let index = location.line_number as usize - 1;
+        match (self.lines.get(index), self.missing_includes.get(&index)) {
+            (Some((path, line)), Some(target)) => format!("{}:{}: cannot find include '{}'", path.display(), line, target),
+            (Some((path, line)), None) => format!("{}:{}:{}: {}", path.display(), line, location.line_position, message),
+            (None, _) => message.to_string(),
+        }
+    }
+}
+
+/// Whether a block comment is still open at the end of `line`, `in_comment` tells if one was open at its start.
+fn ends_in_block_comment(line: &str, mut in_comment: bool) -> bool {
+    let mut rest = line;
+    loop {
+        if in_comment {
+            match rest.find("*/") {
+                Some(end) => {
+                    rest = &rest[end + 2..];
+                    in_comment = false;
+                }
+                None => return true,
+            }
+        } else {
+            match (rest.find("/*"), rest.find("//")) {
+                (Some(start), line_comment) if line_comment.is_none_or(|line_comment| start < line_comment) => {
+                    rest = &rest[start + 2..];
+                    in_comment = true;
+                }
+                _ => return false,
+            }
+        }
+    }
+}
+
+fn error_chain(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
+
+/// Stage of GLSL files named by the conventions of glslang, e.g. `shader.vert`.
+fn stage_from_path(path: &Path) -> Option<vk::ShaderStageFlags> {
+    let stage = match path.extension()?.to_str()? {
+        "vert" => vk::ShaderStageFlags::VERTEX,
+        "tesc" => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        "tese" => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        "geom" => vk::ShaderStageFlags::GEOMETRY,
+        "frag" => vk::ShaderStageFlags::FRAGMENT,
+        "comp" => vk::ShaderStageFlags::COMPUTE,
+        "rgen" => vk::ShaderStageFlags::RAYGEN_KHR,
+        "rint" => vk::ShaderStageFlags::INTERSECTION_KHR,
+        "rahit" => vk::ShaderStageFlags::ANY_HIT_KHR,
+        "rchit" => vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+        "rmiss" => vk::ShaderStageFlags::MISS_KHR,
+        "rcall" => vk::ShaderStageFlags::CALLABLE_KHR,
+        _ => return None,
+    };
+    Some(stage)
+}
+
+/// Compiler of a shader, naga where it supports the language and stage and shaderc for everything else.
+#[derive(Clone, Copy)]
+enum Backend {
+    Naga(naga::ShaderStage),
+    #[cfg(feature = "shaderc")]
+    Shaderc(shaderc::ShaderKind, ShaderLanguage),
+}
+
+impl Backend {
+    fn select(path: &Path, language: ShaderLanguage, stage: vk::ShaderStageFlags) -> VisionResult<Self> {
+        if let (ShaderLanguage::Glsl, Some(stage)) = (language, naga_stage(stage)) {
+            return Ok(Backend::Naga(stage));
+        }
+        #[cfg(feature = "shaderc")]
+        if let Some(kind) = shaderc_kind(stage) {
+            return Ok(Backend::Shaderc(kind, language));
+        }
+        let reason = match cfg!(feature = "shaderc") {
+            true => "the stage is not supported",
+            false => "HLSL and stages other than vertex, fragment and compute need the `shaderc` feature",
+        };
+        Err(VisionError::ShaderCompilation(format!("{}: cannot compile {:?} {:?} shaders, {}",
+                                                   path.display(), language, stage, reason)))
+    }
+
+    fn cache_version(&self) -> &'static str {
+        match self {
+            Backend::Naga(_) => NAGA_CACHE_VERSION,
+            #[cfg(feature = "shaderc")]
+            Backend::Shaderc(..) => SHADERC_CACHE_VERSION,
+        }
+    }
+}
+
+fn naga_stage(stage: vk::ShaderStageFlags) -> Option<naga::ShaderStage> {
+    match stage {
+        vk::ShaderStageFlags::VERTEX => Some(naga::ShaderStage::Vertex),
+        vk::ShaderStageFlags::FRAGMENT => Some(naga::ShaderStage::Fragment),
+        vk::ShaderStageFlags::COMPUTE => Some(naga::ShaderStage::Compute),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "shaderc")]
+fn shaderc_kind(stage: vk::ShaderStageFlags) -> Option<shaderc::ShaderKind> {
+    let kind = match stage {
+        vk::ShaderStageFlags::VERTEX => shaderc::ShaderKind::Vertex,
+        vk::ShaderStageFlags::TESSELLATION_CONTROL => shaderc::ShaderKind::TessControl,
+        vk::ShaderStageFlags::TESSELLATION_EVALUATION => shaderc::ShaderKind::TessEvaluation,
+        vk::ShaderStageFlags::GEOMETRY => shaderc::ShaderKind::Geometry,
+        vk::ShaderStageFlags::FRAGMENT => shaderc::ShaderKind::Fragment,
+        vk::ShaderStageFlags::COMPUTE => shaderc::ShaderKind::Compute,
+        vk::ShaderStageFlags::RAYGEN_KHR => shaderc::ShaderKind::RayGeneration,
+        vk::ShaderStageFlags::INTERSECTION_KHR => shaderc::ShaderKind::Intersection,
+        vk::ShaderStageFlags::ANY_HIT_KHR => shaderc::ShaderKind::AnyHit,
+        vk::ShaderStageFlags::CLOSEST_HIT_KHR => shaderc::ShaderKind::ClosestHit,
+        vk::ShaderStageFlags::MISS_KHR => shaderc::ShaderKind::Miss,
+        vk::ShaderStageFlags::CALLABLE_KHR => shaderc::ShaderKind::Callable,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+fn read_cached(path: &Path) -> Option<Vec<u32>> {
+    let bytes = fs::read(path).ok()?;
+    ash::util::read_spv(&mut Cursor::new(bytes)).ok()
+}
+
+/// Writes to a temporary file first, so that concurrent readers never see partial entries. The temporary name is
+/// unique per process and call, so concurrent writers of the same entry do not write to the same file.
+fn write_cached(path: &Path, code: &[u32]) -> std::io::Result<()> {
+    static TEMPORARY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let bytes = code.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<_>>();
+    let count = TEMPORARY_COUNT.fetch_add(1, Ordering::Relaxed);
+    let temporary = path.with_extension(format!("spv.{}.{}.tmp", process::id(), count));
+    fs::write(&temporary, bytes)
+        .and_then(|_| fs::rename(&temporary, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temporary);
+        })
+}
+
+/// 64 bit FNV-1a, unlike `DefaultHasher` its output is stable across Rust versions.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
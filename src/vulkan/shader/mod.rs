@@ -0,0 +1,7 @@
+pub use self::compiler::{ShaderCompiler, ShaderLanguage};
+pub use self::module::ShaderModule;
+pub use self::reflection::{DescriptorBinding, InterfaceVariable, ShaderReflection, SpecializationConstant};
+
+mod compiler;
+mod module;
+mod reflection;
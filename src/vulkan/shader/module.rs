@@ -0,0 +1,85 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+
+use ash::vk;
+use ash::vk::ShaderModule as VkShaderModule;
+
+use crate::vulkan::{Context, ShaderReflection, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+
+pub struct ShaderModule {
+    context: Arc<Context>,
+    shader_module: VkShaderModule,
+    entry_points: Vec<ShaderReflection>,
+}
+
+impl ShaderModule {
+    /// Creates a module from SPIR-V words and reflects the interface of its entry points.
+    pub fn new(context: Arc<Context>, code: &[u32]) -> VisionResult<Self> {
+        let entry_points = ShaderReflection::parse(code)?;
+        let create_info = vk::ShaderModuleCreateInfo::builder()
+            .code(code);
+
+        let shader_module = unsafe {
+            context.device().vk_device().create_shader_module(&create_info, None)
+        }.map_vk_err("vkCreateShaderModule")?;
+
+        Ok(Self {
+            context,
+            shader_module,
+            entry_points,
+        })
+    }
+
+    /// Creates a module from SPIR-V bytes, e.g. embedded with `include_bytes!`, which need not be 4 byte aligned.
+    pub fn from_bytes(context: Arc<Context>, bytes: &[u8]) -> VisionResult<Self> {
+        let code = ash::util::read_spv(&mut Cursor::new(bytes))
+            .map_err(|error| VisionError::InvalidShader(error.to_string()))?;
+        Self::new(context, &code)
+    }
+
+    /// Reads a compiled `.spv` file.
+    pub fn from_file<P: AsRef<Path>>(context: Arc<Context>, path: P) -> VisionResult<Self> {
+        let path = path.as_ref();
+        let code = File::open(path)
+            .and_then(|mut file| ash::util::read_spv(&mut file))
+            .map_err(|error| VisionError::InvalidShader(format!("{}: {}", path.display(), error)))?;
+        Self::new(context, &code)
+    }
+
+    /// Interface of the entry point called `name`.
+    pub fn reflection(&self, name: &str) -> Option<&ShaderReflection> {
+        self.entry_points.iter().find(|entry_point| entry_point.entry_point() == name)
+    }
+
+    /// Reflection of the entry point called `name`, an error if the module has none.
+    pub(crate) fn require_reflection(&self, name: &str) -> VisionResult<&ShaderReflection> {
+        self.reflection(name)
+            .ok_or_else(|| VisionError::ShaderMismatch(format!("module has no entry point '{}'", name)))
+    }
+
+    /// Entry point name as passed to Vulkan.
+    pub(crate) fn entry_point_name(name: &str) -> VisionResult<CString> {
+        CString::new(name)
+            .map_err(|_| VisionError::InvalidShader(format!("entry point '{}' contains a nul byte", name.escape_debug())))
+    }
+
+    pub fn entry_points(&self) -> &[ShaderReflection] {
+        &self.entry_points
+    }
+
+    pub fn vk_shader_module(&self) -> &VkShaderModule {
+        &self.shader_module
+    }
+}
+
+impl Drop for ShaderModule {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device().vk_device().destroy_shader_module(self.shader_module, None);
+        }
+    }
+}
@@ -0,0 +1,702 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use crate::vulkan::{VisionError, VisionResult};
+
+const MAGIC: u32 = 0x0723_0203;
+
+// Opcodes
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_EXECUTION_MODE: u32 = 16;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT_TRUE: u32 = 48;
+const OP_SPEC_CONSTANT_FALSE: u32 = 49;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_FUNCTION: u32 = 54;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_EXECUTION_MODE_ID: u32 = 331;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+// Decorations
+const DECORATION_SPEC_ID: u32 = 1;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_OUTPUT: u32 = 3;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+// Execution modes
+const MODE_LOCAL_SIZE: u32 = 17;
+const MODE_LOCAL_SIZE_ID: u32 = 38;
+
+// Image dimensions
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// Deepest nesting of arrays, matrices and structs that is reflected. Type ids are not checked to be declared before
+/// use, so this also stops types that contain themselves.
+const MAX_TYPE_DEPTH: u32 = 64;
+
+/// Descriptor used by a shader, arrays of runtime size have a `count` of 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+    pub name: String,
+}
+
+/// Input or output at `location`, array elements and matrix columns take one location each. `format` is `UNDEFINED`
+/// for types without a matching format, e.g. structs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceVariable {
+    pub location: u32,
+    pub format: vk::Format,
+    pub name: String,
+}
+
+/// Constant set through `vk::SpecializationInfo`, booleans are 4 byte `vk::Bool32`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpecializationConstant {
+    pub id: u32,
+    pub size: u32,
+    pub name: String,
+}
+
+/// Interface of an entry point of a SPIR-V module. Descriptor bindings, push constants and specialization constants
+/// are those declared in the module, whether or not the entry point uses them.
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+    entry_point: String,
+    stage: vk::ShaderStageFlags,
+    descriptor_bindings: Vec<DescriptorBinding>,
+    push_constant_range: Option<vk::PushConstantRange>,
+    inputs: Vec<InterfaceVariable>,
+    outputs: Vec<InterfaceVariable>,
+    workgroup_size: Option<[u32; 3]>,
+    specialization_constants: Vec<SpecializationConstant>,
+}
+
+impl ShaderReflection {
+    /// Reflects every entry point of `code`.
+    pub fn parse(code: &[u32]) -> VisionResult<Vec<Self>> {
+        let module = Module::parse(code)?;
+        module.entry_points.iter()
+            .map(|entry_point| module.reflect(entry_point))
+            .collect()
+    }
+
+    pub fn entry_point(&self) -> &str {
+        &self.entry_point
+    }
+
+    pub fn stage(&self) -> vk::ShaderStageFlags {
+        self.stage
+    }
+
+    /// Sorted by set and binding.
+    pub fn descriptor_bindings(&self) -> &[DescriptorBinding] {
+        &self.descriptor_bindings
+    }
+
+    /// Bytes of the push constant block used by the shader, with the offset of its first member.
+    pub fn push_constant_range(&self) -> Option<vk::PushConstantRange> {
+        self.push_constant_range
+    }
+
+    /// Vertex inputs of vertex shaders, sorted by location and without built-ins.
+    pub fn inputs(&self) -> &[InterfaceVariable] {
+        &self.inputs
+    }
+
+    /// Color outputs of fragment shaders, sorted by location and without built-ins.
+    pub fn outputs(&self) -> &[InterfaceVariable] {
+        &self.outputs
+    }
+
+    /// Local size of compute, task and mesh shaders.
+    pub fn workgroup_size(&self) -> Option<[u32; 3]> {
+        self.workgroup_size
+    }
+
+    pub fn specialization_constants(&self) -> &[SpecializationConstant] {
+        &self.specialization_constants
+    }
+
+    /// Checks that `bindings` of descriptor set `set` provide every binding the shader declares in it.
+    pub fn check_descriptor_set(&self, set: u32, bindings: &[vk::DescriptorSetLayoutBinding]) -> VisionResult<()> {
+        for expected in self.descriptor_bindings.iter().filter(|binding| binding.set == set) {
+            let binding = bindings.iter()
+                .find(|binding| binding.binding == expected.binding)
+                .ok_or_else(|| self.mismatch(format!("set {} has no binding {} for '{}'", set, expected.binding, expected.name)))?;
+            if binding.descriptor_type != expected.descriptor_type {
+                return Err(self.mismatch(format!("binding {}.{} is {:?} but '{}' is {:?}",
+                                                 set, expected.binding, binding.descriptor_type,
+                                                 expected.name, expected.descriptor_type)));
+            }
+            if binding.descriptor_count < expected.count {
+                return Err(self.mismatch(format!("binding {}.{} has {} descriptors but '{}' needs {}",
+                                                 set, expected.binding, binding.descriptor_count,
+                                                 expected.name, expected.count)));
+            }
+            if !binding.stage_flags.contains(self.stage) {
+                return Err(self.mismatch(format!("binding {}.{} is not visible to {:?}", set, expected.binding, self.stage)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that one of `ranges` covers the push constant block of the shader.
+    pub fn check_push_constant_ranges(&self, ranges: &[vk::PushConstantRange]) -> VisionResult<()> {
+        let expected = match self.push_constant_range {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+        let covered = ranges.iter().any(|range| {
+            range.stage_flags.contains(self.stage)
+                && range.offset <= expected.offset
+                && range.offset.checked_add(range.size).is_some_and(|end| end >= expected.offset + expected.size)
+        });
+        match covered {
+            true => Ok(()),
+            false => Err(self.mismatch(format!("no push constant range covers bytes {}..{}",
+                                               expected.offset, expected.offset + expected.size))),
+        }
+    }
+
+    /// Checks that `attributes` provide every input location of a vertex shader.
+    pub fn check_vertex_attributes(&self, attributes: &[vk::VertexInputAttributeDescription]) -> VisionResult<()> {
+        match self.inputs.iter().find(|input| attributes.iter().all(|attribute| attribute.location != input.location)) {
+            Some(input) => Err(self.mismatch(format!("no vertex attribute at location {} for '{}'", input.location, input.name))),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that a fragment shader only writes to the first `count` color attachments.
+    pub fn check_color_attachments(&self, count: usize) -> VisionResult<()> {
+        match self.outputs.iter().find(|output| output.location as usize >= count) {
+            Some(output) => Err(self.mismatch(format!("output '{}' at location {} exceeds the {} color attachments",
+                                                      output.name, output.location, count))),
+            None => Ok(()),
+        }
+    }
+
+    fn mismatch(&self, message: String) -> VisionError {
+        VisionError::ShaderMismatch(format!("{} shader '{}': {}", stage_name(self.stage), self.entry_point, message))
+    }
+}
+
+fn stage_name(stage: vk::ShaderStageFlags) -> &'static str {
+    match stage {
+        vk::ShaderStageFlags::VERTEX => "vertex",
+        vk::ShaderStageFlags::TESSELLATION_CONTROL => "tessellation control",
+        vk::ShaderStageFlags::TESSELLATION_EVALUATION => "tessellation evaluation",
+        vk::ShaderStageFlags::GEOMETRY => "geometry",
+        vk::ShaderStageFlags::FRAGMENT => "fragment",
+        vk::ShaderStageFlags::COMPUTE => "compute",
+        vk::ShaderStageFlags::TASK_EXT => "task",
+        vk::ShaderStageFlags::MESH_EXT => "mesh",
+        vk::ShaderStageFlags::RAYGEN_KHR => "raygen",
+        vk::ShaderStageFlags::INTERSECTION_KHR => "intersection",
+        vk::ShaderStageFlags::ANY_HIT_KHR => "any hit",
+        vk::ShaderStageFlags::CLOSEST_HIT_KHR => "closest hit",
+        vk::ShaderStageFlags::MISS_KHR => "miss",
+        vk::ShaderStageFlags::CALLABLE_KHR => "callable",
+        _ => "unknown",
+    }
+}
+
+fn execution_model_stage(execution_model: u32) -> Option<vk::ShaderStageFlags> {
+    match execution_model {
+        0 => Some(vk::ShaderStageFlags::VERTEX),
+        1 => Some(vk::ShaderStageFlags::TESSELLATION_CONTROL),
+        2 => Some(vk::ShaderStageFlags::TESSELLATION_EVALUATION),
+        3 => Some(vk::ShaderStageFlags::GEOMETRY),
+        4 => Some(vk::ShaderStageFlags::FRAGMENT),
+        5 => Some(vk::ShaderStageFlags::COMPUTE),
+        5313 => Some(vk::ShaderStageFlags::RAYGEN_KHR),
+        5314 => Some(vk::ShaderStageFlags::INTERSECTION_KHR),
+        5315 => Some(vk::ShaderStageFlags::ANY_HIT_KHR),
+        5316 => Some(vk::ShaderStageFlags::CLOSEST_HIT_KHR),
+        5317 => Some(vk::ShaderStageFlags::MISS_KHR),
+        5318 => Some(vk::ShaderStageFlags::CALLABLE_KHR),
+        5364 => Some(vk::ShaderStageFlags::TASK_EXT),
+        5365 => Some(vk::ShaderStageFlags::MESH_EXT),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Type {
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct,
+    Pointer { pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Decorations {
+    set: Option<u32>,
+    binding: Option<u32>,
+    location: Option<u32>,
+    spec_id: Option<u32>,
+    offset: Option<u32>,
+    array_stride: Option<u32>,
+    matrix_stride: Option<u32>,
+    built_in: bool,
+    buffer_block: bool,
+}
+
+impl Decorations {
+    fn apply(&mut self, decoration: u32, value: Option<u32>) {
+        match decoration {
+            DECORATION_SPEC_ID => self.spec_id = value,
+            DECORATION_BUFFER_BLOCK => self.buffer_block = true,
+            DECORATION_ARRAY_STRIDE => self.array_stride = value,
+            DECORATION_MATRIX_STRIDE => self.matrix_stride = value,
+            DECORATION_BUILT_IN => self.built_in = true,
+            DECORATION_LOCATION => self.location = value,
+            DECORATION_BINDING => self.binding = value,
+            DECORATION_DESCRIPTOR_SET => self.set = value,
+            DECORATION_OFFSET => self.offset = value,
+            _ => {}
+        }
+    }
+}
+
+struct EntryPoint {
+    stage: vk::ShaderStageFlags,
+    id: u32,
+    name: String,
+    interface: Vec<u32>,
+}
+
+struct Variable {
+    id: u32,
+    pointer: u32,
+    storage_class: u32,
+}
+
+/// Declarations of a SPIR-V module, function bodies are not parsed.
+#[derive(Default)]
+struct Module {
+    entry_points: Vec<EntryPoint>,
+    names: HashMap<u32, String>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), Decorations>,
+    types: HashMap<u32, Type>,
+    struct_members: HashMap<u32, Vec<u32>>,
+    constants: HashMap<u32, u32>,
+    // Id and type of each specialization constant.
+    spec_constants: Vec<(u32, u32)>,
+    variables: Vec<Variable>,
+    // Literal sizes of `LocalSize` and constant ids of `LocalSizeId` per entry point.
+    local_sizes: HashMap<u32, [u32; 3]>,
+    local_size_ids: HashMap<u32, [u32; 3]>,
+}
+
+impl Module {
+    fn parse(code: &[u32]) -> VisionResult<Self> {
+        if code.len() < 5 || code[0] != MAGIC {
+            return Err(VisionError::InvalidShader("missing SPIR-V header".to_string()));
+        }
+
+        let mut module = Module::default();
+        let mut offset = 5;
+        while offset < code.len() {
+            let word_count = (code[offset] >> 16) as usize;
+            let opcode = code[offset] & 0xffff;
+            if word_count == 0 || offset + word_count > code.len() {
+                return Err(VisionError::InvalidShader(format!("truncated instruction at word {}", offset)));
+            }
+            if opcode == OP_FUNCTION {
+                break;
+            }
+            module.parse_instruction(opcode, &code[offset + 1..offset + word_count])
+                .ok_or_else(|| VisionError::InvalidShader(format!("malformed instruction {} at word {}", opcode, offset)))?;
+            offset += word_count;
+        }
+        Ok(module)
+    }
+
+    /// Returns `None` if the instruction is missing operands.
+    fn parse_instruction(&mut self, opcode: u32, operands: &[u32]) -> Option<()> {
+        let operand = |index: usize| operands.get(index).copied();
+        match opcode {
+            OP_NAME => {
+                let (name, _) = parse_string(operands.get(1..)?);
+                self.names.insert(operand(0)?, name);
+            }
+            OP_ENTRY_POINT => {
+                let (name, words) = parse_string(operands.get(2..)?);
+                if let Some(stage) = execution_model_stage(operand(0)?) {
+                    self.entry_points.push(EntryPoint {
+                        stage,
+                        id: operand(1)?,
+                        name,
+                        interface: operands.get(2 + words..)?.to_vec(),
+                    });
+                }
+            }
+            OP_EXECUTION_MODE | OP_EXECUTION_MODE_ID => match operand(1)? {
+                MODE_LOCAL_SIZE => {
+                    self.local_sizes.insert(operand(0)?, [operand(2)?, operand(3)?, operand(4)?]);
+                }
+                MODE_LOCAL_SIZE_ID => {
+                    self.local_size_ids.insert(operand(0)?, [operand(2)?, operand(3)?, operand(4)?]);
+                }
+                _ => {}
+            },
+            OP_TYPE_BOOL => { self.types.insert(operand(0)?, Type::Bool); }
+            OP_TYPE_INT => {
+                self.types.insert(operand(0)?, Type::Int { width: operand(1)?, signed: operand(2)? != 0 });
+            }
+            OP_TYPE_FLOAT => { self.types.insert(operand(0)?, Type::Float { width: operand(1)? }); }
+            OP_TYPE_VECTOR => {
+                self.types.insert(operand(0)?, Type::Vector { component: operand(1)?, count: operand(2)? });
+            }
+            OP_TYPE_MATRIX => {
+                self.types.insert(operand(0)?, Type::Matrix { column: operand(1)?, count: operand(2)? });
+            }
+            OP_TYPE_IMAGE => {
+                self.types.insert(operand(0)?, Type::Image { dim: operand(2)?, sampled: operand(6)? });
+            }
+            OP_TYPE_SAMPLER => { self.types.insert(operand(0)?, Type::Sampler); }
+            OP_TYPE_SAMPLED_IMAGE => { self.types.insert(operand(0)?, Type::SampledImage); }
+            OP_TYPE_ARRAY => {
+                self.types.insert(operand(0)?, Type::Array { element: operand(1)?, length: operand(2)? });
+            }
+            OP_TYPE_RUNTIME_ARRAY => { self.types.insert(operand(0)?, Type::RuntimeArray { element: operand(1)? }); }
+            OP_TYPE_STRUCT => {
+                self.types.insert(operand(0)?, Type::Struct);
+                self.struct_members.insert(operand(0)?, operands.get(1..)?.to_vec());
+            }
+            OP_TYPE_POINTER => {
+                self.types.insert(operand(0)?, Type::Pointer { pointee: operand(2)? });
+            }
+            OP_TYPE_ACCELERATION_STRUCTURE => { self.types.insert(operand(0)?, Type::AccelerationStructure); }
+            OP_CONSTANT | OP_SPEC_CONSTANT => {
+                self.constants.insert(operand(1)?, operand(2)?);
+                if opcode == OP_SPEC_CONSTANT {
+                    self.spec_constants.push((operand(1)?, operand(0)?));
+                }
+            }
+            OP_SPEC_CONSTANT_TRUE | OP_SPEC_CONSTANT_FALSE => {
+                self.constants.insert(operand(1)?, (opcode == OP_SPEC_CONSTANT_TRUE) as u32);
+                self.spec_constants.push((operand(1)?, operand(0)?));
+            }
+            OP_VARIABLE => {
+                self.variables.push(Variable { id: operand(1)?, pointer: operand(0)?, storage_class: operand(2)? });
+            }
+            OP_DECORATE => {
+                self.decorations.entry(operand(0)?).or_default().apply(operand(1)?, operand(2));
+            }
+            OP_MEMBER_DECORATE => {
+                self.member_decorations.entry((operand(0)?, operand(1)?)).or_default().apply(operand(2)?, operand(3));
+            }
+            _ => {}
+        }
+        Some(())
+    }
+
+    fn reflect(&self, entry_point: &EntryPoint) -> VisionResult<ShaderReflection> {
+        let mut descriptor_bindings = vec![];
+        let mut push_constant_range = None;
+        let mut inputs = vec![];
+        let mut outputs = vec![];
+
+        for variable in self.variables.iter() {
+            let pointee = match self.types.get(&variable.pointer) {
+                Some(Type::Pointer { pointee }) => *pointee,
+                // Untyped pointers carry no type to reflect.
+                _ => continue,
+            };
+            let decorations = self.decorations(variable.id);
+            match variable.storage_class {
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let (set, binding) = match (decorations.set, decorations.binding) {
+                        (Some(set), Some(binding)) => (set, binding),
+                        _ => continue,
+                    };
+                    let (element, count) = self.unwrap_arrays(pointee)?;
+                    let descriptor_type = self.descriptor_type(variable.storage_class, element)
+                        .ok_or_else(|| self.invalid(variable.id, "unsupported descriptor type"))?;
+                    descriptor_bindings.push(DescriptorBinding {
+                        set,
+                        binding,
+                        descriptor_type,
+                        count,
+                        stages: entry_point.stage,
+                        name: self.variable_name(variable.id, element),
+                    });
+                }
+                STORAGE_PUSH_CONSTANT => {
+                    let (offset, end) = self.struct_range(pointee, 0)?;
+                    // Push constant ranges are multiples of 4 bytes.
+                    let size = (end - offset).checked_next_multiple_of(4)
+                        .filter(|size| offset.checked_add(*size).is_some())
+                        .ok_or_else(|| self.invalid(pointee, "push constant block is too large"))?;
+                    push_constant_range = Some(vk::PushConstantRange {
+                        stage_flags: entry_point.stage,
+                        offset,
+                        size,
+                    });
+                }
+                STORAGE_INPUT | STORAGE_OUTPUT if entry_point.interface.contains(&variable.id) => {
+                    let is_input = variable.storage_class == STORAGE_INPUT;
+                    let collect = match entry_point.stage {
+                        vk::ShaderStageFlags::VERTEX => is_input,
+                        vk::ShaderStageFlags::FRAGMENT => !is_input,
+                        _ => false,
+                    };
+                    if !collect || self.is_built_in(variable.id, pointee) {
+                        continue;
+                    }
+                    let location = decorations.location
+                        .ok_or_else(|| self.invalid(variable.id, "interface variable without location"))?;
+                    let variables = if is_input { &mut inputs } else { &mut outputs };
+                    self.push_interface_variables(variables, location, pointee, self.variable_name(variable.id, pointee))?;
+                }
+                _ => {}
+            }
+        }
+        descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        inputs.sort_by_key(|variable| variable.location);
+        outputs.sort_by_key(|variable| variable.location);
+
+        let workgroup_size = match self.local_size_ids.get(&entry_point.id) {
+            Some(ids) => Some([self.constant(ids[0])?, self.constant(ids[1])?, self.constant(ids[2])?]),
+            None => self.local_sizes.get(&entry_point.id).copied(),
+        };
+
+        let specialization_constants = self.spec_constants.iter()
+            .filter_map(|&(constant, ty)| {
+                let id = self.decorations(constant).spec_id?;
+                Some(self.size_of(ty, None, 0).map(|size| SpecializationConstant {
+                    id,
+                    size,
+                    name: self.names.get(&constant).cloned().unwrap_or_default(),
+                }))
+            })
+            .collect::<VisionResult<Vec<_>>>()?;
+
+        Ok(ShaderReflection {
+            entry_point: entry_point.name.clone(),
+            stage: entry_point.stage,
+            descriptor_bindings,
+            push_constant_range,
+            inputs,
+            outputs,
+            workgroup_size,
+            specialization_constants,
+        })
+    }
+
+    fn descriptor_type(&self, storage_class: u32, ty: u32) -> Option<vk::DescriptorType> {
+        let descriptor_type = match (storage_class, self.types.get(&ty)?) {
+            (STORAGE_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_UNIFORM, _) if self.decorations(ty).buffer_block => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_UNIFORM, _) => vk::DescriptorType::UNIFORM_BUFFER,
+            (_, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (_, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (_, Type::AccelerationStructure) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            (_, Type::Image { dim: DIM_BUFFER, sampled: 2 }) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            (_, Type::Image { dim: DIM_BUFFER, .. }) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            (_, Type::Image { dim: DIM_SUBPASS_DATA, .. }) => vk::DescriptorType::INPUT_ATTACHMENT,
+            (_, Type::Image { sampled: 2, .. }) => vk::DescriptorType::STORAGE_IMAGE,
+            (_, Type::Image { .. }) => vk::DescriptorType::SAMPLED_IMAGE,
+            _ => return None,
+        };
+        Some(descriptor_type)
+    }
+
+    /// Element type and total length of nested arrays, runtime arrays have a length of 0.
+    fn unwrap_arrays(&self, mut ty: u32) -> VisionResult<(u32, u32)> {
+        let mut count = 1u32;
+        for _ in 0..MAX_TYPE_DEPTH {
+            match self.types.get(&ty) {
+                Some(Type::Array { element, length }) => {
+                    count = count.checked_mul(self.constant(*length)?)
+                        .ok_or_else(|| self.invalid(ty, "array is too large"))?;
+                    ty = *element;
+                }
+                Some(Type::RuntimeArray { element }) => {
+                    count = 0;
+                    ty = *element;
+                }
+                _ => return Ok((ty, count)),
+            }
+        }
+        Err(self.invalid(ty, "arrays are nested too deep"))
+    }
+
+    /// Byte offset of the first member and end of the last member of a struct.
+    fn struct_range(&self, ty: u32, depth: u32) -> VisionResult<(u32, u32)> {
+        let members = self.struct_members.get(&ty).ok_or_else(|| self.invalid(ty, "block is not a struct"))?;
+        let mut range: Option<(u32, u32)> = None;
+        for (index, &member) in members.iter().enumerate() {
+            let decorations = self.member_decorations.get(&(ty, index as u32)).copied().unwrap_or_default();
+            let offset = decorations.offset.unwrap_or(0);
+            let end = offset.checked_add(self.size_of(member, decorations.matrix_stride, depth)?)
+                .ok_or_else(|| self.invalid(ty, "member ends past the maximum block size"))?;
+            range = Some(match range {
+                Some((start, previous_end)) => (start.min(offset), previous_end.max(end)),
+                None => (offset, end),
+            });
+        }
+        Ok(range.unwrap_or((0, 0)))
+    }
+
+    /// Size in bytes as laid out in a block, runtime arrays have a size of 0. `depth` is the nesting level of `ty`.
+    fn size_of(&self, ty: u32, matrix_stride: Option<u32>, depth: u32) -> VisionResult<u32> {
+        if depth >= MAX_TYPE_DEPTH {
+            return Err(self.invalid(ty, "type is nested too deep"));
+        }
+        let size = match self.types.get(&ty) {
+            Some(Type::Bool) => Some(4),
+            Some(Type::Int { width, .. }) | Some(Type::Float { width }) => Some(width / 8),
+            Some(Type::Vector { component, count }) => count.checked_mul(self.size_of(*component, None, depth + 1)?),
+            Some(Type::Matrix { column, count }) => {
+                let stride = match matrix_stride {
+                    Some(stride) => stride,
+                    None => self.size_of(*column, None, depth + 1)?,
+                };
+                count.checked_mul(stride)
+            }
+            Some(Type::Array { element, length }) => {
+                let stride = match self.decorations(ty).array_stride {
+                    Some(stride) => stride,
+                    None => self.size_of(*element, matrix_stride, depth + 1)?,
+                };
+                self.constant(*length)?.checked_mul(stride)
+            }
+            Some(Type::RuntimeArray { .. }) => Some(0),
+            Some(Type::Struct) => Some(self.struct_range(ty, depth + 1)?.1),
+            _ => return Err(self.invalid(ty, "type has no size")),
+        };
+        size.ok_or_else(|| self.invalid(ty, "type is too large"))
+    }
+
+    fn push_interface_variables(&self,
+                                variables: &mut Vec<InterfaceVariable>,
+                                location: u32,
+                                ty: u32,
+                                name: String) -> VisionResult<()> {
+        // Arrays sized by specialization constants are reflected as a single variable of unknown format.
+        let (element, count) = self.unwrap_arrays(ty).unwrap_or((ty, 1));
+        let (column, columns) = match self.types.get(&element) {
+            Some(Type::Matrix { column, count }) => (*column, *count),
+            _ => (element, 1),
+        };
+        let format = self.format(column).unwrap_or(vk::Format::UNDEFINED);
+        let locations = count.max(1).checked_mul(columns)
+            .filter(|locations| location.checked_add(*locations).is_some())
+            .ok_or_else(|| self.invalid(ty, "interface variable exceeds the last location"))?;
+        for index in 0..locations {
+            variables.push(InterfaceVariable { location: location + index, format, name: name.clone() });
+        }
+        Ok(())
+    }
+
+    fn format(&self, ty: u32) -> Option<vk::Format> {
+        let (component, count) = match self.types.get(&ty)? {
+            Type::Vector { component, count } => (*component, *count),
+            _ => (ty, 1),
+        };
+        let formats = match self.types.get(&component)? {
+            Type::Int { width: 8, signed: true } => [vk::Format::R8_SINT, vk::Format::R8G8_SINT, vk::Format::R8G8B8_SINT, vk::Format::R8G8B8A8_SINT],
+            Type::Int { width: 8, signed: false } => [vk::Format::R8_UINT, vk::Format::R8G8_UINT, vk::Format::R8G8B8_UINT, vk::Format::R8G8B8A8_UINT],
+            Type::Float { width: 16 } => [vk::Format::R16_SFLOAT, vk::Format::R16G16_SFLOAT, vk::Format::R16G16B16_SFLOAT, vk::Format::R16G16B16A16_SFLOAT],
+            Type::Float { width: 32 } => [vk::Format::R32_SFLOAT, vk::Format::R32G32_SFLOAT, vk::Format::R32G32B32_SFLOAT, vk::Format::R32G32B32A32_SFLOAT],
+            Type::Float { width: 64 } => [vk::Format::R64_SFLOAT, vk::Format::R64G64_SFLOAT, vk::Format::R64G64B64_SFLOAT, vk::Format::R64G64B64A64_SFLOAT],
+            Type::Int { width: 16, signed: true } => [vk::Format::R16_SINT, vk::Format::R16G16_SINT, vk::Format::R16G16B16_SINT, vk::Format::R16G16B16A16_SINT],
+            Type::Int { width: 16, signed: false } => [vk::Format::R16_UINT, vk::Format::R16G16_UINT, vk::Format::R16G16B16_UINT, vk::Format::R16G16B16A16_UINT],
+            Type::Int { width: 32, signed: true } => [vk::Format::R32_SINT, vk::Format::R32G32_SINT, vk::Format::R32G32B32_SINT, vk::Format::R32G32B32A32_SINT],
+            Type::Int { width: 32, signed: false } => [vk::Format::R32_UINT, vk::Format::R32G32_UINT, vk::Format::R32G32B32_UINT, vk::Format::R32G32B32A32_UINT],
+            Type::Int { width: 64, signed: true } => [vk::Format::R64_SINT, vk::Format::R64G64_SINT, vk::Format::R64G64B64_SINT, vk::Format::R64G64B64A64_SINT],
+            Type::Int { width: 64, signed: false } => [vk::Format::R64_UINT, vk::Format::R64G64_UINT, vk::Format::R64G64B64_UINT, vk::Format::R64G64B64A64_UINT],
+            _ => return None,
+        };
+        formats.get(count.checked_sub(1)? as usize).copied()
+    }
+
+    /// Built-in variables and blocks of built-ins such as `gl_PerVertex`.
+    fn is_built_in(&self, variable: u32, ty: u32) -> bool {
+        let (element, _) = self.unwrap_arrays(ty).unwrap_or((ty, 1));
+        self.decorations(variable).built_in
+            || self.member_decorations.get(&(element, 0)).is_some_and(|decorations| decorations.built_in)
+    }
+
+    /// Name of the variable, or of its block type for blocks without instance name.
+    fn variable_name(&self, variable: u32, ty: u32) -> String {
+        self.names.get(&variable)
+            .filter(|name| !name.is_empty())
+            .or_else(|| self.names.get(&ty))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn decorations(&self, id: u32) -> Decorations {
+        self.decorations.get(&id).copied().unwrap_or_default()
+    }
+
+    fn constant(&self, id: u32) -> VisionResult<u32> {
+        self.constants.get(&id).copied().ok_or_else(|| self.invalid(id, "not a scalar constant"))
+    }
+
+    fn invalid(&self, id: u32, message: &str) -> VisionError {
+        VisionError::InvalidShader(format!("%{}: {}", id, message))
+    }
+}
+
+/// Decodes a nul terminated literal string, returns it with the number of words it takes.
+fn parse_string(words: &[u32]) -> (String, usize) {
+    let mut bytes = vec![];
+    for (index, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes() {
+            if byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), index + 1);
+            }
+            bytes.push(byte);
+        }
+    }
+    (String::from_utf8_lossy(&bytes).into_owned(), words.len())
+}
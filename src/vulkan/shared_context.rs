@@ -1,46 +1,77 @@
-use ash::{Entry, vk};
-use ash::version::InstanceV1_0;
+use ash::Entry;
 use winit::window::Window;
 
-use crate::vulkan::{Device, Instance, PhysicalDevice, Surface};
+use crate::vulkan::{Device, DeviceRequirements, DeviceSelector, Instance, PhysicalDevice, Surface, VisionResult};
 use crate::vulkan::debug::ValidationInfo;
+use crate::vulkan::memory::Allocator;
 
+// Fields are dropped in declaration order, the allocator has to go before the device and the device before the
+// surface and the instance.
 pub struct SharedContext {
-    entry: Entry,
-    instance: Instance,
-    surface: Surface,
+    allocator: Allocator,
     device: Device,
+    surface: Option<Surface>,
+    instance: Instance,
+    _entry: Entry,
 }
 
 impl SharedContext {
 
-    pub fn new(window: &Window, validation_info: ValidationInfo, required_extensions: Vec<*const i8>) -> Self {
-        let entry = Entry::new().expect("Failed to create Entry");
+    pub fn new(window: &Window,
+               validation_info: ValidationInfo,
+               requirements: DeviceRequirements,
+               device_selector: Option<DeviceSelector>) -> VisionResult<Self> {
+        let entry = unsafe { Entry::load() }?;
 
-        let instance = Instance::new(&entry, &window, validation_info);
-        let surface = Surface::new(&entry, &instance, &window);
+        let instance = Instance::new(&entry, Some(window), validation_info)?;
+        let surface = Surface::new(&entry, &instance, window)?;
 
-        let physical_device = PhysicalDevice::optimal_device(&instance, &surface, required_extensions);
-        let device = Device::new(&instance, physical_device);
+        let physical_device = PhysicalDevice::optimal_device(&instance, Some(&surface), requirements, device_selector)?;
+        let device = Device::new(&instance, physical_device)?;
 
-        Self {
-            entry,
+        Ok(Self {
+            allocator: Allocator::new(&instance, &device),
+            device,
+            surface: Some(surface),
             instance,
-            surface,
+            _entry: entry,
+        })
+    }
+
+    /// Creates a context without a window, surface or present queue, suited for offscreen rendering and compute.
+    pub fn new_headless(validation_info: ValidationInfo,
+                        requirements: DeviceRequirements,
+                        device_selector: Option<DeviceSelector>) -> VisionResult<Self> {
+        let entry = unsafe { Entry::load() }?;
+
+        let instance = Instance::new(&entry, None, validation_info)?;
+
+        let physical_device = PhysicalDevice::optimal_device(&instance, None, requirements, device_selector)?;
+        let device = Device::new(&instance, physical_device)?;
+
+        Ok(Self {
+            allocator: Allocator::new(&instance, &device),
             device,
-        }
+            surface: None,
+            instance,
+            _entry: entry,
+        })
     }
 
     pub fn instance(&self) -> &Instance {
         &self.instance
     }
 
-    pub fn surface(&self) -> &Surface {
-        &self.surface
+    pub fn surface(&self) -> Option<&Surface> {
+        self.surface.as_ref()
     }
 
     pub fn device(&self) -> &Device {
         &self.device
     }
 
-}
\ No newline at end of file
+    pub fn allocator(&self) -> &Allocator {
+        &self.allocator
+    }
+
+}
@@ -0,0 +1,205 @@
+use std::any::Any;
+use std::mem;
+use std::time::Duration;
+
+use ash::vk;
+
+use crate::vulkan::{Context, QueueType, VisionResult};
+use crate::vulkan::error::VkResultExt;
+
+/// Pending one time submission created by `Context::execute_async`. The command buffers, the result of the recording
+/// closure and resources passed to `keep_alive` are kept until the GPU completed the work. Dropping a submission
+/// that has not been waited for blocks until it completes.
+pub struct Submission<'a, R> {
+    context: &'a Context,
+    batches: Vec<Batch>,
+    result: Option<R>,
+    resources: Vec<Box<dyn Any>>,
+}
+
+/// A command buffer submitted with a fence to wait on from the host and a semaphore to chain further submissions.
+struct Batch {
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    semaphore: vk::Semaphore,
+}
+
+impl<'a, R> Submission<'a, R> {
+    pub(crate) fn new<F: FnOnce(vk::CommandBuffer) -> R>(context: &'a Context,
+                                                          queue_type: QueueType,
+                                                          executor: F) -> VisionResult<Self> {
+        let (batch, result) = Batch::submit(context, queue_type, None, executor)?;
+        Ok(Self {
+            context,
+            batches: vec![batch],
+            result: Some(result),
+            resources: vec![],
+        })
+    }
+
+    /// Records another command buffer that starts executing once this submission completed on the GPU, the host does
+    /// not wait in between. Resources with exclusive sharing mode need a queue family ownership transfer when the
+    /// queue family changes.
+    pub fn then<R2, F: FnOnce(vk::CommandBuffer) -> R2>(mut self,
+                                                        queue_type: QueueType,
+                                                        executor: F) -> VisionResult<Submission<'a, (R, R2)>> {
+        let wait_semaphore = self.batches.last().map(|batch| batch.semaphore);
+        let (batch, result) = Batch::submit(self.context, queue_type, wait_semaphore, executor)?;
+
+        let mut batches = mem::take(&mut self.batches);
+        batches.push(batch);
+        let previous_result = self.result.take().expect("result is only taken once");
+
+        Ok(Submission {
+            context: self.context,
+            batches,
+            result: Some((previous_result, result)),
+            resources: mem::take(&mut self.resources),
+        })
+    }
+
+    /// Keeps `resource` alive until the submission completed, e.g. a staging buffer the commands read from.
+    pub fn keep_alive<T: Any>(&mut self, resource: T) {
+        self.resources.push(Box::new(resource));
+    }
+
+    pub fn is_complete(&self) -> VisionResult<bool> {
+        for batch in self.batches.iter() {
+            let signaled = unsafe {
+                self.context.device().vk_device().get_fence_status(batch.fence)
+            }.map_vk_err("vkGetFenceStatus")?;
+            if !signaled {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Waits at most `timeout` and returns whether the submission completed.
+    pub fn wait_timeout(&self, timeout: Duration) -> VisionResult<bool> {
+        self.wait_fences(timeout.as_nanos().min(u64::MAX as u128) as u64)
+    }
+
+    /// Waits for completion, releases the command buffers and returns the result of the recording closures.
+    pub fn wait(mut self) -> VisionResult<R> {
+        self.wait_fences(u64::MAX)?;
+        self.release();
+        Ok(self.result.take().expect("result is only taken once"))
+    }
+
+    /// Fences of the submitted batches, e.g. to wait on several submissions at once.
+    pub fn vk_fences(&self) -> Vec<vk::Fence> {
+        self.batches.iter().map(|batch| batch.fence).collect()
+    }
+
+    fn wait_fences(&self, timeout: u64) -> VisionResult<bool> {
+        if self.batches.is_empty() {
+            return Ok(true);
+        }
+
+        let result = unsafe {
+            self.context.device().vk_device().wait_for_fences(&self.vk_fences(), true, timeout)
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(error) => Err(error).map_vk_err("vkWaitForFences"),
+        }
+    }
+
+    fn release(&mut self) {
+        for batch in self.batches.drain(..) {
+            batch.destroy(self.context);
+        }
+        self.resources.clear();
+    }
+}
+
+impl<R> Drop for Submission<'_, R> {
+    fn drop(&mut self) {
+        // Drop cannot report errors, waiting only fails when the device is lost and the command buffers and fences are
+        // released regardless.
+        let _ = self.wait_fences(u64::MAX);
+        self.release();
+    }
+}
+
+impl Batch {
+    fn submit<R, F: FnOnce(vk::CommandBuffer) -> R>(context: &Context,
+                                                     queue_type: QueueType,
+                                                     wait_semaphore: Option<vk::Semaphore>,
+                                                     executor: F) -> VisionResult<(Self, R)> {
+        let device = context.device().vk_device();
+        let mut batch = Batch {
+            command_pool: context.transient_command_pool(queue_type).vk_command_pool(),
+            command_buffer: vk::CommandBuffer::null(),
+            fence: vk::Fence::null(),
+            semaphore: vk::Semaphore::null(),
+        };
+
+        let result = (|| {
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_pool(batch.command_pool)
+                .command_buffer_count(1);
+            batch.command_buffer = unsafe {
+                device.allocate_command_buffers(&alloc_info).map_vk_err("vkAllocateCommandBuffers")?[0]
+            };
+            batch.fence = unsafe {
+                device.create_fence(&vk::FenceCreateInfo::default(), None).map_vk_err("vkCreateFence")?
+            };
+            batch.semaphore = unsafe {
+                device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None).map_vk_err("vkCreateSemaphore")?
+            };
+
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            unsafe {
+                device.begin_command_buffer(batch.command_buffer, &begin_info).map_vk_err("vkBeginCommandBuffer")?
+            };
+
+            let executor_result = executor(batch.command_buffer);
+
+            unsafe {
+                device.end_command_buffer(batch.command_buffer).map_vk_err("vkEndCommandBuffer")?
+            };
+
+            let command_buffers = [batch.command_buffer];
+            let signal_semaphores = [batch.semaphore];
+            let wait_semaphores = wait_semaphore.into_iter().collect::<Vec<_>>();
+            let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+            let submit_info = vk::SubmitInfo::builder()
+                .command_buffers(&command_buffers)
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .signal_semaphores(&signal_semaphores)
+                .build();
+            unsafe {
+                device.queue_submit(context.queue(queue_type), &[submit_info], batch.fence)
+                    .map_vk_err("vkQueueSubmit")?
+            };
+
+            Ok(executor_result)
+        })();
+
+        match result {
+            Ok(executor_result) => Ok((batch, executor_result)),
+            Err(error) => {
+                batch.destroy(context);
+                Err(error)
+            }
+        }
+    }
+
+    fn destroy(self, context: &Context) {
+        let device = context.device().vk_device();
+        unsafe {
+            if self.command_buffer != vk::CommandBuffer::null() {
+                device.free_command_buffers(self.command_pool, &[self.command_buffer]);
+            }
+            device.destroy_semaphore(self.semaphore, None);
+            device.destroy_fence(self.fence, None);
+        }
+    }
+}
@@ -3,7 +3,8 @@ use ash::extensions::khr::Surface as VkSurface;
 use ash_window;
 use winit::window::Window;
 
-use crate::vulkan::Instance;
+use crate::vulkan::{Instance, VisionResult};
+use crate::vulkan::error::VkResultExt;
 
 pub struct Surface {
     surface: vk::SurfaceKHR,
@@ -11,16 +12,16 @@ pub struct Surface {
 }
 
 impl Surface {
-    pub fn new(entry: &Entry, instance: &Instance, window: &Window) -> Self {
+    pub fn new(entry: &Entry, instance: &Instance, window: &Window) -> VisionResult<Self> {
         let surface_loader = VkSurface::new(entry, instance.vk_instance());
         let surface = unsafe {
             ash_window::create_surface(entry, instance.vk_instance(), window, None)
-        }.unwrap();
+        }.map_vk_err("vkCreateSurfaceKHR")?;
 
-        Self {
+        Ok(Self {
             surface,
             surface_loader,
-        }
+        })
     }
 
     pub fn vk_surface(&self) -> &VkSurface {
@@ -31,13 +32,10 @@ impl Surface {
         &self.surface
     }
 
-    pub fn required_extension_names(window: &Window) -> Vec<*const i8> {
+    pub fn required_extension_names(window: &Window) -> VisionResult<Vec<*const i8>> {
         let pointers = ash_window::enumerate_required_extensions(window)
-            .unwrap()
-            .iter()
-            .map(|name| name.as_ptr())
-            .collect();
-        pointers
+            .map_vk_err("enumerate_required_extensions")?;
+        Ok(pointers.to_vec())
     }
 }
 
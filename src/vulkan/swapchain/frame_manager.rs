@@ -0,0 +1,278 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::{Context, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::swapchain::PresentTarget;
+
+/// Outcome of presenting a frame, the swapchain should be recreated unless it is `Optimal`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwapchainStatus {
+    Optimal,
+    Suboptimal,
+    OutOfDate,
+}
+
+/// A frame between `FrameManager::begin_frame` and `FrameManager::end_frame`, its command buffer is recording.
+pub struct Frame {
+    frame_index: usize,
+    image_index: u32,
+    command_buffer: vk::CommandBuffer,
+    suboptimal: bool,
+}
+
+impl Frame {
+    /// Index of the frame in flight, e.g. to pick per frame uniform buffers.
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    /// Index of the acquired swapchain image.
+    pub fn image_index(&self) -> u32 {
+        self.image_index
+    }
+
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+}
+
+struct FrameSync {
+    command_buffer: vk::CommandBuffer,
+    image_available: vk::Semaphore,
+    in_flight: vk::Fence,
+}
+
+/// Owns the command buffers and synchronization objects of `frames_in_flight` frames that are recorded while
+/// previous frames still render.
+pub struct FrameManager {
+    context: Arc<Context>,
+    frames: Vec<FrameSync>,
+    // Render finished semaphores are per swapchain image, presentation may still wait on the semaphore of an image
+    // when the frame slot comes around again.
+    render_finished: Vec<vk::Semaphore>,
+    // Fence of the frame that last rendered to each swapchain image.
+    images_in_flight: Vec<vk::Fence>,
+    current_frame: usize,
+}
+
+impl FrameManager {
+    pub fn new(context: Arc<Context>, frames_in_flight: usize) -> VisionResult<Self> {
+        let mut manager = Self {
+            context: Arc::clone(&context),
+            frames: vec![],
+            render_finished: vec![],
+            images_in_flight: vec![],
+            current_frame: 0,
+        };
+
+        let device = context.device().vk_device();
+        for _ in 0..frames_in_flight.max(1) {
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_pool(context.general_command_pool().vk_command_pool())
+                .command_buffer_count(1);
+            let command_buffer = unsafe {
+                device.allocate_command_buffers(&alloc_info).map_vk_err("vkAllocateCommandBuffers")?[0]
+            };
+            let mut frame = FrameSync {
+                command_buffer,
+                image_available: vk::Semaphore::null(),
+                in_flight: vk::Fence::null(),
+            };
+
+            // Stored as they are created so that drop releases the handles created so far on failure.
+            let result = unsafe {
+                device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                    .map_vk_err("vkCreateSemaphore")
+                    .map(|semaphore| frame.image_available = semaphore)
+                    .and_then(|_| {
+                        let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+                        device.create_fence(&fence_info, None).map_vk_err("vkCreateFence")
+                    })
+                    .map(|fence| frame.in_flight = fence)
+            };
+            manager.frames.push(frame);
+            result?;
+        }
+
+        Ok(manager)
+    }
+
+    /// Waits until the oldest frame finished, acquires the next image of `swapchain` and begins recording. Returns
+    /// `None` if the swapchain is out of date and has to be recreated.
+    pub fn begin_frame<T: PresentTarget + ?Sized>(&mut self, swapchain: &T) -> VisionResult<Option<Frame>> {
+        self.ensure_image_resources(swapchain)?;
+
+        let device = self.context.device().vk_device();
+        let frame = &self.frames[self.current_frame];
+
+        unsafe {
+            device.wait_for_fences(&[frame.in_flight], true, u64::MAX).map_vk_err("vkWaitForFences")?;
+        }
+
+        let (image_index, suboptimal) = match swapchain.acquire_next_image(frame.image_available, u64::MAX) {
+            Ok(acquired) => acquired,
+            Err(error) if error.vk_result() == Some(vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        // The image may still be in use by a frame other than the one that last used this slot.
+        let image_fence = self.images_in_flight[image_index as usize];
+        if image_fence != vk::Fence::null() && image_fence != frame.in_flight {
+            unsafe {
+                device.wait_for_fences(&[image_fence], true, u64::MAX).map_vk_err("vkWaitForFences")?;
+            }
+        }
+        self.images_in_flight[image_index as usize] = frame.in_flight;
+
+        unsafe {
+            device.reset_command_buffer(frame.command_buffer, vk::CommandBufferResetFlags::empty())
+                .map_vk_err("vkResetCommandBuffer")?;
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device.begin_command_buffer(frame.command_buffer, &begin_info)
+                .map_vk_err("vkBeginCommandBuffer")?;
+        }
+
+        Ok(Some(Frame {
+            frame_index: self.current_frame,
+            image_index,
+            command_buffer: frame.command_buffer,
+            suboptimal,
+        }))
+    }
+
+    /// Ends recording, submits the frame to the graphics queue and presents its image.
+    pub fn end_frame<T: PresentTarget + ?Sized>(&mut self, swapchain: &T, frame: Frame) -> VisionResult<SwapchainStatus> {
+        let device = self.context.device().vk_device();
+        let sync = &self.frames[frame.frame_index];
+        let render_finished = self.render_finished[frame.image_index as usize];
+
+        unsafe {
+            device.end_command_buffer(frame.command_buffer).map_vk_err("vkEndCommandBuffer")?;
+        }
+
+        let wait_semaphores = [sync.image_available];
+        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let command_buffers = [frame.command_buffer];
+        let signal_semaphores = [render_finished];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores)
+            .build();
+
+        let submitted = unsafe {
+            device.reset_fences(&[sync.in_flight])
+                .map_vk_err("vkResetFences")
+                .and_then(|_| {
+                    device.queue_submit(self.context.device().graphics_queue(), &[submit_info], sync.in_flight)
+                        .map_vk_err("vkQueueSubmit")
+                })
+        };
+        if let Err(error) = submitted {
+            // The reset fence would never be signaled and the next `begin_frame` of this slot would wait forever.
+            self.recreate_in_flight_fence(frame.frame_index)?;
+            return Err(error);
+        }
+
+        self.current_frame = (self.current_frame + 1) % self.frames.len();
+
+        match swapchain.present(frame.image_index, &signal_semaphores) {
+            Ok(false) if !frame.suboptimal => Ok(SwapchainStatus::Optimal),
+            Ok(_) => Ok(SwapchainStatus::Suboptimal),
+            Err(error) if error.vk_result() == Some(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(SwapchainStatus::OutOfDate),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Waits until all frames in flight finished rendering.
+    pub fn wait_idle(&self) -> VisionResult<()> {
+        let fences = self.frames.iter()
+            .map(|frame| frame.in_flight)
+            .filter(|fence| *fence != vk::Fence::null())
+            .collect::<Vec<_>>();
+        if fences.is_empty() {
+            return Ok(());
+        }
+        unsafe {
+            self.context.device().vk_device().wait_for_fences(&fences, true, u64::MAX).map_vk_err("vkWaitForFences")
+        }
+    }
+
+    /// Presentation has no fence, waiting for the present queue ensures the semaphores are no longer in use.
+    fn wait_present_idle(&self) -> VisionResult<()> {
+        self.wait_idle()?;
+        if let Some(queue) = self.context.device().present_queue() {
+            unsafe {
+                self.context.device().vk_device().queue_wait_idle(queue).map_vk_err("vkQueueWaitIdle")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the fence of a frame with a signaled one.
+    fn recreate_in_flight_fence(&mut self, frame_index: usize) -> VisionResult<()> {
+        let device = self.context.device().vk_device();
+        let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+        let fence = unsafe { device.create_fence(&fence_info, None).map_vk_err("vkCreateFence")? };
+
+        let old_fence = std::mem::replace(&mut self.frames[frame_index].in_flight, fence);
+        for image_fence in self.images_in_flight.iter_mut().filter(|image_fence| **image_fence == old_fence) {
+            *image_fence = vk::Fence::null();
+        }
+        unsafe { device.destroy_fence(old_fence, None) };
+        Ok(())
+    }
+
+    /// Recreates the per image semaphores when the swapchain image count changed.
+    fn ensure_image_resources<T: PresentTarget + ?Sized>(&mut self, swapchain: &T) -> VisionResult<()> {
+        let image_count = swapchain.images().len();
+        if self.render_finished.len() == image_count {
+            return Ok(());
+        }
+
+        self.wait_present_idle()?;
+        self.destroy_render_finished();
+        self.images_in_flight = vec![vk::Fence::null(); image_count];
+
+        for _ in 0..image_count {
+            let semaphore = unsafe {
+                self.context.device().vk_device()
+                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                    .map_vk_err("vkCreateSemaphore")?
+            };
+            self.render_finished.push(semaphore);
+        }
+        Ok(())
+    }
+
+    fn destroy_render_finished(&mut self) {
+        for semaphore in self.render_finished.drain(..) {
+            unsafe { self.context.device().vk_device().destroy_semaphore(semaphore, None) };
+        }
+    }
+}
+
+impl Drop for FrameManager {
+    fn drop(&mut self) {
+        let _ = self.wait_present_idle();
+        self.destroy_render_finished();
+
+        let device = self.context.device().vk_device();
+        for frame in self.frames.drain(..) {
+            unsafe {
+                device.destroy_fence(frame.in_flight, None);
+                device.destroy_semaphore(frame.image_available, None);
+                device.free_command_buffers(self.context.general_command_pool().vk_command_pool(), &[frame.command_buffer]);
+            }
+        }
+    }
+}
@@ -1,6 +1,16 @@
+pub use self::frame_manager::{Frame, FrameManager, SwapchainStatus};
+pub use self::output_encoding::{OutputEncoding, OutputParameters};
+pub use self::present_target::PresentTarget;
 pub use self::swapchain::Swapchain;
+pub use self::swapchain_config::{HdrMetadata, PresentMode, SwapchainConfig};
 pub use self::swapchain_support_details::SwapchainSupportDetails;
+pub use self::virtual_swapchain::VirtualSwapchain;
 
+mod frame_manager;
+mod output_encoding;
+mod present_target;
+mod swapchain_config;
 mod swapchain_support_details;
+mod virtual_swapchain;
+#[allow(clippy::module_inception)]
 mod swapchain;
-
@@ -0,0 +1,51 @@
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+
+/// How the final shader stage encodes linear BT.709 scene colors for a swapchain format, the values match the
+/// `OUTPUT_ENCODING_*` constants of `encode_output` in `assets/shaders/output_encoding.glsl`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// Tonemapped to SDR, the `_SRGB` format applies the transfer function on store.
+    Srgb = 0,
+    /// Tonemapped to SDR with the sRGB transfer function applied in the shader.
+    SrgbUnorm = 1,
+    /// Linear BT.709 where 1.0 is 80 nits, values above 1.0 are brighter than SDR white.
+    ExtendedSrgbLinear = 2,
+    /// BT.2020 primaries with the SMPTE ST 2084 (PQ) transfer function.
+    Hdr10 = 3,
+}
+
+impl OutputEncoding {
+    pub fn from_surface_format(surface_format: vk::SurfaceFormatKHR) -> Self {
+        match surface_format.color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => OutputEncoding::Hdr10,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => OutputEncoding::ExtendedSrgbLinear,
+            _ => match surface_format.format {
+                vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32 => {
+                    OutputEncoding::Srgb
+                }
+                _ => OutputEncoding::SrgbUnorm,
+            },
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, OutputEncoding::ExtendedSrgbLinear | OutputEncoding::Hdr10)
+    }
+}
+
+/// Arguments of `encode_output`, laid out to be passed to the final shader stage as push constants or uniforms.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputParameters {
+    pub encoding: u32,
+    /// Luminance of scene color 1.0 on HDR outputs.
+    pub paper_white_nits: f32,
+    /// HDR outputs are clamped to this luminance.
+    pub max_nits: f32,
+}
+
+unsafe impl Zeroable for OutputParameters {}
+
+unsafe impl Pod for OutputParameters {}
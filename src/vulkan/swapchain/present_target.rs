@@ -0,0 +1,31 @@
+use ash::vk;
+
+use crate::vulkan::{Image, VisionResult};
+use crate::vulkan::swapchain::OutputParameters;
+
+/// Ring of images frames are rendered into and presented from, implemented by the window `Swapchain` and the
+/// offscreen `VirtualSwapchain`.
+pub trait PresentTarget {
+    /// Acquires the next image, `semaphore` is signaled once it can be rendered to. Returns the image index and
+    /// whether the target is suboptimal, `ERROR_OUT_OF_DATE_KHR` is passed on as error.
+    fn acquire_next_image(&self, semaphore: vk::Semaphore, timeout: u64) -> VisionResult<(u32, bool)>;
+
+    /// Presents `image_index` after `wait_semaphores` are signaled, returns whether the target is suboptimal.
+    fn present(&self, image_index: u32, wait_semaphores: &[vk::Semaphore]) -> VisionResult<bool>;
+
+    fn images(&self) -> &[Image];
+
+    fn image_views(&self) -> &[vk::ImageView];
+
+    fn image_format(&self) -> vk::Format;
+
+    fn extent(&self) -> &vk::Extent2D;
+
+    /// Layout the images have to be in when they are presented, render passes targeting it end in this layout.
+    fn present_layout(&self) -> vk::ImageLayout;
+
+    /// Encoding of the presented colors, `None` if the images hold them as rendered.
+    fn output_parameters(&self) -> Option<OutputParameters> {
+        None
+    }
+}
@@ -1,49 +1,332 @@
+use std::mem;
 use std::sync::Arc;
 
 use ash::extensions::khr::Swapchain as VkSwapchain;
-use ash::version::DeviceV1_0;
 use ash::vk;
 
-use crate::vulkan::{Context, Device, Image, Instance, Surface};
-use crate::vulkan::render_pass::RenderPass;
-use crate::vulkan::swapchain::SwapchainSupportDetails;
+use crate::vulkan::{Context, Image, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::swapchain::{OutputEncoding, OutputParameters, PresentTarget, SwapchainConfig, SwapchainSupportDetails};
 
 pub struct Swapchain {
     context: Arc<Context>,
+    swapchain_loader: VkSwapchain,
+    hdr_metadata_fn: Option<vk::ExtHdrMetadataFn>,
+    config: SwapchainConfig,
+    // Set by `set_config` until the next `recreate`, acquire and present report the swapchain as suboptimal.
+    config_changed: bool,
+    resources: SwapchainResources,
+    // Replaced swapchains with fences signaled once the work submitted before their replacement completed.
+    retired: Vec<(SwapchainResources, Vec<vk::Fence>)>,
+}
 
+/// A swapchain with the views of its images, released on drop.
+struct SwapchainResources {
+    context: Arc<Context>,
     swapchain_loader: VkSwapchain,
     swapchain: vk::SwapchainKHR,
     images: Vec<Image>,
     image_views: Vec<vk::ImageView>,
-    framebuffers: Vec<vk::Framebuffer>,
 
     format: vk::SurfaceFormatKHR,
     present_mode: vk::PresentModeKHR,
     extent: vk::Extent2D,
     image_count: u32,
+    image_usage: vk::ImageUsageFlags,
 }
 
 impl Swapchain {
-    pub fn new(context: Arc<Context>, render_pass: &RenderPass, preferred_dimensions: [u32; 2]) -> Self {
-        let support_details = SwapchainSupportDetails::new(context.device().physical_device(), context.surface());
-        let format = support_details.optimal_surface_format();
-        let present_mode = support_details.optimal_present_mode();
+    /// Fails with `ZeroSizedSurface` while the window is minimized. Framebuffers over the image views are created by
+    /// the consumer, e.g. with `RenderPass::create_framebuffers`.
+    pub fn new(context: Arc<Context>, preferred_dimensions: [u32; 2], config: SwapchainConfig) -> VisionResult<Self> {
+        let swapchain_loader = VkSwapchain::new(context.instance().vk_instance(), context.device().vk_device());
+        let resources = SwapchainResources::new(&context,
+                                                &swapchain_loader,
+                                                preferred_dimensions,
+                                                &config,
+                                                vk::SwapchainKHR::null())?
+            .ok_or(VisionError::ZeroSizedSurface)?;
+
+        let hdr_metadata_fn = if context.device().is_extension_enabled(vk::ExtHdrMetadataFn::name()) {
+            let instance = context.instance().vk_instance();
+            let device = context.device().vk_device().handle();
+            Some(vk::ExtHdrMetadataFn::load(|name| unsafe {
+                mem::transmute(instance.get_device_proc_addr(device, name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        let swapchain = Self {
+            context,
+            swapchain_loader,
+            hdr_metadata_fn,
+            config,
+            config_changed: false,
+            resources,
+            retired: vec![],
+        };
+        swapchain.apply_hdr_metadata();
+        Ok(swapchain)
+    }
+
+    /// Rebuilds the swapchain after a resize or an out of date or suboptimal result, framebuffers over the previous
+    /// image views have to be recreated. The old swapchain is passed as `old_swapchain` and destroyed once the work
+    /// submitted so far completed. Returns `false` without recreating while the window is minimized.
+    pub fn recreate(&mut self, preferred_dimensions: [u32; 2]) -> VisionResult<bool> {
+        self.destroy_idle_retired()?;
+
+        let resources = SwapchainResources::new(&self.context,
+                                                &self.swapchain_loader,
+                                                preferred_dimensions,
+                                                &self.config,
+                                                self.resources.swapchain)?;
+        let resources = match resources {
+            Some(resources) => resources,
+            None => return Ok(false),
+        };
+
+        // The images are rendered to on the graphics queue and presented on the present queue.
+        let present_queue = self.context.device().present_queue().ok_or(VisionError::MissingSurface)?;
+        let mut queues = vec![self.context.device().graphics_queue(), present_queue];
+        queues.dedup();
+        let mut fences = Vec::with_capacity(queues.len());
+        for queue in queues {
+            match self.signal_fence(queue) {
+                Ok(fence) => fences.push(fence),
+                Err(error) => {
+                    Self::destroy_fences(&self.context, &fences);
+                    return Err(error);
+                }
+            }
+        }
+
+        let old_resources = mem::replace(&mut self.resources, resources);
+        self.retired.push((old_resources, fences));
+        self.config_changed = false;
+        self.apply_hdr_metadata();
+        Ok(true)
+    }
+
+    /// An empty submission signals its fence once all previously submitted work on the queue completed.
+    fn signal_fence(&self, queue: vk::Queue) -> VisionResult<vk::Fence> {
+        let device = self.context.device().vk_device();
+        let fence = unsafe {
+            device.create_fence(&vk::FenceCreateInfo::default(), None).map_vk_err("vkCreateFence")?
+        };
+        if let Err(error) = unsafe { device.queue_submit(queue, &[], fence) }.map_vk_err("vkQueueSubmit") {
+            unsafe { device.destroy_fence(fence, None) };
+            return Err(error);
+        }
+        Ok(fence)
+    }
+
+    fn destroy_fences(context: &Context, fences: &[vk::Fence]) {
+        for fence in fences {
+            unsafe { context.device().vk_device().destroy_fence(*fence, None) };
+        }
+    }
+
+    /// Passes the configured HDR metadata to the display if `VK_EXT_hdr_metadata` is enabled and the swapchain uses an
+    /// HDR color space.
+    fn apply_hdr_metadata(&self) {
+        let (hdr_metadata_fn, metadata) = match (&self.hdr_metadata_fn, &self.config.hdr_metadata) {
+            (Some(hdr_metadata_fn), Some(metadata)) if self.output_encoding().is_hdr() => (hdr_metadata_fn, metadata),
+            _ => return,
+        };
+        let swapchains = [self.resources.swapchain];
+        let metadata = [metadata.vk_hdr_metadata()];
+        unsafe {
+            (hdr_metadata_fn.set_hdr_metadata_ext)(self.context.device().vk_device().handle(),
+                                                   1,
+                                                   swapchains.as_ptr(),
+                                                   metadata.as_ptr());
+        }
+    }
+
+    /// Destroys replaced swapchains whose images are no longer in use.
+    pub fn destroy_idle_retired(&mut self) -> VisionResult<()> {
+        let device = self.context.device().vk_device();
+        let mut index = 0;
+        while index < self.retired.len() {
+            let mut signaled = true;
+            for fence in self.retired[index].1.iter() {
+                signaled &= unsafe { device.get_fence_status(*fence) }.map_vk_err("vkGetFenceStatus")?;
+            }
+            if signaled {
+                let (_, fences) = self.retired.swap_remove(index);
+                Self::destroy_fences(&self.context, &fences);
+            } else {
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Acquires the next presentable image, `semaphore` is signaled once it can be rendered to. Returns the image
+    /// index and whether the swapchain is suboptimal, `ERROR_OUT_OF_DATE_KHR` is passed on as error.
+    pub fn acquire_next_image(&self, semaphore: vk::Semaphore, timeout: u64) -> VisionResult<(u32, bool)> {
+        let (image_index, suboptimal) = unsafe {
+            self.swapchain_loader.acquire_next_image(self.resources.swapchain, timeout, semaphore, vk::Fence::null())
+        }.map_vk_err("vkAcquireNextImageKHR")?;
+        Ok((image_index, suboptimal || self.config_changed))
+    }
+
+    /// Queues `image_index` for presentation after `wait_semaphores` are signaled, returns whether the swapchain is
+    /// suboptimal.
+    pub fn present(&self, image_index: u32, wait_semaphores: &[vk::Semaphore]) -> VisionResult<bool> {
+        let queue = self.context.device().present_queue().ok_or(VisionError::MissingSurface)?;
+        let swapchains = [self.resources.swapchain];
+        let image_indices = [image_index];
+        let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(wait_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices);
+
+        let suboptimal = unsafe {
+            self.swapchain_loader.queue_present(queue, &present_info)
+        }.map_vk_err("vkQueuePresentKHR")?;
+        Ok(suboptimal || self.config_changed)
+    }
+
+    pub fn config(&self) -> &SwapchainConfig {
+        &self.config
+    }
+
+    /// Replaces the preferences, they take effect with the next `recreate`. Until then the swapchain is reported as
+    /// suboptimal by `acquire_next_image` and `present`.
+    pub fn set_config(&mut self, config: SwapchainConfig) {
+        self.config = config;
+        self.config_changed = true;
+    }
+
+    pub fn vk_swapchain(&self) -> vk::SwapchainKHR {
+        self.resources.swapchain
+    }
+
+    pub fn images(&self) -> &[Image] {
+        &self.resources.images
+    }
+
+    pub fn image_views(&self) -> &[vk::ImageView] {
+        &self.resources.image_views
+    }
+
+    pub fn image_usage(&self) -> vk::ImageUsageFlags {
+        self.resources.image_usage
+    }
+
+    pub fn format(&self) -> &vk::SurfaceFormatKHR {
+        &self.resources.format
+    }
+
+    /// Encoding the final shader stage has to apply for the surface format in use.
+    pub fn output_encoding(&self) -> OutputEncoding {
+        OutputEncoding::from_surface_format(self.resources.format)
+    }
+
+    /// Arguments of `encode_output` for the surface format in use, HDR outputs are clamped to the mastering luminance
+    /// of the HDR metadata.
+    pub fn output_parameters(&self) -> OutputParameters {
+        OutputParameters {
+            encoding: self.output_encoding() as u32,
+            paper_white_nits: self.config.paper_white_nits,
+            max_nits: self.config.hdr_metadata.map_or(10000.0, |metadata| metadata.max_luminance),
+        }
+    }
+
+    pub fn present_mode(&self) -> &vk::PresentModeKHR {
+        &self.resources.present_mode
+    }
+
+    pub fn extent(&self) -> &vk::Extent2D {
+        &self.resources.extent
+    }
+
+    pub fn image_count(&self) -> &u32 {
+        &self.resources.image_count
+    }
+}
+
+impl PresentTarget for Swapchain {
+    fn acquire_next_image(&self, semaphore: vk::Semaphore, timeout: u64) -> VisionResult<(u32, bool)> {
+        Swapchain::acquire_next_image(self, semaphore, timeout)
+    }
+
+    fn present(&self, image_index: u32, wait_semaphores: &[vk::Semaphore]) -> VisionResult<bool> {
+        Swapchain::present(self, image_index, wait_semaphores)
+    }
+
+    fn images(&self) -> &[Image] {
+        Swapchain::images(self)
+    }
+
+    fn image_views(&self) -> &[vk::ImageView] {
+        Swapchain::image_views(self)
+    }
+
+    fn image_format(&self) -> vk::Format {
+        self.resources.format.format
+    }
+
+    fn extent(&self) -> &vk::Extent2D {
+        Swapchain::extent(self)
+    }
+
+    fn present_layout(&self) -> vk::ImageLayout {
+        vk::ImageLayout::PRESENT_SRC_KHR
+    }
+
+    fn output_parameters(&self) -> Option<OutputParameters> {
+        Some(Swapchain::output_parameters(self))
+    }
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        for (resources, fences) in self.retired.drain(..) {
+            unsafe {
+                let _ = self.context.device().vk_device().wait_for_fences(&fences, true, u64::MAX);
+            }
+            Self::destroy_fences(&self.context, &fences);
+            drop(resources);
+        }
+    }
+}
+
+impl SwapchainResources {
+    /// Returns `None` if the surface has a zero sized extent.
+    fn new(context: &Arc<Context>,
+           swapchain_loader: &VkSwapchain,
+           preferred_dimensions: [u32; 2],
+           config: &SwapchainConfig,
+           old_swapchain: vk::SwapchainKHR) -> VisionResult<Option<Self>> {
+        let surface = context.surface().ok_or(VisionError::MissingSurface)?;
+        let support_details = SwapchainSupportDetails::new(context.device().physical_device(), surface)?;
+        let format = support_details.surface_format(&config.surface_formats)?;
+        let present_mode = support_details.present_mode(config.present_mode);
         let extent = support_details.optimal_extent(preferred_dimensions);
-        let image_count = support_details.optimal_image_count();
+        let image_count = support_details.image_count(config.image_count);
+        let image_usage = config.image_usage & support_details.capabilities().supported_usage_flags;
+
+        if extent.width == 0 || extent.height == 0 || preferred_dimensions.contains(&0) {
+            return Ok(None);
+        }
 
         let mut create_info = vk::SwapchainCreateInfoKHR::builder()
-            .surface(*context.surface().vk_surface_khr())
+            .surface(*surface.vk_surface_khr())
             .min_image_count(image_count)
             .image_format(format.format)
             .image_color_space(format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+            .image_usage(image_usage);
 
         let queue_family_indices = context.device().physical_device().queue_family_indices();
-        let queue_families = [queue_family_indices.graphics_family, queue_family_indices.present_family];
+        let present_family = queue_family_indices.present_family.ok_or(VisionError::MissingSurface)?;
+        let queue_families = [queue_family_indices.graphics_family, present_family];
 
-        create_info = if queue_family_indices.graphics_family != queue_family_indices.present_family {
+        create_info = if queue_family_indices.graphics_family != present_family {
             create_info.image_sharing_mode(vk::SharingMode::CONCURRENT)
                 .queue_family_indices(&queue_families)
         } else {
@@ -53,100 +336,64 @@ impl Swapchain {
         create_info = create_info.pre_transform(support_details.capabilities().current_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
             .present_mode(present_mode)
-            .clipped(true);
+            .clipped(true)
+            .old_swapchain(old_swapchain);
 
-        let swapchain_loader = VkSwapchain::new(context.instance().vk_instance(), context.device().vk_device());
         let swapchain = unsafe {
             swapchain_loader.create_swapchain(&create_info, None)
-                .expect("Failed to create swapchain")
-        };
-
-        let images = unsafe {
-            swapchain_loader.get_swapchain_images(swapchain)
-                .expect("Failed to get swapchain images")
-                .iter()
-                .map(|image| {
-                    Image::create_swapchain_image(context.clone(), *image, format, extent)
-                }).collect::<Vec<_>>()
+                .map_vk_err("vkCreateSwapchainKHR")?
         };
 
-        let image_views: Vec<vk::ImageView> = images.iter()
-            .map(|image| {
-                let view_create_info = vk::ImageViewCreateInfo::builder()
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(format.format)
-                    .components(vk::ComponentMapping {
-                        r: vk::ComponentSwizzle::R,
-                        g: vk::ComponentSwizzle::G,
-                        b: vk::ComponentSwizzle::B,
-                        a: vk::ComponentSwizzle::A,
-                    })
-                    .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    })
-                    .image(image.vk_image());
-                unsafe { context.device().vk_device().create_image_view(&view_create_info, None) }
-                    .expect("Failed to create image view")
-            }).collect();
-
-        let framebuffers: Vec<vk::Framebuffer> = image_views
-            .iter()
-            .map(|image_view| match render_pass.color_attachment() {
-                Some(texture) => vec![texture.view(), render_pass.depth_attachment().view(), *image_view],
-                _ => vec![*image_view, render_pass.depth_attachment().view()],
-            })
-            .map(|attachments| {
-                let framebuffer_info = vk::FramebufferCreateInfo::builder()
-                    .render_pass(render_pass.vk_render_pass())
-                    .attachments(&attachments)
-                    .width(extent.width)
-                    .height(extent.height)
-                    .layers(1);
-                unsafe {
-                    context
-                        .device()
-                        .vk_device()
-                        .create_framebuffer(&framebuffer_info, None)
-                        .expect("Failed to create framebuffer")
-                }
-            }).collect();
-
-        Self {
-            context,
-            swapchain_loader,
+        let mut result = Self {
+            context: Arc::clone(context),
+            swapchain_loader: swapchain_loader.clone(),
             swapchain,
-            images,
-            image_views,
+            images: vec![],
+            image_views: vec![],
             format,
             present_mode,
             extent,
             image_count,
-            framebuffers
-        }
-    }
-
-    pub fn format(&self) -> &vk::SurfaceFormatKHR {
-        &self.format
-    }
+            image_usage,
+        };
 
-    pub fn present_mode(&self) -> &vk::PresentModeKHR {
-        &self.present_mode
-    }
+        result.images = unsafe {
+            swapchain_loader.get_swapchain_images(swapchain)
+                .map_vk_err("vkGetSwapchainImagesKHR")?
+                .iter()
+                .map(|image| {
+                    Image::create_swapchain_image(Arc::clone(context), *image, format, extent)
+                }).collect::<Vec<_>>()
+        };
 
-    pub fn extent(&self) -> &vk::Extent2D {
-        &self.extent
-    }
+        for image in result.images.iter() {
+            let view_create_info = vk::ImageViewCreateInfo::builder()
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format.format)
+                .components(vk::ComponentMapping {
+                    r: vk::ComponentSwizzle::R,
+                    g: vk::ComponentSwizzle::G,
+                    b: vk::ComponentSwizzle::B,
+                    a: vk::ComponentSwizzle::A,
+                })
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image(image.vk_image());
+            let image_view = unsafe { context.device().vk_device().create_image_view(&view_create_info, None) }
+                .map_vk_err("vkCreateImageView")?;
+            result.image_views.push(image_view);
+        }
 
-    pub fn image_count(&self) -> &u32 {
-        &self.image_count
+        Ok(Some(result))
     }
 }
 
-impl Drop for Swapchain {
+impl Drop for SwapchainResources {
     fn drop(&mut self) {
         unsafe {
             for image_view in self.image_views.iter() {
@@ -155,4 +402,4 @@ impl Drop for Swapchain {
             self.swapchain_loader.destroy_swapchain(self.swapchain, None);
         }
     }
-}
\ No newline at end of file
+}
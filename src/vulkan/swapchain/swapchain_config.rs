@@ -0,0 +1,134 @@
+use ash::vk;
+
+/// Requested presentation behaviour, resolved to the first supported mode of its fallback chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Waits for vertical blank, always supported.
+    Vsync,
+    /// Waits for vertical blank unless the frame is late, tears in that case.
+    VsyncRelaxed,
+    /// Replaces the queued image instead of blocking, without tearing.
+    Mailbox,
+    /// Presents immediately and may tear.
+    Immediate,
+}
+
+impl PresentMode {
+    /// Present modes in order of preference, ending with `FIFO` which every surface supports.
+    pub fn fallbacks(&self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            PresentMode::Vsync => &[vk::PresentModeKHR::FIFO],
+            PresentMode::VsyncRelaxed => &[vk::PresentModeKHR::FIFO_RELAXED, vk::PresentModeKHR::FIFO],
+            PresentMode::Mailbox => &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO],
+            PresentMode::Immediate => &[
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::FIFO,
+            ],
+        }
+    }
+}
+
+/// Preferences the swapchain is created with, changes take effect with the next `Swapchain::recreate`.
+#[derive(Clone, Debug)]
+pub struct SwapchainConfig {
+    pub present_mode: PresentMode,
+    /// Surface formats in order of preference, the first format the surface reports is used if none is supported.
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+    /// Number of images, clamped to the surface limits. `None` requests one more than the minimum.
+    pub image_count: Option<u32>,
+    /// Usage of the images, e.g. `STORAGE` for compute shaders writing to them. Usages the surface does not support
+    /// are dropped.
+    pub image_usage: vk::ImageUsageFlags,
+    /// Passed to the display for HDR color spaces if `VK_EXT_hdr_metadata` is enabled.
+    pub hdr_metadata: Option<HdrMetadata>,
+    /// Luminance SDR white is mapped to on HDR color spaces.
+    pub paper_white_nits: f32,
+}
+
+impl SwapchainConfig {
+    /// Prefers HDR10 over extended sRGB linear and falls back to SDR on surfaces without HDR color spaces. The HDR
+    /// color spaces require `VK_EXT_swapchain_colorspace`, which the instance enables when available.
+    pub fn hdr() -> Self {
+        let mut surface_formats = vec![
+            vk::SurfaceFormatKHR {
+                format: vk::Format::A2B10G10R10_UNORM_PACK32,
+                color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            },
+            vk::SurfaceFormatKHR {
+                format: vk::Format::R16G16B16A16_SFLOAT,
+                color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+            },
+        ];
+        let sdr = Self::default();
+        surface_formats.extend(sdr.surface_formats);
+
+        Self {
+            surface_formats,
+            hdr_metadata: Some(HdrMetadata::default()),
+            ..sdr
+        }
+    }
+}
+
+impl Default for SwapchainConfig {
+    fn default() -> Self {
+        Self {
+            present_mode: PresentMode::Mailbox,
+            surface_formats: vec![vk::SurfaceFormatKHR {
+                format: vk::Format::B8G8R8A8_SRGB,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            }],
+            image_count: None,
+            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            hdr_metadata: None,
+            // Reference white of ITU-R BT.2408.
+            paper_white_nits: 203.0,
+        }
+    }
+}
+
+/// Mastering display and content light levels, chromaticities are CIE 1931 xy coordinates and luminances in nits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HdrMetadata {
+    pub display_primary_red: [f32; 2],
+    pub display_primary_green: [f32; 2],
+    pub display_primary_blue: [f32; 2],
+    pub white_point: [f32; 2],
+    pub max_luminance: f32,
+    pub min_luminance: f32,
+    pub max_content_light_level: f32,
+    pub max_frame_average_light_level: f32,
+}
+
+impl HdrMetadata {
+    pub(crate) fn vk_hdr_metadata(&self) -> vk::HdrMetadataEXT {
+        let xy = |[x, y]: [f32; 2]| vk::XYColorEXT { x, y };
+        vk::HdrMetadataEXT::builder()
+            .display_primary_red(xy(self.display_primary_red))
+            .display_primary_green(xy(self.display_primary_green))
+            .display_primary_blue(xy(self.display_primary_blue))
+            .white_point(xy(self.white_point))
+            .max_luminance(self.max_luminance)
+            .min_luminance(self.min_luminance)
+            .max_content_light_level(self.max_content_light_level)
+            .max_frame_average_light_level(self.max_frame_average_light_level)
+            .build()
+    }
+}
+
+impl Default for HdrMetadata {
+    /// BT.2020 primaries with a D65 white point mastered at 1000 nits.
+    fn default() -> Self {
+        Self {
+            display_primary_red: [0.708, 0.292],
+            display_primary_green: [0.170, 0.797],
+            display_primary_blue: [0.131, 0.046],
+            white_point: [0.3127, 0.3290],
+            max_luminance: 1000.0,
+            min_luminance: 0.001,
+            max_content_light_level: 1000.0,
+            max_frame_average_light_level: 400.0,
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use ash::vk;
 
-use crate::vulkan::{PhysicalDevice, Surface};
+use crate::vulkan::{PhysicalDevice, Surface, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::swapchain::{PresentMode, SwapchainConfig};
 
 pub struct SwapchainSupportDetails {
     capabilities: vk::SurfaceCapabilitiesKHR,
@@ -9,51 +11,56 @@ pub struct SwapchainSupportDetails {
 }
 
 impl SwapchainSupportDetails {
-    pub fn new(physical_device: &PhysicalDevice, surface: &Surface) -> Self {
+    pub fn new(physical_device: &PhysicalDevice, surface: &Surface) -> VisionResult<Self> {
         let capabilities = unsafe {
             surface.vk_surface()
                 .get_physical_device_surface_capabilities(physical_device.vk_physical_device(), *surface.vk_surface_khr())
-                .expect("Failed to get physical device surface capabilities")
+                .map_vk_err("vkGetPhysicalDeviceSurfaceCapabilitiesKHR")?
         };
         let formats = unsafe {
             surface.vk_surface()
                 .get_physical_device_surface_formats(physical_device.vk_physical_device(), *surface.vk_surface_khr())
-                .expect("Failed to get physical device surface capabilities")
+                .map_vk_err("vkGetPhysicalDeviceSurfaceFormatsKHR")?
         };
         let present_modes = unsafe {
             surface.vk_surface()
                 .get_physical_device_surface_present_modes(physical_device.vk_physical_device(), *surface.vk_surface_khr())
-                .expect("Failed to get physical device surface capabilities")
+                .map_vk_err("vkGetPhysicalDeviceSurfacePresentModesKHR")?
         };
 
-        Self {
+        Ok(Self {
             capabilities,
             formats,
             present_modes,
-        }
+        })
     }
 
-    pub fn optimal_surface_format(&self) -> vk::SurfaceFormatKHR {
-        for &format in self.formats.iter() {
-            if format.format == vk::Format::B8G8R8A8_SRGB && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
-                return format;
-            }
-        }
-        self.formats.first().expect("Failed to get optimal surface format").clone()
+    pub fn optimal_surface_format(&self) -> VisionResult<vk::SurfaceFormatKHR> {
+        self.surface_format(&SwapchainConfig::default().surface_formats)
+    }
+
+    /// The first of `preferred` the surface supports, otherwise the first format the surface reports.
+    pub fn surface_format(&self, preferred: &[vk::SurfaceFormatKHR]) -> VisionResult<vk::SurfaceFormatKHR> {
+        preferred.iter()
+            .find(|format| self.formats.contains(format))
+            .or_else(|| self.formats.first())
+            .copied()
+            .ok_or(VisionError::NoSurfaceFormat)
     }
 
     pub fn optimal_present_mode(&self) -> vk::PresentModeKHR {
-        if self.present_modes.contains(&vk::PresentModeKHR::MAILBOX) {
-            vk::PresentModeKHR::MAILBOX
-        } else if self.present_modes.contains(&vk::PresentModeKHR::FIFO) {
-            vk::PresentModeKHR::FIFO
-        } else {
-            vk::PresentModeKHR::IMMEDIATE
-        }
+        self.present_mode(SwapchainConfig::default().present_mode)
+    }
+
+    pub fn present_mode(&self, preferred: PresentMode) -> vk::PresentModeKHR {
+        preferred.fallbacks().iter()
+            .copied()
+            .find(|present_mode| self.present_modes.contains(present_mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO)
     }
 
     pub fn optimal_extent(&self, preferred_dimensions: [u32; 2]) -> vk::Extent2D {
-        if self.capabilities.current_extent.width != std::u32::MAX {
+        if self.capabilities.current_extent.width != u32::MAX {
             return self.capabilities.current_extent;
         }
         let min = self.capabilities.min_image_extent;
@@ -64,12 +71,18 @@ impl SwapchainSupportDetails {
     }
 
     pub fn optimal_image_count(&self) -> u32 {
+        self.image_count(None)
+    }
+
+    /// `preferred` clamped to the surface limits, `None` requests one more than the minimum.
+    pub fn image_count(&self, preferred: Option<u32>) -> u32 {
+        let min = self.capabilities.min_image_count;
         let max = self.capabilities.max_image_count;
-        let mut preferred = self.capabilities.min_image_count + 1;
-        if max > 0 && preferred > max {
-            preferred = max;
+        let mut image_count = preferred.unwrap_or(min + 1).max(min);
+        if max > 0 && image_count > max {
+            image_count = max;
         }
-        preferred
+        image_count
     }
 
     pub fn capabilities(&self) -> &vk::SurfaceCapabilitiesKHR {
@@ -0,0 +1,242 @@
+use std::cell::Cell;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::{Buffer, Context, Image, ImageParameters, VisionError, VisionResult};
+use crate::vulkan::error::VkResultExt;
+use crate::vulkan::image::{image_byte_size, texel_size};
+use crate::vulkan::swapchain::PresentTarget;
+
+/// Offscreen stand-in for `Swapchain` rendering into a ring of device local images, e.g. for headless golden image
+/// tests. Presenting copies the image into host memory where it can be read with `read_frame`. Images are expected
+/// in the layout passed to `new` when presented.
+pub struct VirtualSwapchain {
+    context: Arc<Context>,
+    images: Vec<Image>,
+    image_views: Vec<vk::ImageView>,
+    frames: Vec<VirtualFrame>,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    present_layout: vk::ImageLayout,
+    next_image: Cell<u32>,
+    last_presented: Cell<Option<u32>>,
+    presented_count: Cell<u64>,
+}
+
+/// Host copy of an image, `readback_finished` is signaled once the copy of the last present completed.
+struct VirtualFrame {
+    command_buffer: vk::CommandBuffer,
+    readback_finished: vk::Fence,
+    pixels: Buffer,
+}
+
+impl VirtualSwapchain {
+    /// `present_layout` is the layout rendering leaves the images in, e.g. `COLOR_ATTACHMENT_OPTIMAL` or
+    /// `TRANSFER_SRC_OPTIMAL`. `PRESENT_SRC_KHR` is only valid if the context enables `VK_KHR_swapchain`, which
+    /// headless contexts do not.
+    pub fn new(context: Arc<Context>,
+               extent: vk::Extent2D,
+               format: vk::Format,
+               image_count: u32,
+               present_layout: vk::ImageLayout) -> VisionResult<Self> {
+        if extent.width == 0 || extent.height == 0 {
+            return Err(VisionError::ZeroSizedSurface);
+        }
+        let texel_size = texel_size(format).ok_or(VisionError::UnsupportedFormat {
+            format,
+            features: vk::FormatFeatureFlags::TRANSFER_SRC,
+        })?;
+        let frame_size = image_byte_size(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+                                         texel_size)?;
+
+        let mut swapchain = Self {
+            context: Arc::clone(&context),
+            images: vec![],
+            image_views: vec![],
+            frames: vec![],
+            format,
+            extent,
+            present_layout,
+            next_image: Cell::new(0),
+            last_presented: Cell::new(None),
+            presented_count: Cell::new(0),
+        };
+
+        let device = context.device().vk_device();
+        for _ in 0..image_count.max(1) {
+            let image = Image::create(Arc::clone(&context), ImageParameters {
+                memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                extent,
+                format,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                ..Default::default()
+            })?;
+            let image_view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR)?;
+            swapchain.images.push(image);
+            swapchain.image_views.push(image_view);
+
+            let pixels = Buffer::create(Arc::clone(&context),
+                                        frame_size,
+                                        vk::BufferUsageFlags::TRANSFER_DST,
+                                        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT)?;
+            let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+            let readback_finished = unsafe { device.create_fence(&fence_info, None) }.map_vk_err("vkCreateFence")?;
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_pool(context.general_command_pool().vk_command_pool())
+                .command_buffer_count(1);
+            let command_buffer = match unsafe { device.allocate_command_buffers(&alloc_info) } {
+                Ok(command_buffers) => command_buffers[0],
+                Err(error) => {
+                    unsafe { device.destroy_fence(readback_finished, None) };
+                    return Err(error).map_vk_err("vkAllocateCommandBuffers");
+                }
+            };
+            swapchain.frames.push(VirtualFrame {
+                command_buffer,
+                readback_finished,
+                pixels,
+            });
+        }
+
+        Ok(swapchain)
+    }
+
+    /// Waits for the copy of `image_index` to complete and returns its tightly packed texels.
+    pub fn read_frame(&self, image_index: u32) -> VisionResult<Vec<u8>> {
+        let frame = self.frame(image_index)?;
+        unsafe {
+            self.context.device().vk_device()
+                .wait_for_fences(&[frame.readback_finished], true, u64::MAX)
+                .map_vk_err("vkWaitForFences")?;
+        }
+        frame.pixels.read(0, frame.pixels.len())
+    }
+
+    /// Texels of the most recently presented image, `None` before the first present.
+    pub fn read_last_frame(&self) -> VisionResult<Option<Vec<u8>>> {
+        self.last_presented.get().map(|image_index| self.read_frame(image_index)).transpose()
+    }
+
+    pub fn last_presented(&self) -> Option<u32> {
+        self.last_presented.get()
+    }
+
+    /// Number of frames presented so far.
+    pub fn presented_count(&self) -> u64 {
+        self.presented_count.get()
+    }
+
+    pub fn image_count(&self) -> u32 {
+        self.images.len() as u32
+    }
+
+    fn frame(&self, image_index: u32) -> VisionResult<&VirtualFrame> {
+        self.frames.get(image_index as usize)
+            .ok_or(VisionError::ImageIndexOutOfRange { index: image_index, count: self.image_count() })
+    }
+}
+
+impl PresentTarget for VirtualSwapchain {
+    /// Hands out the images in order, waiting until the previous copy of the image completed.
+    fn acquire_next_image(&self, semaphore: vk::Semaphore, timeout: u64) -> VisionResult<(u32, bool)> {
+        let image_index = self.next_image.get();
+        let frame = self.frame(image_index)?;
+        let device = self.context.device().vk_device();
+
+        unsafe {
+            device.wait_for_fences(&[frame.readback_finished], true, timeout).map_vk_err("vkWaitForFences")?;
+        }
+
+        // An empty submission signals the semaphore right away, as an acquired swapchain image would be.
+        if semaphore != vk::Semaphore::null() {
+            let signal_semaphores = [semaphore];
+            let submit_info = vk::SubmitInfo::builder().signal_semaphores(&signal_semaphores).build();
+            unsafe {
+                device.queue_submit(self.context.device().graphics_queue(), &[submit_info], vk::Fence::null())
+                    .map_vk_err("vkQueueSubmit")?;
+            }
+        }
+
+        self.next_image.set((image_index + 1) % self.image_count());
+        Ok((image_index, false))
+    }
+
+    /// Copies the image into host memory once `wait_semaphores` are signaled.
+    fn present(&self, image_index: u32, wait_semaphores: &[vk::Semaphore]) -> VisionResult<bool> {
+        let frame = self.frame(image_index)?;
+        let image = &self.images[image_index as usize];
+        let device = self.context.device().vk_device();
+
+        unsafe {
+            device.reset_command_buffer(frame.command_buffer, vk::CommandBufferResetFlags::empty())
+                .map_vk_err("vkResetCommandBuffer")?;
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device.begin_command_buffer(frame.command_buffer, &begin_info).map_vk_err("vkBeginCommandBuffer")?;
+        }
+        image.cmd_copy_to_buffer(frame.command_buffer, &frame.pixels, self.present_layout, 0, 0);
+        self.context.cmd_memory_barrier(frame.command_buffer,
+                                        vk::PipelineStageFlags::TRANSFER,
+                                        vk::AccessFlags::TRANSFER_WRITE,
+                                        vk::PipelineStageFlags::HOST,
+                                        vk::AccessFlags::HOST_READ);
+        unsafe {
+            device.end_command_buffer(frame.command_buffer).map_vk_err("vkEndCommandBuffer")?;
+        }
+
+        let wait_stages = vec![vk::PipelineStageFlags::TRANSFER; wait_semaphores.len()];
+        let command_buffers = [frame.command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .build();
+        unsafe {
+            device.reset_fences(&[frame.readback_finished]).map_vk_err("vkResetFences")?;
+            device.queue_submit(self.context.device().graphics_queue(), &[submit_info], frame.readback_finished)
+                .map_vk_err("vkQueueSubmit")?;
+        }
+
+        self.last_presented.set(Some(image_index));
+        self.presented_count.set(self.presented_count.get() + 1);
+        Ok(false)
+    }
+
+    fn images(&self) -> &[Image] {
+        &self.images
+    }
+
+    fn image_views(&self) -> &[vk::ImageView] {
+        &self.image_views
+    }
+
+    fn image_format(&self) -> vk::Format {
+        self.format
+    }
+
+    fn extent(&self) -> &vk::Extent2D {
+        &self.extent
+    }
+
+    fn present_layout(&self) -> vk::ImageLayout {
+        self.present_layout
+    }
+}
+
+impl Drop for VirtualSwapchain {
+    fn drop(&mut self) {
+        let device = self.context.device().vk_device();
+        for frame in self.frames.drain(..) {
+            unsafe {
+                let _ = device.wait_for_fences(&[frame.readback_finished], true, u64::MAX);
+                device.destroy_fence(frame.readback_finished, None);
+                device.free_command_buffers(self.context.general_command_pool().vk_command_pool(), &[frame.command_buffer]);
+            }
+        }
+        for image_view in self.image_views.drain(..) {
+            unsafe { device.destroy_image_view(image_view, None) };
+        }
+    }
+}
@@ -1,13 +1,9 @@
-use std::mem::size_of;
 use std::sync::Arc;
 
-use ash::version::DeviceV1_0;
 use ash::vk;
 
-use crate::vulkan::{Context, Image};
-use crate::vulkan::buffer::Buffer;
-use crate::vulkan::image::ImageParameters;
-use crate::vulkan::util::mem_copy;
+use crate::vulkan::{Buffer, Context, Image, ImageParameters, VisionResult};
+use crate::vulkan::error::VkResultExt;
 
 pub struct Texture {
     context: Arc<Context>,
@@ -31,35 +27,24 @@ impl Texture {
         }
     }
 
-    pub fn from_rgba(context: &Arc<Context>, width: u32, height: u32, data: &[u8]) -> Self {
+    pub fn from_rgba(context: &Arc<Context>, width: u32, height: u32, data: &[u8]) -> VisionResult<Self> {
         let (texture, _) = context.execute_transient(|command_buffer| {
             Self::cmd_from_rgba(context, command_buffer, width, height, data)
-        });
-        texture
+        })??;
+        Ok(texture)
     }
 
     pub fn cmd_from_rgba(context: &Arc<Context>,
                          command_buffer: vk::CommandBuffer,
                          width: u32,
                          height: u32,
-                         data: &[u8]) -> (Self, Buffer) {
+                         data: &[u8]) -> VisionResult<(Self, Buffer)> {
 
         let max_mip_levels = ((width.min(height) as f32).log2().floor() + 1.0) as u32;
         let extent = vk::Extent2D { width, height };
-        let image_size = (data.len() * size_of::<u8>()) as vk::DeviceSize;
         let device = context.device().vk_device();
 
-        let mut buffer = Buffer::create(
-            Arc::clone(context),
-            image_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
-
-        unsafe {
-            let ptr = buffer.map_memory();
-            mem_copy(ptr, &data);
-        }
+        let buffer = Buffer::staging_from_slice(Arc::clone(context), data)?;
 
         let image = Image::create(
             Arc::clone(context),
@@ -73,7 +58,7 @@ impl Texture {
                     | vk::ImageUsageFlags::SAMPLED,
                 ..Default::default()
             },
-        );
+        )?;
 
         // Transition the image layout and copy the buffer into the image
         // and transition the layout again to be readable from fragment shader.
@@ -86,10 +71,10 @@ impl Texture {
 
             image.cmd_copy_buffer(command_buffer, &buffer, extent);
 
-            image.cmd_generate_mipmaps(command_buffer, extent);
+            image.cmd_generate_mipmaps(command_buffer, extent)?;
         }
 
-        let image_view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
+        let image_view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR)?;
 
         let sampler = {
             let sampler_info = vk::SamplerCreateInfo::builder()
@@ -111,32 +96,21 @@ impl Texture {
 
             unsafe {
                 device.create_sampler(&sampler_info, None)
-                    .expect("Failed to create sampler")
+                    .map_vk_err("vkCreateSampler")?
             }
         };
 
         let texture = Texture::new(Arc::clone(context), image, image_view, Some(sampler));
 
-        (texture, buffer)
+        Ok((texture, buffer))
     }
 
-    pub fn from_rgba_32(context: &Arc<Context>, width: u32, height: u32, data: &[f32]) -> Self {
+    pub fn from_rgba_32(context: &Arc<Context>, width: u32, height: u32, data: &[f32]) -> VisionResult<Self> {
         let max_mip_levels = ((width.min(height) as f32).log2().floor() + 1.0) as u32;
         let extent = vk::Extent2D { width, height };
-        let image_size = (data.len() * size_of::<f32>()) as vk::DeviceSize;
         let device = context.device();
 
-        let mut buffer = Buffer::create(
-            Arc::clone(context),
-            image_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
-
-        unsafe {
-            let ptr = buffer.map_memory();
-            mem_copy(ptr, &data);
-        }
+        let buffer = Buffer::staging_from_slice(Arc::clone(context), data)?;
 
         let image = Image::create(
             Arc::clone(context),
@@ -150,7 +124,7 @@ impl Texture {
                     | vk::ImageUsageFlags::SAMPLED,
                 ..Default::default()
             },
-        );
+        )?;
 
         // Transition the image layout and copy the buffer into the image
         // and transition the layout again to be readable from fragment shader.
@@ -158,14 +132,14 @@ impl Texture {
             image.transition_image_layout(
                 vk::ImageLayout::UNDEFINED,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            );
+            )?;
 
-            image.copy_buffer(&buffer, extent);
+            image.copy_buffer(&buffer, extent)?;
 
-            image.generate_mipmaps(extent);
+            image.generate_mipmaps(extent)?;
         }
 
-        let image_view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
+        let image_view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR)?;
 
         let sampler = {
             let sampler_info = vk::SamplerCreateInfo::builder()
@@ -189,34 +163,23 @@ impl Texture {
                 device
                     .vk_device()
                     .create_sampler(&sampler_info, None)
-                    .expect("Failed to create sampler")
+                    .map_vk_err("vkCreateSampler")?
             }
         };
 
-        Texture::new(Arc::clone(context), image, image_view, Some(sampler))
+        Ok(Texture::new(Arc::clone(context), image, image_view, Some(sampler)))
     }
 
-    pub fn create_cubemap_from_data(context: &Arc<Context>, size: u32, data: &[f32]) -> Self {
+    pub fn create_cubemap_from_data(context: &Arc<Context>, size: u32, data: &[f32]) -> VisionResult<Self> {
         let max_mip_levels = (size as f32).log2().floor() as u32 + 1;
         let extent = vk::Extent2D {
             width: size,
             height: size,
         };
 
-        let image_size = (data.len() * size_of::<f32>()) as vk::DeviceSize;
         let device = context.device();
 
-        let mut buffer = Buffer::create(
-            Arc::clone(context),
-            image_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
-
-        unsafe {
-            let ptr = buffer.map_memory();
-            mem_copy(ptr, &data);
-        }
+        let buffer = Buffer::staging_from_slice(Arc::clone(context), data)?;
 
         let image = Image::create(
             Arc::clone(context),
@@ -232,7 +195,7 @@ impl Texture {
                 create_flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
                 ..Default::default()
             },
-        );
+        )?;
 
         // Transition the image layout and copy the buffer into the image
         // and transition the layout again to be readable from fragment shader.
@@ -240,14 +203,14 @@ impl Texture {
             image.transition_image_layout(
                 vk::ImageLayout::UNDEFINED,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            );
+            )?;
 
-            image.copy_buffer(&buffer, extent);
+            image.copy_buffer(&buffer, extent)?;
 
-            image.generate_mipmaps(extent);
+            image.generate_mipmaps(extent)?;
         }
 
-        let image_view = image.create_view(vk::ImageViewType::CUBE, vk::ImageAspectFlags::COLOR);
+        let image_view = image.create_view(vk::ImageViewType::CUBE, vk::ImageAspectFlags::COLOR)?;
 
         let sampler = {
             let sampler_info = vk::SamplerCreateInfo::builder()
@@ -271,14 +234,14 @@ impl Texture {
                 device
                     .vk_device()
                     .create_sampler(&sampler_info, None)
-                    .expect("Failed to create sampler")
+                    .map_vk_err("vkCreateSampler")?
             }
         };
 
-        Texture::new(Arc::clone(context), image, image_view, Some(sampler))
+        Ok(Texture::new(Arc::clone(context), image, image_view, Some(sampler)))
     }
 
-    pub fn create_renderable_cubemap(context: &Arc<Context>, size: u32, mip_levels: u32) -> Self {
+    pub fn create_renderable_cubemap(context: &Arc<Context>, size: u32, mip_levels: u32) -> VisionResult<Self> {
         let extent = vk::Extent2D {
             width: size,
             height: size,
@@ -301,14 +264,14 @@ impl Texture {
                 create_flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
                 ..Default::default()
             },
-        );
+        )?;
 
         image.transition_image_layout(
             vk::ImageLayout::UNDEFINED,
             vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        );
+        )?;
 
-        let image_view = image.create_view(vk::ImageViewType::CUBE, vk::ImageAspectFlags::COLOR);
+        let image_view = image.create_view(vk::ImageViewType::CUBE, vk::ImageAspectFlags::COLOR)?;
 
         let sampler = {
             let sampler_info = vk::SamplerCreateInfo::builder()
@@ -331,11 +294,11 @@ impl Texture {
             unsafe {
                 device.vk_device()
                     .create_sampler(&sampler_info, None)
-                    .expect("Failed to create sampler")
+                    .map_vk_err("vkCreateSampler")?
             }
         };
 
-        Texture::new(Arc::clone(context), image, image_view, Some(sampler))
+        Ok(Texture::new(Arc::clone(context), image, image_view, Some(sampler)))
     }
 
     pub fn create_renderable_texture(
@@ -343,7 +306,7 @@ impl Texture {
         width: u32,
         height: u32,
         format: vk::Format,
-    ) -> Self {
+    ) -> VisionResult<Self> {
         let extent = vk::Extent2D { width, height };
 
         let device = context.device();
@@ -357,14 +320,14 @@ impl Texture {
                 usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT,
                 ..Default::default()
             },
-        );
+        )?;
 
         image.transition_image_layout(
             vk::ImageLayout::UNDEFINED,
             vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        );
+        )?;
 
-        let image_view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
+        let image_view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR)?;
 
         let sampler = {
             let sampler_info = vk::SamplerCreateInfo::builder()
@@ -387,11 +350,15 @@ impl Texture {
             unsafe {
                 device.vk_device()
                     .create_sampler(&sampler_info, None)
-                    .expect("Failed to create sampler")
+                    .map_vk_err("vkCreateSampler")?
             }
         };
 
-        Texture::new(Arc::clone(context), image, image_view, Some(sampler))
+        Ok(Texture::new(Arc::clone(context), image, image_view, Some(sampler)))
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
     }
 
     pub fn view(&self) -> vk::ImageView {
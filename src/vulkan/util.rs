@@ -3,6 +3,9 @@ use std::{ffi::c_void, mem::size_of};
 use ash::{util::Align, vk::DeviceSize};
 
 /// Utility function that copy the content of a slice at the position of a given pointer.
+///
+/// # Safety
+/// `ptr` must point to mapped memory large enough to hold `data`.
 pub unsafe fn mem_copy<T: Copy>(ptr: *mut c_void, data: &[T]) {
     let elem_size = size_of::<T>() as DeviceSize;
     let size = data.len() as DeviceSize * elem_size;
@@ -11,8 +14,16 @@ pub unsafe fn mem_copy<T: Copy>(ptr: *mut c_void, data: &[T]) {
 }
 
 /// Utility function that copy the content of a slice at the position of a given pointer and pad elements to respect the requested alignment.
+///
+/// # Safety
+/// `ptr` must point to mapped memory large enough to hold `data.len()` elements of `alignment` bytes.
 pub unsafe fn mem_copy_aligned<T: Copy>(ptr: *mut c_void, alignment: DeviceSize, data: &[T]) {
     let size = data.len() as DeviceSize * alignment;
     let mut align = Align::new(ptr, alignment, size);
     align.copy_from_slice(data);
-}
\ No newline at end of file
+}
+
+/// Rounds `value` up to the next multiple of `alignment`.
+pub fn align_up(value: DeviceSize, alignment: DeviceSize) -> DeviceSize {
+    value.div_ceil(alignment) * alignment
+}